@@ -21,6 +21,63 @@ pub struct TestEntry {
     /// TestStatus::to_str() form ("PASSED", "FAILED", ...)
     pub status: String,
     pub duration: u64,
+    /// For a failure: the commit that introduced it, once bisection has
+    /// pinned it down (crate::first_bad_commit).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_bad: Option<String>,
+    /// Set when the test was re-run at this commit (rerun_failed).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reruns: Option<Reruns>,
+    /// KTAP (KUnit) cases that failed, when the subtest ran a suite
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_cases: Vec<String>,
+    /// For a failure: the normalized line that explains it, if the log
+    /// had one (crate::signature)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// For a failure: the known issue it matches (crate::known_issues)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_issue: Option<crate::known_issues::KnownIssue>,
+    /// Set if the run was flagged slow: the typical duration it was
+    /// judged against (crate::flag_slow)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_mean: Option<u64>,
+}
+
+/// How a re-run test fared across all its runs at one commit.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Reruns {
+    pub runs: u64,
+    pub failed: u64,
+    /// crate::TestResult::flaky(), as judged when this was built
+    #[serde(default)]
+    pub flaky: bool,
+}
+
+impl Reruns {
+    pub fn from_result(r: &crate::TestResult) -> Option<Reruns> {
+        (r.runs() > 1).then(|| Reruns {
+            runs: r.completed_runs(),
+            failed: r.failures(),
+            flaky: r.flaky().is_some(),
+        })
+    }
+
+    /// Failed some runs but not all.
+    pub fn is_flaky(&self) -> bool {
+        self.flaky
+    }
+
+    /// "flaky 2/5" or "failed 5/5"; empty if no run failed.
+    pub fn describe(&self) -> String {
+        if self.is_flaky() {
+            format!("flaky {}/{}", self.failed, self.runs)
+        } else if self.failed != 0 {
+            format!("failed {}/{}", self.failed, self.runs)
+        } else {
+            String::new()
+        }
+    }
 }
 
 /// Per-test detail for one commit.
@@ -31,6 +88,245 @@ pub struct CommitTests {
     pub tests: Vec<TestEntry>,
 }
 
+/// Tests failing at the branch tip that passed at an older commit
+/// (`?user=X&branch=Y&view=regressions&format=json`); see
+/// crate::branch_regressions().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Regressions {
+    /// The tip: newest commit with results. Empty if there are none.
+    pub commit: String,
+    pub regressions: Vec<Regression>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Regression {
+    pub name: String,
+    /// Newest commit where the test passed
+    pub last_passed: String,
+    pub last_passed_subject: String,
+    /// How many commits back from the tip last_passed is
+    pub age: usize,
+    /// Set once bisection has pinned down the breaking commit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_bad: Option<String>,
+}
+
+/// One test's results down a branch
+/// (`?user=X&branch=Y&view=history&test=T&format=json`); see
+/// crate::test_history().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestHistory {
+    pub test: String,
+    /// Every commit the branch walk visits, newest first
+    pub commits: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub commit: String,
+    pub subject: String,
+    /// None if the test has no result at this commit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<HistoryResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryResult {
+    /// TestStatus::to_str() form
+    pub status: String,
+    pub duration: u64,
+    /// Unix seconds
+    pub starttime: i64,
+}
+
+/// Pass/fail totals over the commits where a test got a verdict.
+#[derive(Debug, Default, PartialEq)]
+pub struct HistoryStats {
+    pub completed: u64,
+    pub passed: u64,
+    pub failed: u64,
+    /// Mean duration of those runs, seconds
+    pub mean_duration: u64,
+}
+
+impl TestHistory {
+    pub fn stats(&self) -> HistoryStats {
+        let mut stats = HistoryStats::default();
+        let mut duration = 0;
+        for r in self.commits.iter().filter_map(|c| c.result.as_ref()) {
+            match crate::TestStatus::from_str(&r.status) {
+                crate::TestStatus::Passed => stats.passed += 1,
+                crate::TestStatus::Failed => stats.failed += 1,
+                _ => continue,
+            }
+            stats.completed += 1;
+            duration += r.duration;
+        }
+        stats.mean_duration = duration.checked_div(stats.completed).unwrap_or(0);
+        stats
+    }
+}
+
+/// Failures down a branch grouped by signature
+/// (`?user=X&branch=Y&view=failures&format=json`); see
+/// crate::failure_clusters().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailureClusters {
+    /// Commits looked at, from the branch tip back
+    pub commits: usize,
+    /// Most occurrences first
+    pub clusters: Vec<FailureCluster>,
+    /// Failures with no signature, which can't be grouped
+    pub unclassified: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailureCluster {
+    pub signature: String,
+    /// Failed results carrying it, over every commit
+    pub count: usize,
+    /// `<test>.<subtest>`, kernel and env stripped; sorted
+    pub tests: Vec<String>,
+    /// Sanitized kernel ids; empty for unqualified results. Sorted
+    pub kernels: Vec<String>,
+    /// Oldest commit in the window with this signature
+    pub first_seen: String,
+    pub first_seen_subject: String,
+    /// Newest commit with it, and one of its result keys there — for a
+    /// log link
+    pub last_seen: String,
+    pub last_seen_key: String,
+}
+
+/// What changed between two commits' results (`?compare=A..B&format=json`);
+/// see crate::compare_results().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Comparison {
+    pub a: String,
+    pub b: String,
+    /// Only groups with changes, sorted by test, kernel, env
+    pub groups: Vec<CompareGroup>,
+}
+
+/// Changed subtests of one test file under one kernel/env qualifier.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompareGroup {
+    pub test: String,
+    /// Sanitized kernel and encoded env, as in the result key; empty if
+    /// unqualified
+    pub kernel: String,
+    pub env: String,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Change {
+    pub subtest: String,
+    /// The full result key
+    pub key: String,
+    pub kind: ChangeKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub a: Option<CompareSide>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b: Option<CompareSide>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// Failed at B, not at A
+    NewlyFailing,
+    /// Failed at A, passed at B
+    NewlyPassing,
+    /// Any other status change
+    Changed,
+    /// A result at A, none at B
+    Missing,
+    /// A result at B, none at A
+    Added,
+    /// Same verdict, duration past the threshold
+    Slower,
+    Faster,
+}
+
+impl ChangeKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ChangeKind::NewlyFailing => "newly failing",
+            ChangeKind::NewlyPassing => "newly passing",
+            ChangeKind::Changed => "changed",
+            ChangeKind::Missing => "missing",
+            ChangeKind::Added => "added",
+            ChangeKind::Slower => "slower",
+            ChangeKind::Faster => "faster",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompareSide {
+    /// TestStatus::to_str() form
+    pub status: String,
+    pub duration: u64,
+}
+
+impl Comparison {
+    /// How many changes of `kind`, across all groups.
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.groups
+            .iter()
+            .flat_map(|g| &g.changes)
+            .filter(|c| c.kind == kind)
+            .count()
+    }
+}
+
+/// A commit's results pivoted
+/// (`?user=X&branch=Y&commit=Z&view=matrix&format=json`): a row per
+/// (test, subtest), a column per kernel/env qualifier; see
+/// crate::results_matrix().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Matrix {
+    pub commit: String,
+    pub columns: Vec<MatrixColumn>,
+    pub rows: Vec<MatrixRow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatrixColumn {
+    /// Sanitized kernel and encoded env; both empty for unqualified
+    /// results
+    pub kernel: String,
+    pub env: String,
+}
+
+impl MatrixColumn {
+    pub fn label(&self) -> String {
+        match (self.kernel.is_empty(), self.env.is_empty()) {
+            (true, true) => "default".to_string(),
+            (false, true) => self.kernel.clone(),
+            (true, false) => self.env.clone(),
+            (false, false) => format!("{} {}", self.kernel, self.env),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatrixRow {
+    pub test: String,
+    pub subtest: String,
+    /// One per column; None where the subtest has no result there
+    pub cells: Vec<Option<MatrixCell>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatrixCell {
+    /// The result key, for the log link
+    pub key: String,
+    /// TestStatus::to_str() form
+    pub status: String,
+}
+
 /// A user's configured branches (`?user=X&format=json`, and one element
 /// of the `?format=json` index).
 #[derive(Debug, Serialize, Deserialize)]
@@ -3,9 +3,14 @@ use std::fmt::Write;
 extern crate cgi;
 extern crate querystring;
 
+use ci_cgi::junit::commit_junit;
+use ci_cgi::known_issues::KnownIssues;
 use ci_cgi::{
-    api, branch_get_results, ciconfig_read, format_duration, last_good_line, update_lcov,
-    CiConfig, CommitResults, TestResultsMap, TestStatus, Userrc,
+    api, branch_get_results, branch_regressions, branch_test_history, ciconfig_read, compare_commits,
+    count_quarantined, decompress_brotli, failure_clusters, first_bad_commit, format_duration,
+    is_quarantined, last_good_line, results_matrix, short_commit, update_lcov, CiConfig,
+    CommitResults, Quarantine, TestResultsMap, TestStatus, Userrc, COMPARE_DURATION_THRESHOLD,
+    FAILURE_CLUSTER_COMMITS,
 };
 
 const STYLESHEET: &str = "bootstrap.min.css";
@@ -102,10 +107,21 @@ struct Ci {
     user: Option<String>,
     branch: Option<String>,
     commit: Option<String>,
+    /// Alternate view of a branch: `regressions`, `history`,
+    /// `failures`; of a commit: `matrix`
+    view: Option<String>,
+    /// `commits=`: how far back from the tip the failures view looks
+    commits: usize,
     tests_matching: Regex,
+    /// `compare=A..B`: two commits' results side by side
+    compare: Option<String>,
+    /// `threshold=`: duration change reported by compare, as a fraction
+    threshold: f64,
     /// format=json: machine-readable variants of every view, the
     /// contract ci-status's server mode consumes (src/api.rs)
     json: bool,
+    /// format=junit: JUnit XML of the commit view
+    junit: bool,
 }
 
 fn json_response<T: serde::Serialize>(val: &T) -> cgi::Response {
@@ -135,21 +151,48 @@ fn ci_branch_get_results(ci: &Ci) -> Result<Vec<CommitResults>, String> {
     )
 }
 
+/// Quarantine patterns of the branch being viewed (none outside a
+/// branch view).
+fn ci_quarantine(ci: &Ci) -> Quarantine {
+    let (Some(user), Some(branch)) = (&ci.user, &ci.branch) else {
+        return Quarantine::default();
+    };
+    ci.rc
+        .users
+        .get(user)
+        .and_then(|u| u.as_ref().ok())
+        .map(|u| u.branch_quarantine(branch))
+        .unwrap_or_default()
+}
+
+/// Escapes a string from the query (or anywhere else untrusted) for use
+/// in HTML text or a quoted attribute.
+fn attr(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+}
+
+/// The known-issues list. A broken file shouldn't take the pages down
+/// with it, but shouldn't silently stop annotating them either: log it
+/// (to the web server's error log) and go on without.
+fn known_issues(ci: &Ci) -> KnownIssues {
+    KnownIssues::load(&ci.rc.ktest.output_dir).unwrap_or_else(|e| {
+        eprintln!("cgi: {:#}", e);
+        KnownIssues::default()
+    })
+}
+
 /// Test-name search box: submits back to this view with a `test=` regex,
 /// which branch_get_results already applies as the test filter - the form
 /// is just a way to type it. Hidden fields keep the current view's context.
 fn search_form(out: &mut String, ci: &Ci) {
-    fn attr(s: &str) -> String {
-        s.replace('&', "&amp;")
-            .replace('<', "&lt;")
-            .replace('"', "&quot;")
-    }
-
     writeln!(out, "<form method=get action={}>", ci.script_name).unwrap();
     for (name, val) in [
         ("user", &ci.user),
         ("branch", &ci.branch),
         ("commit", &ci.commit),
+        ("view", &ci.view),
     ] {
         if let Some(val) = val {
             writeln!(
@@ -211,7 +254,6 @@ fn single_test_stats(commits: &[CommitResults]) -> Option<SingleTestStats> {
 #[cfg(test)]
 mod single_test_stats_tests {
     use super::*;
-    use chrono::Utc;
     use ci_cgi::TestResult;
 
     fn commit(tests: &[(&str, TestStatus, u64)]) -> CommitResults {
@@ -220,16 +262,7 @@ mod single_test_stats_tests {
             message: String::new(),
             tests: tests
                 .iter()
-                .map(|(name, status, duration)| {
-                    (
-                        (*name).to_string(),
-                        TestResult {
-                            status: *status,
-                            starttime: Utc::now(),
-                            duration: *duration,
-                        },
-                    )
-                })
+                .map(|(name, status, duration)| ((*name).to_string(), TestResult::new(*status, *duration)))
                 .collect(),
         }
     }
@@ -282,9 +315,11 @@ fn ci_log(ci: &Ci) -> cgi::Response {
     }
 
     let commits = commits.unwrap();
+    let quarantine = ci_quarantine(ci);
 
     if ci.json {
-        return json_response(&ci_cgi::branch_entries(commits));
+        let known = known_issues(ci);
+        return json_response(&ci_cgi::branch_entries(commits, &quarantine, Some(&known)));
     }
 
     let mut multiple_test_view = false;
@@ -306,6 +341,18 @@ fn ci_log(ci: &Ci) -> cgi::Response {
     writeln!(&mut out, "<body>").unwrap();
     writeln!(&mut out, "<div class=\"container\">").unwrap();
     search_form(&mut out, ci);
+    writeln!(
+        &mut out,
+        "<p> <a href=\"{}?user={}&branch={}&view=regressions\">Regressions</a> \
+         <a href=\"{}?user={}&branch={}&view=failures\">Failures</a> </p>",
+        ci.script_name,
+        ci.user.as_ref().unwrap(),
+        branch,
+        ci.script_name,
+        ci.user.as_ref().unwrap(),
+        branch
+    )
+    .unwrap();
 
     if !ci.tests_matching.as_str().is_empty() {
         if let Some(stats) = single_test_stats(&commits) {
@@ -333,6 +380,7 @@ fn ci_log(ci: &Ci) -> cgi::Response {
         writeln!(&mut out, "<th> Description </th>").unwrap();
         writeln!(&mut out, "<th> Passed      </th>").unwrap();
         writeln!(&mut out, "<th> Failed      </th>").unwrap();
+        writeln!(&mut out, "<th> Quarantined </th>").unwrap();
         writeln!(&mut out, "<th> Failed to run </th>").unwrap();
         writeln!(&mut out, "<th> Not run     </th>").unwrap();
         writeln!(&mut out, "<th> In progress </th>").unwrap();
@@ -361,6 +409,7 @@ fn ci_log(ci: &Ci) -> cgi::Response {
                 let subject_len = r.message.find('\n').unwrap_or(r.message.len());
 
                 let duration: u64 = r.tests.iter().map(|x| x.1.duration).sum();
+                let quarantined = count_quarantined(&r.tests, &quarantine) as usize;
 
                 writeln!(&mut out, "<tr>").unwrap();
                 writeln!(
@@ -383,9 +432,10 @@ fn ci_log(ci: &Ci) -> cgi::Response {
                 writeln!(
                     &mut out,
                     "<td> {} </td>",
-                    count(&r.tests, TestStatus::Failed)
+                    count(&r.tests, TestStatus::Failed) - quarantined
                 )
                 .unwrap();
+                writeln!(&mut out, "<td> {} </td>", quarantined).unwrap();
                 writeln!(
                     &mut out,
                     "<td> {} </td>",
@@ -486,6 +536,316 @@ fn ci_log(ci: &Ci) -> cgi::Response {
     cgi::html_response(200, out)
 }
 
+fn ci_regressions(ci: &Ci) -> cgi::Response {
+    let mut out = String::new();
+    let branch = ci.branch.as_ref().unwrap();
+    let user = ci.user.as_ref().unwrap();
+
+    let commits = ci_branch_get_results(ci);
+    if let Err(e) = commits {
+        return if ci.json { json_error(e) } else { error_response(e) };
+    }
+    let r = branch_regressions(&commits.unwrap());
+
+    if ci.json {
+        return json_response(&r);
+    }
+
+    let commit_link = |id: &str| {
+        format!(
+            "<a href=\"{}?user={}&branch={}&commit={}\">{}</a>",
+            ci.script_name,
+            user,
+            branch,
+            id,
+            &id[..id.len().min(14)]
+        )
+    };
+
+    writeln!(&mut out, "<!DOCTYPE HTML>").unwrap();
+    writeln!(&mut out, "<html><head><title>{} regressions</title></head>", attr(branch)).unwrap();
+    writeln!(
+        &mut out,
+        "<link href=\"{}\" rel=\"stylesheet\">",
+        ci.stylesheet
+    )
+    .unwrap();
+
+    writeln!(&mut out, "<body>").unwrap();
+    writeln!(&mut out, "<div class=\"container\">").unwrap();
+    search_form(&mut out, ci);
+
+    if r.commit.is_empty() {
+        writeln!(&mut out, "<p> No results for {} </p>", attr(branch)).unwrap();
+    } else {
+        writeln!(
+            &mut out,
+            "<p> {} regressions at {} </p>",
+            r.regressions.len(),
+            commit_link(&r.commit)
+        )
+        .unwrap();
+    }
+
+    writeln!(&mut out, "<table class=\"table\">").unwrap();
+    writeln!(&mut out, "<tr>").unwrap();
+    writeln!(&mut out, "<th> Test        </th>").unwrap();
+    writeln!(&mut out, "<th> Last passed </th>").unwrap();
+    writeln!(&mut out, "<th> Description </th>").unwrap();
+    writeln!(&mut out, "<th> Commits ago </th>").unwrap();
+    writeln!(&mut out, "<th> First bad   </th>").unwrap();
+    writeln!(&mut out, "</tr>").unwrap();
+
+    for t in &r.regressions {
+        writeln!(&mut out, "<tr class=table-danger>").unwrap();
+        writeln!(
+            &mut out,
+            "<td> <a href=\"c/{}/{}/log.br\">{}</a> </td>",
+            &r.commit,
+            attr(&t.name),
+            attr(&t.name)
+        )
+        .unwrap();
+        writeln!(&mut out, "<td> {} </td>", commit_link(&t.last_passed)).unwrap();
+        writeln!(&mut out, "<td> {} </td>", attr(&t.last_passed_subject)).unwrap();
+        writeln!(&mut out, "<td> {} </td>", t.age).unwrap();
+        match &t.first_bad {
+            Some(bad) => writeln!(&mut out, "<td> {} </td>", commit_link(bad)).unwrap(),
+            None => writeln!(&mut out, "<td> </td>").unwrap(),
+        }
+        writeln!(
+            &mut out,
+            "<td> <a href={}?user={}&branch={}&test={}> git log </a> </td>",
+            ci.script_name,
+            user,
+            branch,
+            exact_test_query(&t.name)
+        )
+        .unwrap();
+        writeln!(&mut out, "</tr>").unwrap();
+    }
+
+    writeln!(&mut out, "</table>").unwrap();
+    writeln!(&mut out, "</div>").unwrap();
+    writeln!(&mut out, "</body>").unwrap();
+    writeln!(&mut out, "</html>").unwrap();
+    cgi::html_response(200, out)
+}
+
+/// One test's result at every commit down the branch: `test=` is the
+/// exact result key here, not a regex.
+fn ci_history(ci: &Ci) -> cgi::Response {
+    let mut out = String::new();
+    let branch = ci.branch.as_ref().unwrap();
+    let user = ci.user.as_ref().unwrap();
+    let test = ci.tests_matching.as_str();
+
+    if test.is_empty() {
+        let e = "history needs test=<result key>".to_string();
+        return if ci.json { json_error(e) } else { error_response(e) };
+    }
+
+    let h = branch_test_history(
+        &ci.repo,
+        &ci.rc.ktest,
+        Some(user),
+        Some(branch),
+        None,
+        test,
+    );
+    if let Err(e) = h {
+        return if ci.json { json_error(e) } else { error_response(e) };
+    }
+    let h = h.unwrap();
+
+    if ci.json {
+        return json_response(&h);
+    }
+
+    writeln!(&mut out, "<!DOCTYPE HTML>").unwrap();
+    writeln!(&mut out, "<html><head><title>{} history</title></head>", attr(test)).unwrap();
+    writeln!(
+        &mut out,
+        "<link href=\"{}\" rel=\"stylesheet\">",
+        ci.stylesheet
+    )
+    .unwrap();
+
+    writeln!(&mut out, "<body>").unwrap();
+    writeln!(&mut out, "<div class=\"container\">").unwrap();
+    writeln!(&mut out, "<h4> {} on {} </h4>", attr(test), attr(branch)).unwrap();
+
+    let stats = h.stats();
+    if stats.completed != 0 {
+        writeln!(
+            &mut out,
+            "<p> {}/{} passed ({:.1}%); mean duration {} </p>",
+            stats.passed,
+            stats.completed,
+            stats.passed as f64 * 100.0 / stats.completed as f64,
+            format_duration(stats.mean_duration)
+        )
+        .unwrap();
+    }
+
+    writeln!(&mut out, "<table class=\"table\">").unwrap();
+    writeln!(&mut out, "<tr>").unwrap();
+    writeln!(&mut out, "<th> Commit      </th>").unwrap();
+    writeln!(&mut out, "<th> Description </th>").unwrap();
+    writeln!(&mut out, "<th> Status      </th>").unwrap();
+    writeln!(&mut out, "<th> Duration    </th>").unwrap();
+    writeln!(&mut out, "<th> Started     </th>").unwrap();
+    writeln!(&mut out, "</tr>").unwrap();
+
+    for c in &h.commits {
+        let status = c.result.as_ref().map(|r| TestStatus::from_str(&r.status));
+        writeln!(
+            &mut out,
+            "<tr class={}>",
+            status.map_or("", |s| s.table_class())
+        )
+        .unwrap();
+        writeln!(
+            &mut out,
+            "<td> <a href=\"{}?user={}&branch={}&commit={}\">{}</a> </td>",
+            ci.script_name,
+            attr(user),
+            attr(branch),
+            c.commit,
+            &c.commit[..c.commit.len().min(14)]
+        )
+        .unwrap();
+        writeln!(&mut out, "<td> {} </td>", attr(&c.subject)).unwrap();
+        match &c.result {
+            Some(r) => {
+                use chrono::TimeZone;
+                let started = chrono::Utc.timestamp_opt(r.starttime, 0).single()
+                    .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+                writeln!(
+                    &mut out,
+                    "<td> <a href=\"c/{}/{}/log.br\">{}</a> </td>",
+                    c.commit, attr(test), r.status
+                )
+                .unwrap();
+                writeln!(&mut out, "<td> {}s </td>", r.duration).unwrap();
+                writeln!(&mut out, "<td> {} </td>", started).unwrap();
+            }
+            None => writeln!(&mut out, "<td> </td> <td> </td> <td> </td>").unwrap(),
+        }
+        writeln!(&mut out, "</tr>").unwrap();
+    }
+
+    writeln!(&mut out, "</table>").unwrap();
+    writeln!(&mut out, "</div>").unwrap();
+    writeln!(&mut out, "</body>").unwrap();
+    writeln!(&mut out, "</html>").unwrap();
+    cgi::html_response(200, out)
+}
+
+/// Failed results over the last `commits=` commits, grouped by failure
+/// signature.
+fn ci_failures(ci: &Ci) -> cgi::Response {
+    let mut out = String::new();
+    let branch = ci.branch.as_ref().unwrap();
+    let user = ci.user.as_ref().unwrap();
+
+    let commits = ci_branch_get_results(ci);
+    if let Err(e) = commits {
+        return if ci.json { json_error(e) } else { error_response(e) };
+    }
+    let mut commits = commits.unwrap();
+    commits.truncate(ci.commits);
+    let f = failure_clusters(&commits);
+
+    if ci.json {
+        return json_response(&f);
+    }
+
+    let commit_link = |id: &str| {
+        format!(
+            "<a href=\"{}?user={}&branch={}&commit={}\">{}</a>",
+            ci.script_name,
+            user,
+            branch,
+            id,
+            &id[..id.len().min(14)]
+        )
+    };
+
+    writeln!(&mut out, "<!DOCTYPE HTML>").unwrap();
+    writeln!(&mut out, "<html><head><title>{} failures</title></head>", attr(branch)).unwrap();
+    writeln!(
+        &mut out,
+        "<link href=\"{}\" rel=\"stylesheet\">",
+        ci.stylesheet
+    )
+    .unwrap();
+
+    writeln!(&mut out, "<body>").unwrap();
+    writeln!(&mut out, "<div class=\"container\">").unwrap();
+    search_form(&mut out, ci);
+    writeln!(
+        &mut out,
+        "<p> {} failure signatures over the last {} commits; {} failures without one </p>",
+        f.clusters.len(),
+        f.commits,
+        f.unclassified
+    )
+    .unwrap();
+
+    writeln!(&mut out, "<table class=\"table\">").unwrap();
+    writeln!(&mut out, "<tr>").unwrap();
+    writeln!(&mut out, "<th> Signature  </th>").unwrap();
+    writeln!(&mut out, "<th> Count      </th>").unwrap();
+    writeln!(&mut out, "<th> Tests      </th>").unwrap();
+    writeln!(&mut out, "<th> Kernels    </th>").unwrap();
+    writeln!(&mut out, "<th> First seen </th>").unwrap();
+    writeln!(&mut out, "<th> Last seen  </th>").unwrap();
+    writeln!(&mut out, "</tr>").unwrap();
+
+    for c in &f.clusters {
+        let tests: Vec<String> = c.tests.iter().map(|t| attr(t)).collect();
+        let kernels: Vec<String> = c
+            .kernels
+            .iter()
+            .map(|k| if k.is_empty() { "default".to_string() } else { attr(k) })
+            .collect();
+        writeln!(&mut out, "<tr class=table-danger>").unwrap();
+        writeln!(
+            &mut out,
+            "<td> <code>{}</code> </td>",
+            attr(&c.signature)
+        )
+        .unwrap();
+        writeln!(&mut out, "<td> {} </td>", c.count).unwrap();
+        writeln!(&mut out, "<td> {} </td>", tests.join("<br>")).unwrap();
+        writeln!(&mut out, "<td> {} </td>", kernels.join("<br>")).unwrap();
+        writeln!(
+            &mut out,
+            "<td> {} {} </td>",
+            commit_link(&c.first_seen),
+            attr(&c.first_seen_subject)
+        )
+        .unwrap();
+        writeln!(
+            &mut out,
+            "<td> {} <a href=\"c/{}/{}/log.br\">log</a> </td>",
+            commit_link(&c.last_seen),
+            c.last_seen,
+            attr(&c.last_seen_key)
+        )
+        .unwrap();
+        writeln!(&mut out, "</tr>").unwrap();
+    }
+
+    writeln!(&mut out, "</table>").unwrap();
+    writeln!(&mut out, "</div>").unwrap();
+    writeln!(&mut out, "</body>").unwrap();
+    writeln!(&mut out, "</html>").unwrap();
+    cgi::html_response(200, out)
+}
+
 fn log_link(out: &mut String, fname: &str, link: &str) {
     let onclick = format!(
         "fetch('{}')
@@ -515,6 +875,17 @@ fn ci_commit(ci: &Ci) -> cgi::Response {
     }
     let commits = commits.unwrap();
 
+    if ci.junit {
+        let r = &commits[0];
+        let xml = commit_junit(&r.id, &r.tests, |key| {
+            let log = ci.rc.ktest.output_dir.join(&r.id).join(key).join("log.br");
+            decompress_brotli(&std::fs::read(log).ok()?).ok()
+        });
+        return cgi::binary_response(200, "application/xml", xml.into_bytes());
+    }
+
+    let known = known_issues(ci);
+
     if ci.json {
         let r = &commits[0];
         return json_response(&api::CommitTests {
@@ -527,6 +898,12 @@ fn ci_commit(ci: &Ci) -> cgi::Response {
                     name: name.clone(),
                     status: t.status.to_str().to_string(),
                     duration: t.duration,
+                    first_bad: first_bad_commit(&commits, name).map(String::from),
+                    reruns: api::Reruns::from_result(t),
+                    failed_cases: t.failed_cases().into_iter().map(String::from).collect(),
+                    signature: t.signature.clone(),
+                    known_issue: known.lookup(&r.id, name, t).cloned(),
+                    slow_mean: t.slow,
                 })
                 .collect(),
         });
@@ -537,6 +914,7 @@ fn ci_commit(ci: &Ci) -> cgi::Response {
     let subject_len = message.find('\n').unwrap_or(message.len());
 
     update_lcov(&ci.rc.ktest, &first_commit.id);
+    let quarantine = ci_quarantine(ci);
 
     writeln!(&mut out, "<!DOCTYPE HTML>").unwrap();
 
@@ -562,6 +940,15 @@ fn ci_commit(ci: &Ci) -> cgi::Response {
     writeln!(&mut out, "<h3><th>{}</th></h3>", &message[..subject_len]).unwrap();
     search_form(&mut out, ci);
 
+    if let (Some(user), Some(branch)) = (&ci.user, &ci.branch) {
+        writeln!(
+            &mut out,
+            "<p> <a href={}?user={}&branch={}&commit={}&view=matrix> Kernel/env matrix </a> </p>",
+            ci.script_name, user, branch, &first_commit.id
+        )
+        .unwrap();
+    }
+
     if ci
         .rc
         .ktest
@@ -586,23 +973,70 @@ fn ci_commit(ci: &Ci) -> cgi::Response {
     writeln!(&mut out, "<div class=\"horizontal\">").unwrap();
     writeln!(&mut out, "<table class=\"table no-wrap\">").unwrap();
     for (name, result) in &first_commit.tests {
-        writeln!(&mut out, "<tr class={}>", result.status.table_class()).unwrap();
+        let reruns = api::Reruns::from_result(result);
+        let class = match &reruns {
+            Some(r) if r.is_flaky() => "table-warning",
+            _ => result.status.table_class(),
+        };
+        writeln!(&mut out, "<tr class={}>", class).unwrap();
         log_link(
             &mut out,
             &format!("c/{}/{}/log.br", &first_commit.id, name),
             name,
         );
-        writeln!(&mut out, "<td> {}s </td>", result.duration).unwrap();
+        match result.slow {
+            Some(_) => writeln!(&mut out, "<td class=table-warning> <b>{}s</b> </td>", result.duration),
+            None => writeln!(&mut out, "<td> {}s </td>", result.duration),
+        }
+        .unwrap();
         writeln!(&mut out, "<td> {}  </td>", result.status.to_str()).unwrap();
         writeln!(&mut out, "<td> {}  </td>", last_good_line(&commits, name)).unwrap();
+        match (first_bad_commit(&commits, name), &ci.branch) {
+            (Some(bad), Some(branch)) => writeln!(
+                &mut out,
+                "<td> first bad <a href={}?user={}&branch={}&commit={}>{}</a> </td>",
+                ci.script_name,
+                ci.user.as_ref().unwrap(),
+                branch,
+                bad,
+                short_commit(bad)
+            )
+            .unwrap(),
+            (Some(bad), None) => writeln!(&mut out, "<td> first bad {} </td>", short_commit(bad)).unwrap(),
+            (None, _) => writeln!(&mut out, "<td> </td>").unwrap(),
+        }
+        let mut notes: Vec<String> = reruns
+            .map(|r| r.describe())
+            .filter(|d| !d.is_empty())
+            .into_iter()
+            .collect();
+        if is_quarantined(&quarantine, name) {
+            notes.push("quarantined".to_string());
+        }
+        let failed_cases = result.failed_cases();
+        if !failed_cases.is_empty() {
+            notes.push(format!("failed: {}", failed_cases.join(" ")));
+        }
+        if let Some(sig) = &result.signature {
+            // Straight from the log: could have anything in it.
+            notes.push(format!("<code>{}</code>", attr(sig)));
+        }
+        if let Some(mean) = result.slow {
+            notes.push(format!("slow: usually {}", format_duration(mean)));
+        }
+        if let Some(issue) = known.lookup(&first_commit.id, name, result) {
+            let label = if issue.note.is_empty() { "known issue" } else { &issue.note };
+            notes.push(format!("<a href=\"{}\">{}</a>", attr(&issue.bug), attr(label)));
+        }
+        writeln!(&mut out, "<td> {} </td>", notes.join(", ")).unwrap();
         if let Some(branch) = &ci.branch {
             writeln!(
                 &mut out,
-                "<td> <a href={}?user={}&branch={}&test=^{}$> git log        </a> </td>",
+                "<td> <a href={}?user={}&branch={}&test={}> git log        </a> </td>",
                 ci.script_name,
                 ci.user.as_ref().unwrap(),
                 &branch,
-                name
+                exact_test_query(name)
             )
             .unwrap();
         }
@@ -634,6 +1068,83 @@ fn ci_commit(ci: &Ci) -> cgi::Response {
     cgi::html_response(200, out)
 }
 
+/// The commit's results pivoted: a row per (test, subtest), a column
+/// per kernel/env variant — "fails only on kasan" at a glance.
+fn ci_matrix(ci: &Ci) -> cgi::Response {
+    let mut out = String::new();
+
+    let commits = ci_branch_get_results(ci);
+    let commit = match commits.as_ref().map(|c| c.first()) {
+        Ok(Some(c)) => c,
+        Ok(None) => {
+            let e = "no results".to_string();
+            return if ci.json { json_error(e) } else { error_response(e) };
+        }
+        Err(e) => {
+            let e = e.clone();
+            return if ci.json { json_error(e) } else { error_response(e) };
+        }
+    };
+    let m = results_matrix(&commit.id, &commit.tests);
+
+    if ci.json {
+        return json_response(&m);
+    }
+
+    let subject_len = commit.message.find('\n').unwrap_or(commit.message.len());
+    let subject = attr(&commit.message[..subject_len]);
+
+    writeln!(&mut out, "<!DOCTYPE HTML>").unwrap();
+    writeln!(&mut out, "<html><head><title>{}</title></head>", subject).unwrap();
+    writeln!(
+        &mut out,
+        "<link href=\"{}\" rel=\"stylesheet\">",
+        ci.stylesheet
+    )
+    .unwrap();
+
+    writeln!(&mut out, "<body>").unwrap();
+    writeln!(&mut out, "<div class=\"container-fluid\">").unwrap();
+    writeln!(&mut out, "<h3> {} </h3>", subject).unwrap();
+    search_form(&mut out, ci);
+
+    writeln!(&mut out, "<table class=\"table table-sm\">").unwrap();
+    writeln!(&mut out, "<tr>").unwrap();
+    writeln!(&mut out, "<th> Test </th>").unwrap();
+    writeln!(&mut out, "<th> Subtest </th>").unwrap();
+    for c in &m.columns {
+        writeln!(&mut out, "<th> {} </th>", attr(&c.label())).unwrap();
+    }
+    writeln!(&mut out, "</tr>").unwrap();
+
+    for r in &m.rows {
+        writeln!(&mut out, "<tr>").unwrap();
+        writeln!(&mut out, "<td> {} </td>", attr(&r.test)).unwrap();
+        writeln!(&mut out, "<td> {} </td>", attr(&r.subtest)).unwrap();
+        for c in &r.cells {
+            match c {
+                Some(c) => writeln!(
+                    &mut out,
+                    "<td class={}> <a href=\"c/{}/{}/log.br\">{}</a> </td>",
+                    TestStatus::from_str(&c.status).table_class(),
+                    &commit.id,
+                    attr(&c.key),
+                    c.status
+                )
+                .unwrap(),
+                None => writeln!(&mut out, "<td> </td>").unwrap(),
+            }
+        }
+        writeln!(&mut out, "</tr>").unwrap();
+    }
+
+    writeln!(&mut out, "</table>").unwrap();
+    writeln!(&mut out, "</div>").unwrap();
+    writeln!(&mut out, "</body>").unwrap();
+    writeln!(&mut out, "</html>").unwrap();
+    cgi::html_response(200, out)
+}
+
 fn ci_list_branches(ci: &Ci, user: &Userrc, out: &mut String) {
     writeln!(out, "<div> <table class=\"table\">").unwrap();
 
@@ -731,6 +1242,90 @@ fn ci_list_users(ci: &Ci, out: &mut String) {
     writeln!(out, "</table> </div>").unwrap();
 }
 
+/// `compare=A..B`: what changed from commit A to commit B, grouped by
+/// test and kernel/env. Commits are hashes or prefixes of tested
+/// commits, from any branch or repo.
+fn ci_compare(ci: &Ci) -> cgi::Response {
+    let mut out = String::new();
+    let compare = ci.compare.as_ref().unwrap();
+
+    let Some((a, b)) = compare.split_once("..") else {
+        let e = format!("compare={}: want A..B", compare);
+        return if ci.json { json_error(e) } else { error_response(e) };
+    };
+    let c = match compare_commits(&ci.rc.ktest, a, b, ci.threshold) {
+        Ok(c) => c,
+        Err(e) => {
+            let e = format!("{:#}", e);
+            return if ci.json { json_error(e) } else { error_response(e) };
+        }
+    };
+
+    if ci.json {
+        return json_response(&c);
+    }
+
+    // Resolved to output directory names, which needn't be full hashes.
+    let short = |id: &str| attr(&id[..id.len().min(14)]);
+
+    writeln!(&mut out, "<!DOCTYPE HTML>").unwrap();
+    writeln!(&mut out, "<html><head><title>{}..{}</title></head>", short(&c.a), short(&c.b)).unwrap();
+    writeln!(
+        &mut out,
+        "<link href=\"{}\" rel=\"stylesheet\">",
+        ci.stylesheet
+    )
+    .unwrap();
+
+    writeln!(&mut out, "<body>").unwrap();
+    writeln!(&mut out, "<div class=\"container\">").unwrap();
+    writeln!(&mut out, "<h4> {} → {} </h4>", short(&c.a), short(&c.b)).unwrap();
+    if c.groups.is_empty() {
+        writeln!(&mut out, "<p> No changes </p>").unwrap();
+    }
+
+    writeln!(&mut out, "<table class=\"table\">").unwrap();
+    for g in &c.groups {
+        writeln!(
+            &mut out,
+            "<tr> <th colspan=4> {} {} {} </th> </tr>",
+            attr(&g.test),
+            attr(&g.kernel),
+            attr(&g.env)
+        )
+        .unwrap();
+        for ch in &g.changes {
+            let class = match ch.kind {
+                api::ChangeKind::NewlyFailing => "table-danger",
+                api::ChangeKind::NewlyPassing => "table-success",
+                _ => "table-secondary",
+            };
+            let side = |commit: &str, s: &Option<api::CompareSide>| match s {
+                Some(s) => format!(
+                    "<a href=\"c/{}/{}/log.br\">{}</a> {}s",
+                    attr(commit),
+                    attr(&ch.key),
+                    s.status,
+                    s.duration
+                ),
+                None => String::new(),
+            };
+            writeln!(&mut out, "<tr class={}>", class).unwrap();
+            writeln!(&mut out, "<td> {} </td>", attr(&ch.subtest)).unwrap();
+            writeln!(&mut out, "<td> {} </td>", ch.kind.describe()).unwrap();
+            writeln!(&mut out, "<td> {} </td>", side(&c.a, &ch.a)).unwrap();
+            writeln!(&mut out, "<td> {} </td>", side(&c.b, &ch.b)).unwrap();
+            writeln!(&mut out, "</tr>").unwrap();
+        }
+    }
+
+    writeln!(&mut out, "</table>").unwrap();
+    writeln!(&mut out, "</div>").unwrap();
+    writeln!(&mut out, "</body>").unwrap();
+    writeln!(&mut out, "</html>").unwrap();
+    cgi::html_response(200, out)
+}
+
 fn ci_home(ci: &Ci) -> cgi::Response {
     if ci.json {
         let users: Vec<api::UserBranches> = ci
@@ -782,6 +1377,69 @@ fn cgi_header_get(request: &cgi::Request, name: &str) -> String {
         .unwrap_or(String::new())
 }
 
+/// `%XX`-escape everything but unreserved characters, for a query value.
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A `test=` value matching just this test: the filter is a regex.
+fn exact_test_query(test: &str) -> String {
+    url_encode(&format!("^{}$", regex::escape(test)))
+}
+
+/// Undo `%XX` escapes in a query value; querify() hands values over
+/// raw. Result keys carry `=` for env, which has to travel encoded.
+fn url_decode(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex: Vec<u8> = bytes.clone().take(2).collect();
+                match std::str::from_utf8(&hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(c) if hex.len() == 2 => {
+                        out.push(c);
+                        bytes.nth(1);
+                    }
+                    _ => out.push(b),
+                }
+            }
+            _ => out.push(b),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod url_decode_tests {
+    use super::{exact_test_query, url_decode};
+    use regex::Regex;
+
+    #[test]
+    fn decodes_escapes_only() {
+        assert_eq!(url_decode("fs.ec%40A%3D1.ec"), "fs.ec@A=1.ec");
+        assert_eq!(url_decode("%5Efoo.*%24"), "^foo.*$");
+        assert_eq!(url_decode("a+b"), "a+b");
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn exact_test_query_round_trips() {
+        let q = exact_test_query("fs.ec@A=1 <b>&c");
+        assert!(q.bytes().all(|b| b.is_ascii_alphanumeric() || b"%-._~".contains(&b)));
+        let re = Regex::new(&url_decode(&q)).unwrap();
+        assert!(re.is_match("fs.ec@A=1 <b>&c"));
+        assert!(!re.is_match("fs.ec@A=1 <b>&c.x"));
+        assert!(!re.is_match("fsxec@A=1 <b>&c"));
+    }
+}
+
 fn error_response(msg: String) -> cgi::Response {
     let mut out = String::new();
     writeln!(&mut out, "{}", msg).unwrap();
@@ -803,6 +1461,7 @@ td.running, .running { color: #060; }
 td.pending { color: #888; }
 td.failed, .failed { color: #c00; }
 td.cancelled { color: #c60; }
+.drained { color: #c00; font-style: italic; }
 #logview { background: #111; color: #ddd; padding: .5em; height: 26em;
            overflow-y: auto; white-space: pre-wrap; font: 12px/1.3 monospace; }
 ";
@@ -887,11 +1546,15 @@ function render(s) {
   const pend = Object.entries(s.pending_by_group || {}).sort((a, b) => b[1] - a[1]);
   const pendTotal = pend.reduce((acc, e) => acc + e[1], 0);
   const pendStr = pend.map(e => e[0] + ' ' + e[1]).join(', ');
+  // Slots the daemon stopped feeding after repeated infra failures.
+  const drained = s.drained || {};
+  const nDrained = Object.keys(drained).length;
   document.getElementById('summary').textContent =
     pendTotal + ' pending' + (pendStr ? ' (' + pendStr + ')' : '') +
     ' · ' + (counts.running || 0) + ' running' +
     ' · ' + (counts.completed || 0) + ' completed' +
-    ' · ' + (counts.failed || 0) + ' failed';
+    ' · ' + (counts.failed || 0) + ' failed' +
+    (nDrained ? ' · ' + nDrained + ' drained' : '');
 
   // Fair-share standing: groups (users) by decayed recent farm time,
   // lowest first — that is who the scheduler serves next.
@@ -914,12 +1577,16 @@ function render(s) {
   for (const host of Object.keys(hosts).sort()) {
     const execs = hosts[host].sort((a, b) => a.name.localeCompare(b.name));
     const running = execs.filter(e => e.current_jobs.length).length;
+    const nd = execs.filter(e => drained[e.name]).length;
     const box = el('div', 'exec');
-    box.appendChild(el('h5', null, host + ' (' + running + '/' + execs.length + ')'));
+    box.appendChild(el('h5', null, host + ' (' + running + '/' + execs.length + ')' +
+                       (nd ? ' ' + nd + ' drained' : '')));
     for (const e of execs) {
       const j = e.current_jobs.length ? byId[e.current_jobs[0]] : null;
-      const row = el('div', 'job' + (j ? ' running' : ''),
-                     e.name + ' — ' + (j ? j.name : 'idle'));
+      const d = drained[e.name];
+      const row = el('div', 'job' + (j ? ' running' : d ? ' drained' : ''),
+                     e.name + ' — ' + (j ? j.name : d ? 'drained: ' + d.error : 'idle'));
+      if (d) row.title = 'since ' + d.since + (d.host ? ' (whole host)' : '');
       const u = logUrl(e.log_path);
       if (u) row.onclick = () => openLog(e.name, u);
       box.appendChild(row);
@@ -1010,7 +1677,7 @@ cgi::cgi_main! {|request: cgi::Request| -> cgi::Response {
     let query: std::collections::HashMap<_, _> =
         querystring::querify(&query_string).into_iter().collect();
 
-    let tests_matching = query.get("test").unwrap_or(&"");
+    let tests_matching = url_decode(query.get("test").unwrap_or(&""));
 
     /* The dashboard handles multiple repos (linux, bcachefs-tools, ...) —
      * pick the right one for the current (user, branch) request. Mirrors
@@ -1038,18 +1705,35 @@ cgi::cgi_main! {|request: cgi::Request| -> cgi::Response {
         user:               query.get("user").map(|x| x.to_string()),
         branch:             query.get("branch").map(|x| x.to_string()),
         commit:             query.get("commit").map(|x| x.to_string()),
-        tests_matching:     Regex::new(tests_matching).unwrap_or(Regex::new("").unwrap()),
+        view:               query.get("view").map(|x| x.to_string()),
+        commits:            query.get("commits").and_then(|n| n.parse().ok())
+                                .unwrap_or(FAILURE_CLUSTER_COMMITS),
+        tests_matching:     Regex::new(&tests_matching).unwrap_or(Regex::new("").unwrap()),
+        compare:            query.get("compare").map(|x| x.to_string()),
+        threshold:          query.get("threshold").and_then(|t| t.parse().ok())
+                                .unwrap_or(COMPARE_DURATION_THRESHOLD),
         json:               query.get("format").map(|f| *f == "json").unwrap_or(false),
+        junit:              query.get("format").map(|f| *f == "junit").unwrap_or(false),
     };
 
     // querify() drops a bare key with no '=', so check the raw string.
     if query_string.split('&').any(|p| p == "status" || p.starts_with("status=")) {
         ci_status_page(&ci)
+    } else if ci.compare.is_some() {
+        ci_compare(&ci)
     } else if ci.user.is_some() {
         if ci.commit.is_some() {
-            ci_commit(&ci)
+            match ci.view.as_deref() {
+                Some("matrix") => ci_matrix(&ci),
+                _ => ci_commit(&ci),
+            }
         } else if ci.branch.is_some() {
-            ci_log(&ci)
+            match ci.view.as_deref() {
+                Some("regressions") => ci_regressions(&ci),
+                Some("history") => ci_history(&ci),
+                Some("failures") => ci_failures(&ci),
+                _ => ci_log(&ci),
+            }
         } else {
             ci_user(&ci)
         }
@@ -9,27 +9,51 @@
 // The matrix can be millions of jobs; jobkit only ever holds the
 // window (~2000). Each job is one subtest; an executor claims a
 // duration-bounded *batch* of one test file's subtests and runs them in
-// a single VM — checkout → build supervisor → run → pull results.
+// a single VM — checkout → build supervisor → run → pull results —
+// through its slot's Transport (ssh to a worker, or local).
 //
-// Deferred: gcov/lcov upload; the daemon's own git fetch and reconcile
-// on branch change (it currently refills only as the window drains).
+// Branches are fetched by a background thread: all of them every
+// fetch_interval, and one at a time on request through a spool dir
+// (see spawn_repo_fetcher()); a ref that moved triggers an immediate
+// refill instead of waiting for the window to drain.
+//
+// The config is reread on SIGHUP or when ktest-ci.json5 or a user
+// config changes: executors are added and retired to match, the
+// fetcher gets the new branch list, and the next refill uses the new
+// matrix — no restart, so nothing in flight is lost.
+//
+// Deferred: gcov/lcov upload.
 
 use anyhow::Result;
-use ci_cgi::jobs::{desired_jobs, Job, JobKey};
+use ci_cgi::check::check_config;
+use ci_cgi::index::ResultsIndex;
+use ci_cgi::jobs::{branch_tips, desired_jobs, live_commits, BatchTimeout, BranchTip, Job, JobKey};
+use ci_cgi::ktap;
+use ci_cgi::signature;
+use ci_cgi::metrics::{Family, Kind, Metrics};
+use ci_cgi::notify::{new_failures, recipients, Delivery, Notification};
+use ci_cgi::users::fetch_args;
 use ci_cgi::{
-    ciconfig_read, read_test_result, result_basename, subtest_result_key, CiConfig, TestResult,
-    TestResultsMap, TestResultsStore, TestStatus,
+    archive_test_attempt, ciconfig_read, drop_test_run, flag_slow, is_quarantined, ktestrc_path, read_test_attempts, read_test_result,
+    restore_test_attempt, result_basename, short_commit, subtest_result_key, CiConfig, ExecutorHost, TestResult, TestResultsMap,
+    TestResultsStore, TestStatus, SLOW_FACTOR,
 };
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use chrono::Utc;
 use clap::Parser;
 use jobkit::{
     ClaimedJob, Choir, Command, ExecutorConfig, ExecutorHandle, JobId, JobOutcome, JobSpec,
     TaskError,
 };
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::{Duration, Instant, SystemTime};
 
 /// Bounded job window: jobkit never holds much more than this — the
 /// desired matrix itself can be millions of jobs.
@@ -39,6 +63,25 @@ const WINDOW: usize = 2000;
 /// stays fresh between reconciles.
 const STATUS_INTERVAL: Duration = Duration::from_secs(2);
 
+/// How often to rescan the fetch spool when inotify is unavailable.
+const SPOOL_POLL: Duration = Duration::from_secs(5);
+
+/// Default fetch_interval, as before there were fetch requests.
+const FETCH_INTERVAL: u64 = 60;
+
+/// A notification that fails to send is retried up to NOTIFY_RETRIES
+/// times, waiting NOTIFY_BACKOFF before the first retry and twice as
+/// long before each one after, then dropped.
+const NOTIFY_RETRIES: u32 = 8;
+const NOTIFY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Set by SIGHUP: reread the config at the next status tick.
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    RELOAD.store(true, Ordering::Relaxed);
+}
+
 /// How often to run periodic upkeep — gc-results and gen-avg-duration,
 /// the maintenance the old ci-loop used to drive.
 const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30 * 60);
@@ -58,6 +101,60 @@ const SSH_OPTS: &[&str] = &[
     "-o", "StrictHostKeyChecking=accept-new",
 ];
 
+/// Everything the daemon exports to Prometheus (see write_status()).
+const METRICS: &[Family] = &[
+    Family {
+        name: "ci_daemon_jobs",
+        kind: Kind::Gauge,
+        help: "Jobs in the window, by user and state (pending, running)",
+    },
+    Family {
+        name: "ci_daemon_jobs_finished_total",
+        kind: Kind::Counter,
+        help: "Jobs finished, by user and outcome (completed, failed)",
+    },
+    Family {
+        name: "ci_daemon_executors",
+        kind: Kind::Gauge,
+        help: "Executor slots, by host and state (busy, idle, drained)",
+    },
+    Family {
+        name: "ci_daemon_refills_total",
+        kind: Kind::Counter,
+        help: "Window refill passes",
+    },
+    Family {
+        name: "ci_daemon_refill_desired",
+        kind: Kind::Gauge,
+        help: "Jobs desired_jobs() returned on the last refill",
+    },
+    Family {
+        name: "ci_daemon_refill_submitted",
+        kind: Kind::Gauge,
+        help: "Jobs newly submitted on the last refill",
+    },
+    Family {
+        name: "ci_daemon_desired_jobs_seconds",
+        kind: Kind::Summary,
+        help: "Time spent computing desired_jobs()",
+    },
+    Family {
+        name: "ci_daemon_batch_step_seconds",
+        kind: Kind::Summary,
+        help: "Batch step durations, by step",
+    },
+    Family {
+        name: "ci_daemon_retries_total",
+        kind: Kind::Counter,
+        help: "Batches failed with a retryable infrastructure error, by host",
+    },
+    Family {
+        name: "ci_daemon_fetch_failures_total",
+        kind: Kind::Counter,
+        help: "Failed branch fetches, by user and branch",
+    },
+];
+
 #[derive(Parser)]
 #[command(about = "push-mode CI job runner")]
 struct Args {
@@ -73,17 +170,281 @@ struct Args {
     /// daemon/worker version mismatch) terminalized them spuriously.
     #[arg(long)]
     clear_failed_to_run: bool,
+    /// Validate ktest-ci.json5 and every user config, then exit: non-zero
+    /// if anything would keep tests from running.
+    #[arg(long)]
+    check_config: bool,
+}
+
+// --- the executor closure ---
+
+/// The worker farm's bookkeeping, shared between refill(), the status
+/// writer and the executors: the executors themselves, batches in
+/// flight, the commits among them that no branch wants any more (so a
+/// running batch for a force-pushed-away commit can be abandoned), and
+/// each slot's health.
+/// A live executor (see Farm::sync_slots()).
+struct Slot {
+    /// Its host's entry in the config it was started with.
+    config: ExecutorHost,
+    /// Set when a config reload drops the slot, or changes how it's
+    /// reached; the executor exits before its next batch.
+    retired: bool,
 }
 
-fn short_commit(c: &str) -> &str {
-    &c[..c.len().min(12)]
+struct Farm {
+    /// Live executors by name ("host:slot").
+    executors: Mutex<BTreeMap<String, Slot>>,
+    running: Mutex<HashSet<JobId>>,
+    obsolete: Mutex<HashSet<String>>,
+    health: Health,
+    /// How often a drained slot probes its worker.
+    probe_interval: Mutex<Duration>,
 }
 
-// --- the executor closure ---
+impl Farm {
+    fn new(rc: &CiConfig) -> Farm {
+        let farm = Farm {
+            executors: Mutex::default(),
+            running: Mutex::default(),
+            obsolete: Mutex::default(),
+            health: Health::new(0, &rc.ktest.executors),
+            probe_interval: Mutex::new(Duration::ZERO),
+        };
+        farm.configure(rc);
+        farm
+    }
+
+    /// Apply the drain and probe settings; again on a config reload.
+    fn configure(&self, rc: &CiConfig) {
+        self.health.set_threshold(rc.ktest.drain_after_failures.unwrap_or(3));
+        *self.probe_interval.lock().unwrap() = Duration::from_secs(rc.ktest.probe_interval.unwrap_or(300));
+    }
+
+    fn probe_interval(&self) -> Duration {
+        *self.probe_interval.lock().unwrap()
+    }
+
+    /// Bring the live executors in line with the `executors` config, one
+    /// per host slot; returns the slots to start an executor for, with
+    /// their host's config. Slots no longer configured are retired, and
+    /// so are slots whose host entry changed other than in `slots` —
+    /// `local`, say — to be started again, the new way, once the old
+    /// executor has gone: until then two would share the slot. A slot
+    /// dropped and re-added before its executor noticed just keeps it.
+    fn sync_slots(&self, want: &BTreeMap<String, ExecutorHost>) -> Vec<(String, ExecutorHost, usize)> {
+        let same_worker = |a: &ExecutorHost, b: &ExecutorHost| {
+            ExecutorHost { slots: b.slots, ..a.clone() } == *b
+        };
+        let mut executors = self.executors.lock().unwrap();
+        let mut wanted = BTreeSet::new();
+        let mut added = Vec::new();
+
+        for (host, ex) in want {
+            for slot in 0..ex.slots as usize {
+                let name = format!("{}:{}", host, slot);
+                wanted.insert(name.clone());
+                match executors.get_mut(&name) {
+                    Some(s) if same_worker(&s.config, ex) => {
+                        s.config = ex.clone();
+                        s.retired = false;
+                    }
+                    Some(s) => {
+                        if !s.retired {
+                            eprintln!("ci-daemon: retiring executor {}: its host's config changed", name);
+                            s.retired = true;
+                        }
+                    }
+                    None => {
+                        executors.insert(name, Slot { config: ex.clone(), retired: false });
+                        added.push((host.clone(), ex.clone(), slot));
+                    }
+                }
+            }
+        }
+        for (name, s) in executors.iter_mut() {
+            if !wanted.contains(name) && !s.retired {
+                eprintln!("ci-daemon: retiring executor {}", name);
+                s.retired = true;
+            }
+        }
+        added
+    }
+
+    /// True if the executor `name` has been retired and should exit; it
+    /// is then forgotten, so re-adding the slot starts a new executor.
+    fn leaving(&self, name: &str) -> bool {
+        let mut executors = self.executors.lock().unwrap();
+        if executors.get(name).is_some_and(|s| s.retired) {
+            executors.remove(name);
+            return true;
+        }
+        false
+    }
+
+    fn is_obsolete(&self, commit: &str) -> bool {
+        self.obsolete.lock().unwrap().contains(commit)
+    }
+
+    /// Err if the batch's commit has been marked obsolete — checked
+    /// between steps, so an abandoned batch stops at the next one.
+    fn check(&self, commit: &str) -> Result<(), TaskError> {
+        if self.is_obsolete(commit) {
+            return Err(TaskError::Fatal(format!(
+                "cancelled: {} is no longer on any branch",
+                short_commit(commit)
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Why and since when a slot (or a whole host) stopped claiming work.
+#[derive(Clone, serde::Serialize)]
+struct Drained {
+    since: String,
+    error: String,
+}
+
+#[derive(Default)]
+struct SlotHealth {
+    /// Consecutive infrastructure failures (TaskError::Retry)
+    failures: u32,
+    drained: Option<Drained>,
+}
+
+#[derive(Default)]
+struct HostHealth {
+    /// Consecutive infrastructure failures across all the host's slots
+    failures: u32,
+    /// The slots those failures came from
+    failing: BTreeSet<usize>,
+    drained: Option<Drained>,
+    slots: BTreeMap<usize, SlotHealth>,
+}
+
+/// Per-slot and per-host infra-failure tracking. A broken worker (full
+/// disk, dead kvm) would otherwise keep claiming batches only to fail
+/// them, FailedToRun-ing subtests across every commit it touches. After
+/// `threshold` consecutive infra failures a slot is drained — it stops
+/// claiming and probes instead (see run_executor()); the same count
+/// across a host's slots, with no success in between, drains the whole
+/// host — if at least two slots are failing, else it's just the one
+/// slot that's broken. A successful probe puts the slot (and its host)
+/// back.
+struct Health {
+    /// 0 = never drain
+    threshold: AtomicU32,
+    hosts: Mutex<BTreeMap<String, HostHealth>>,
+}
+
+impl Health {
+    fn new(threshold: u32, executors: &BTreeMap<String, ExecutorHost>) -> Health {
+        let hosts = executors
+            .iter()
+            .map(|(host, ex)| {
+                let slots = (0..ex.slots as usize).map(|s| (s, SlotHealth::default())).collect();
+                (host.clone(), HostHealth { slots, ..Default::default() })
+            })
+            .collect();
+        Health { threshold: AtomicU32::new(threshold), hosts: Mutex::new(hosts) }
+    }
+
+    fn set_threshold(&self, threshold: u32) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    fn is_drained(&self, host: &str, slot: usize) -> bool {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.get(host).is_some_and(|h| {
+            h.drained.is_some() || h.slots.get(&slot).is_some_and(|s| s.drained.is_some())
+        })
+    }
+
+    fn success(&self, host: &str, slot: usize) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let h = hosts.entry(host.to_string()).or_default();
+        h.failures = 0;
+        h.failing.clear();
+        h.slots.entry(slot).or_default().failures = 0;
+    }
+
+    /// Record an infra failure; returns true if it drained the slot or
+    /// its host.
+    fn failure(&self, host: &str, slot: usize, error: &str) -> bool {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        if threshold == 0 {
+            return false;
+        }
+        let drained = || Some(Drained {
+            since: Utc::now().to_rfc3339(),
+            error: error.to_string(),
+        });
+        let mut hosts = self.hosts.lock().unwrap();
+        let h = hosts.entry(host.to_string()).or_default();
+        let mut newly = false;
+
+        h.failures += 1;
+        h.failing.insert(slot);
+        if h.failures >= threshold && h.failing.len() >= 2 && h.drained.is_none() {
+            h.drained = drained();
+            newly = true;
+        }
+        let s = h.slots.entry(slot).or_default();
+        s.failures += 1;
+        if s.failures >= threshold && s.drained.is_none() {
+            s.drained = drained();
+            newly = true;
+        }
+        newly
+    }
+
+    /// A probe succeeded: back in service.
+    fn recovered(&self, host: &str, slot: usize) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let h = hosts.entry(host.to_string()).or_default();
+        h.failures = 0;
+        h.failing.clear();
+        h.drained = None;
+        *h.slots.entry(slot).or_default() = SlotHealth::default();
+    }
+
+    /// Forget a slot whose executor was retired.
+    fn remove(&self, host: &str, slot: usize) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(h) = hosts.get_mut(host) {
+            h.slots.remove(&slot);
+            if h.slots.is_empty() {
+                hosts.remove(host);
+            }
+        }
+    }
+
+    /// Drained executors for the status snapshot, keyed by executor
+    /// name ("host:slot"); a drained host lists all its slots.
+    fn status(&self) -> serde_json::Value {
+        let hosts = self.hosts.lock().unwrap();
+        let mut out = serde_json::Map::new();
+        for (host, h) in hosts.iter() {
+            for (slot, s) in &h.slots {
+                if let Some(d) = s.drained.as_ref().or(h.drained.as_ref()) {
+                    out.insert(format!("{}:{}", host, slot), serde_json::json!({
+                        "since": d.since,
+                        "error": d.error,
+                        "host": s.drained.is_none(),
+                    }));
+                }
+            }
+        }
+        serde_json::Value::Object(out)
+    }
+}
 
 /// Everything the executor closure needs to run one subtest job.
 #[derive(Clone)]
 struct JobParams {
+    /// CI user the job belongs to - for metrics.
+    user: String,
     repo: String,
     commit: String,
     /// Kernel-store id, or empty for the build-from-repo path.
@@ -100,6 +461,12 @@ struct JobParams {
     ktest_url: String,
     /// Daemon-local results dir; pulled results land in `<it>/<commit>/`.
     output_dir: PathBuf,
+    /// Ktestrc::slow_factor, resolved
+    slow_factor: f64,
+    /// Historical p90 runtime, seconds (Job::p90)
+    p90: Option<u64>,
+    /// The test group's settings for the supervisor's -T
+    timeout: BatchTimeout,
 }
 
 /// An ssh command to `host` running `remote` (one shell command line).
@@ -119,6 +486,204 @@ fn ssh_cmd(host: &str, remote: &str, tty: bool) -> Command {
     c
 }
 
+/// How an executor reaches its worker slot. Every batch step is one
+/// shell command line, run from the worker user's home directory; the
+/// transport decides how it gets there and how results come back. Ssh
+/// is the farm; Local runs on the daemon's own machine (a `local` host
+/// in the executors config); tests drive the batch logic through an
+/// in-process fake.
+trait Transport: Send + Sync {
+    /// The worker host, for logs, metrics and health tracking.
+    fn host(&self) -> &str;
+
+    /// The worker's ktest checkout, relative to its home, and where to
+    /// sync it from, given the configured `ktest_url` (empty: don't).
+    fn ktest<'a>(&'a self, ktest_url: &'a str) -> (&'a str, &'a str) {
+        ("ktest", ktest_url)
+    }
+
+    /// Run `remote` on the worker. `tty` asks for the command's whole
+    /// process tree to die with the daemon's end of it (see ssh_cmd()).
+    fn run(
+        &self,
+        handle: &ExecutorHandle<JobParams>,
+        remote: &str,
+        tty: bool,
+    ) -> impl Future<Output = std::io::Result<ExitStatus>> + Send;
+
+    /// Copy the result dirs `dirs` under the workspace `ws`'s
+    /// ktest-out/out into `dst`.
+    fn pull(
+        &self,
+        handle: &ExecutorHandle<JobParams>,
+        ws: &str,
+        dirs: &[String],
+        dst: &Path,
+    ) -> impl Future<Output = std::io::Result<ExitStatus>> + Send;
+
+    /// Drop the workspace's per-batch scratch dir (ktest-tmp), whatever
+    /// state the batch left it in. Best effort.
+    fn cleanup(
+        &self,
+        handle: &ExecutorHandle<JobParams>,
+        ws: &str,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+struct Ssh {
+    host: String,
+}
+
+impl Transport for Ssh {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    async fn run(
+        &self,
+        handle: &ExecutorHandle<JobParams>,
+        remote: &str,
+        tty: bool,
+    ) -> std::io::Result<ExitStatus> {
+        handle.run_command(ssh_cmd(&self.host, remote, tty)).await
+    }
+
+    /// tar piped back over the ssh connection.
+    async fn pull(
+        &self,
+        handle: &ExecutorHandle<JobParams>,
+        ws: &str,
+        dirs: &[String],
+        dst: &Path,
+    ) -> std::io::Result<ExitStatus> {
+        let pull = format!(
+            "mkdir -p {dst} && ssh {opts} {host} 'cd {ws}/ktest-out/out && tar -c {dirs}' \
+             | tar -x -C {dst}",
+            dst = dst.display(),
+            opts = SSH_OPTS.join(" "),
+            host = self.host, ws = ws, dirs = dirs.join(" "),
+        );
+        let mut c = Command::new("bash");
+        c.arg("-c").arg(pull);
+        handle.run_command(c).await
+    }
+
+    async fn cleanup(&self, handle: &ExecutorHandle<JobParams>, ws: &str) {
+        let _ = self.run(handle, &format!("rm -rf {}/ktest-tmp", ws), false).await;
+    }
+}
+
+/// Slots on the daemon's own machine, workspaces in the daemon user's
+/// home; no ssh, results copied straight into output_dir.
+struct Local {
+    host: String,
+    /// The daemon's ktest_dir: the local slots' own checkout is synced
+    /// from it, whatever ktest_url says.
+    ktest_dir: String,
+}
+
+impl Local {
+    fn cmd(remote: &str) -> Command {
+        let mut c = Command::new("bash");
+        c.stdin(std::process::Stdio::null());
+        c.arg("-c").arg(format!("cd && {}", remote));
+        c
+    }
+}
+
+impl Transport for Local {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Next to the workspaces, not the daemon user's own ~/ktest — the
+    /// sync step force-checks it out — and synced from the ktest the job
+    /// matrix was enumerated from.
+    fn ktest<'a>(&'a self, _ktest_url: &'a str) -> (&'a str, &'a str) {
+        ("ktest-ci/ktest", &self.ktest_dir)
+    }
+
+    /// With `tty`, the command gets a process group of its own, killed
+    /// once the run ends however it ends (see ProcessGroup).
+    async fn run(
+        &self,
+        handle: &ExecutorHandle<JobParams>,
+        remote: &str,
+        tty: bool,
+    ) -> std::io::Result<ExitStatus> {
+        let mut c = Local::cmd(remote);
+        let _group = if tty { Some(ProcessGroup::new()?.attach(&mut c)) } else { None };
+        handle.run_command(c).await
+    }
+
+    async fn pull(
+        &self,
+        handle: &ExecutorHandle<JobParams>,
+        ws: &str,
+        dirs: &[String],
+        dst: &Path,
+    ) -> std::io::Result<ExitStatus> {
+        let pull = format!(
+            "mkdir -p {dst} && cd ~/{ws}/ktest-out/out && cp -r {dirs} {dst}/",
+            dst = dst.display(), ws = ws, dirs = dirs.join(" "),
+        );
+        handle.run_command(Local::cmd(&pull)).await
+    }
+
+    async fn cleanup(&self, handle: &ExecutorHandle<JobParams>, ws: &str) {
+        let _ = self.run(handle, &format!("rm -rf {}/ktest-tmp", ws), false).await;
+    }
+}
+
+/// A Local command's process group, killed when this is dropped: what
+/// ssh -tt gets from the remote pty hanging up, so a finished, timed-out
+/// or abandoned run doesn't leak its VM and kernel build. The child
+/// leads the group, and reports its pid — the group id — down a pipe as
+/// it starts.
+struct ProcessGroup {
+    rx: std::fs::File,
+    tx: OwnedFd,
+}
+
+impl ProcessGroup {
+    fn new() -> std::io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: both just opened, and owned by nothing else.
+        Ok(unsafe {
+            ProcessGroup {
+                rx: std::fs::File::from_raw_fd(fds[0]),
+                tx: OwnedFd::from_raw_fd(fds[1]),
+            }
+        })
+    }
+
+    fn attach(self, c: &mut Command) -> Self {
+        let tx = self.tx.as_raw_fd();
+        c.process_group(0);
+        // SAFETY: getpid() and write() are async-signal-safe.
+        unsafe {
+            c.pre_exec(move || {
+                let pid = libc::getpid();
+                libc::write(tx, &pid as *const libc::pid_t as *const libc::c_void, size_of::<libc::pid_t>());
+                Ok(())
+            });
+        }
+        self
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        let mut pid = [0; size_of::<libc::pid_t>()];
+        if self.rx.read_exact(&mut pid).is_ok() {
+            unsafe { libc::kill(-libc::pid_t::from_ne_bytes(pid), libc::SIGKILL) };
+        }
+    }
+}
+
 /// Shell env-var prefix for the supervisor command: `ktest_deps_dir`
 /// pointing at the workspace, plus the per-job env overrides. Encoded
 /// env values can't contain spaces, so no quoting is needed.
@@ -131,22 +696,25 @@ fn job_env_prefix(env: &str, ws: &str) -> String {
     prefix
 }
 
-/// Run one ssh step that must succeed; non-zero exit or a spawn failure
-/// is infrastructure failure → retry the job.
+/// Run one worker step that must succeed; non-zero exit or a spawn
+/// failure is infrastructure failure → retry the job. Its duration is
+/// recorded under `desc` as the step name.
 async fn run_step(
     handle: &ExecutorHandle<JobParams>,
-    host: &str,
+    metrics: &Metrics,
+    worker: &impl Transport,
     remote: &str,
     desc: &str,
 ) -> Result<(), TaskError> {
     handle.log_line(format!("=== {} ===", desc));
-    let status = handle
-        .run_command(ssh_cmd(host, remote, false))
-        .await
-        .map_err(|e| TaskError::Retry(format!("{desc}: {e}")))?;
+    let start = Instant::now();
+    let status = worker.run(handle, remote, false).await;
+    metrics.observe("ci_daemon_batch_step_seconds", &[("step", desc)],
+                    start.elapsed().as_secs_f64());
+    let status = status.map_err(|e| TaskError::Retry(format!("{desc}: {e}")))?;
     if !status.success() {
         return Err(TaskError::Retry(format!(
-            "{desc}: ssh exited {:?}",
+            "{desc}: exited {:?}",
             status.code()
         )));
     }
@@ -230,7 +798,9 @@ fn brotli_compress(path: &std::path::Path) -> std::io::Result<()> {
 /// Returns Err only on infrastructure failure (a step couldn't run).
 async fn run_ktest_job(
     handle: &ExecutorHandle<JobParams>,
-    host: &str,
+    metrics: &Metrics,
+    farm: &Farm,
+    worker: &impl Transport,
     slot: usize,
     results: &TestResultsStore,
     batch: &[ClaimedJob<JobParams>],
@@ -245,9 +815,7 @@ async fn run_ktest_job(
     let exec_log_offset_start = handle.log_offset();
     let exec_log_path = handle.log_path().to_path_buf();
 
-    let result = run_ktest_job_inner(
-        handle, host, slot, &exec_log_path, results, batch,
-    ).await;
+    let result = run_ktest_job_inner(handle, metrics, farm, worker, slot, results, batch).await;
 
     // Drop the worker-side per-batch ktest-tmp dir (scratch devices,
     // sockets, env files). ktest's own EXIT trap usually cleans it
@@ -255,9 +823,7 @@ async fn run_ktest_job(
     // mechanism the /tmp/ktest-* leaks were going through. Always
     // ssh+rm here so the daemon is the source of truth for that dir.
     let ws = format!("ktest-ci/{}", slot);
-    let _ = handle.run_command(
-        ssh_cmd(host, &format!("rm -rf {}/ktest-tmp", ws), false),
-    ).await;
+    worker.cleanup(handle, &ws).await;
 
     let p = &batch[0].payload;
     let commit_dir = p.output_dir.join(&p.commit);
@@ -276,7 +842,7 @@ async fn run_ktest_job(
 
     if !missing.is_empty() {
         let content = format_full_log(
-            host, slot, p, &batch.iter().map(|j| j.payload.subtest.as_str())
+            worker.host(), slot, p, &batch.iter().map(|j| j.payload.subtest.as_str())
                 .collect::<Vec<_>>(),
             &exec_log_path, exec_log_offset_start,
             &[],  // no supervisor body — inner didn't reach the pull
@@ -306,8 +872,42 @@ async fn run_ktest_job(
     // IN PROGRESS would stick them forever (job_wanted skips a live
     // Inprogress); deleting them would re-emit on every refill until a
     // VM finally landed, which buries a systematically-broken subtest.
-    if result.is_err() {
-        let now = Utc::now();
+    // A re-run is different: its subtest already has a verdict, which
+    // stands — the re-run that couldn't run is just recorded as an
+    // attempt (restore_test_attempt()).
+    //
+    // The exception is a batch abandoned because its commit is obsolete:
+    // nothing failed, and no refill will re-emit it, so its in-progress
+    // runs are just dropped — back to the verdict a re-run started from,
+    // if there was one. Earlier attempts are kept either way.
+    if result.is_err() && farm.is_obsolete(&p.commit) {
+        let mut stale = Vec::new();
+        let mut restored = TestResultsMap::new();
+        for j in batch {
+            let key = subtest_result_key(
+                &j.payload.test, &j.payload.subtest, &j.payload.kernel, &j.payload.env,
+            );
+            if results.lookup(&p.commit, &key) != Some(TestStatus::Inprogress) {
+                continue;
+            }
+            let d = commit_dir.join(&key);
+            match restore_test_attempt(&d, false) {
+                Ok(true) => if let Some(r) = read_test_result(&d) {
+                    restored.insert(key, r);
+                    continue;
+                },
+                Ok(false) => {
+                    let _ = drop_test_run(&d);
+                    // Gone altogether if it had no attempts.
+                    let _ = std::fs::remove_dir(&d);
+                }
+                Err(e) => handle.log_line(format!("restoring {key}'s previous run: {e}")),
+            }
+            stale.push(key);
+        }
+        results.delete(&p.commit, &stale);
+        results.update(&p.commit, restored);
+    } else if result.is_err() {
         let mut updates = TestResultsMap::new();
         for j in batch {
             let key = subtest_result_key(
@@ -317,11 +917,14 @@ async fn run_ktest_job(
                 let d = commit_dir.join(&key);
                 let _ = std::fs::create_dir_all(&d)
                     .and_then(|()| std::fs::write(d.join("status"), "FAILED TO RUN\n"));
-                updates.insert(key, TestResult {
-                    status: TestStatus::FailedToRun,
-                    starttime: now,
-                    duration: 0,
+                if let Err(e) = restore_test_attempt(&d, true) {
+                    handle.log_line(format!("restoring {key}'s previous run: {e}"));
+                }
+                let r = read_test_result(&d).unwrap_or_else(|| TestResult {
+                    attempts: read_test_attempts(&d),
+                    ..TestResult::new(TestStatus::FailedToRun, 0)
                 });
+                updates.insert(key, r);
             }
         }
         results.update(&p.commit, updates);
@@ -332,13 +935,15 @@ async fn run_ktest_job(
 
 async fn run_ktest_job_inner(
     handle: &ExecutorHandle<JobParams>,
-    host: &str,
+    metrics: &Metrics,
+    farm: &Farm,
+    worker: &impl Transport,
     slot: usize,
-    exec_log_path: &std::path::Path,
     results: &TestResultsStore,
     batch: &[ClaimedJob<JobParams>],
 ) -> Result<(), TaskError> {
     let p = &batch[0].payload;
+    let exec_log_path = handle.log_path();
     if p.repo_url.is_empty() {
         return Err(TaskError::Fatal(format!("repo {} not configured", p.repo)));
     }
@@ -346,7 +951,7 @@ async fn run_ktest_job_inner(
     let basename = result_basename(&p.test, &p.kernel, &p.env);
     let commit_dir = p.output_dir.join(&p.commit);
 
-    handle.log_line(format!("=== batch on {}:{} ===", host, slot));
+    handle.log_line(format!("=== batch on {}:{} ===", worker.host(), slot));
 
     // Subtests still owed a verdict — the whole claimed batch to start.
     let mut remaining: Vec<String> =
@@ -359,20 +964,28 @@ async fn run_ktest_job_inner(
     // this up front — before checkout — means the cgi shows the
     // claim the moment an executor picks the batch, not after the
     // multi-second checkout + supervisor build.
-    let now = Utc::now();
+    //
+    // A subtest that already has a verdict is being re-run (the test
+    // group's rerun_failed): its earlier run is archived as an attempt
+    // first, so the new run doesn't overwrite it.
     let mut inprogress_map = TestResultsMap::new();
     for st in &remaining {
         let key = subtest_result_key(&p.test, st, &p.kernel, &p.env);
         let d = commit_dir.join(&key);
+        if matches!(results.lookup(&p.commit, &key),
+                    Some(TestStatus::Passed | TestStatus::Failed)) {
+            if let Err(e) = archive_test_attempt(&d) {
+                handle.log_line(format!("archiving {st}'s previous run: {e}"));
+            }
+        }
         if let Err(e) = std::fs::create_dir_all(&d)
             .and_then(|()| std::fs::write(d.join("status"), "IN PROGRESS\n"))
         {
             handle.log_line(format!("marking {st} in-progress: {e}"));
         }
         inprogress_map.insert(key, TestResult {
-            status: TestStatus::Inprogress,
-            starttime: now,
-            duration: 0,
+            attempts: read_test_attempts(&d),
+            ..TestResult::new(TestStatus::Inprogress, 0)
         });
     }
     results.update(&p.commit, inprogress_map);
@@ -401,7 +1014,8 @@ async fn run_ktest_job_inner(
          test \"$(git rev-parse HEAD)\" = \"{commit}\"",
         ws = ws, repo = p.repo, url = p.repo_url, commit = p.commit,
     );
-    run_step(handle, host, &checkout, "checkout").await?;
+    farm.check(&p.commit)?;
+    run_step(handle, metrics, worker, &checkout, "checkout").await?;
 
     // 2. Sync the worker's ~/ktest from the jobserver - the pull
     //    model's sync_git_repos step, lost in the port to ci-daemon:
@@ -410,18 +1024,21 @@ async fn run_ktest_job_inner(
     //    found". checkout -f of a shared ~/ktest can race another
     //    slot's running batch, same as the old model - content only
     //    actually changes when ktest was updated, and a caught batch
-    //    retries.
-    if !p.ktest_url.is_empty() {
+    //    retries. (A local host's checkout is its own, see Local.)
+    let (ktest, ktest_url) = worker.ktest(&p.ktest_url);
+    if !ktest_url.is_empty() {
         let sync = format!(
             "retry() {{ n=0; until \"$@\"; do n=$((n+1)); [ $n -ge 5 ] && return 1; echo \"sync ktest: retry $n: $*\" >&2; sleep $((n*n)); done; }}; \
-             retry git -C ~/ktest fetch {url} && git -C ~/ktest checkout -f FETCH_HEAD",
-            url = p.ktest_url,
+             {{ [ -d ~/{ktest}/.git ] || retry git clone {url} ~/{ktest}; }} && \
+             retry git -C ~/{ktest} fetch {url} && git -C ~/{ktest} checkout -f FETCH_HEAD",
+            ktest = ktest,
+            url = ktest_url,
         );
-        run_step(handle, host, &sync, "sync ktest").await?;
+        run_step(handle, metrics, worker, &sync, "sync ktest").await?;
     }
 
     // 3. Build the supervisor (idempotent C helper).
-    run_step(handle, host, "make -C ~/ktest/lib supervisor", "build supervisor").await?;
+    run_step(handle, metrics, worker, &format!("make -C ~/{}/lib supervisor", ktest), "build supervisor").await?;
 
     // 4. Clean ktest-out (keep the kernel build cache); mark every
     //    subtest in-progress worker-side too so a dead VM still leaves
@@ -443,12 +1060,15 @@ async fn run_ktest_job_inner(
          {mark}",
         ws = ws, mark = mark,
     );
-    run_step(handle, host, &prepare, "prepare").await?;
+    farm.check(&p.commit)?;
+    run_step(handle, metrics, worker, &prepare, "prepare").await?;
 
     // Resume loop: run the not-yet-completed subtests in one VM; retry
     // any the VM died before reaching.
     let mut did_clean = false;
     while !remaining.is_empty() {
+        farm.check(&p.commit)?;
+
         // Mark this iter's window in the executor log so the post-pull
         // full_log write splices in exactly what we did this iter.
         let iter_offset = handle.log_offset();
@@ -463,9 +1083,9 @@ async fn run_ktest_job_inner(
         // teardown below catches it even if bash's EXIT trap doesn't
         // fire (SIGKILL, OOM); avoids /tmp/ktest-* leaks.
         let runner = if p.kernel.is_empty() {
-            format!("~/ktest/build-test-kernel run -k {}/{} -T ktest-tmp -P", ws, p.repo)
+            format!("~/{}/build-test-kernel run -k {}/{} -T ktest-tmp -P", ktest, ws, p.repo)
         } else {
-            format!("~/ktest/ktest run -k {} -T ktest-tmp", p.kernel)
+            format!("~/{}/ktest run -k {} -T ktest-tmp", ktest, p.kernel)
         };
         // The supervisor's -f full-log is one file per VM run; it lives
         // in remaining[0]'s dir, and every other subtest's full_log.br
@@ -474,11 +1094,18 @@ async fn run_ktest_job_inner(
             "{}/full_log",
             subtest_result_key(&p.test, &remaining[0], &p.kernel, &p.env),
         );
+        // -T: the supervisor's per-test watchdog, from the history of
+        // the subtests this VM runs (see BatchTimeout::secs()).
+        let timeout = p.timeout.secs(batch.iter()
+            .filter(|j| remaining.contains(&j.payload.subtest))
+            .map(|j| j.payload.p90));
         let inner = format!(
-            "cd {ws}; {env}~/ktest/lib/supervisor -T 1200 -f {full_log} \
-             -S -F -b {base} -o ktest-out/out -- {runner} ~/ktest/tests/{test} {subtests}",
+            "cd {ws}; {env}~/{ktest}/lib/supervisor -T {timeout} -f {full_log} \
+             -S -F -b {base} -o ktest-out/out -- {runner} ~/{ktest}/tests/{test} {subtests}",
             ws = ws,
+            ktest = ktest,
             env = job_env_prefix(&p.env, &ws),
+            timeout = timeout,
             full_log = full_log,
             base = basename,
             runner = runner,
@@ -488,7 +1115,7 @@ async fn run_ktest_job_inner(
         // build-test-kernel compiles the kernel on the worker — it needs
         // ci/shell.nix's toolchain. `ktest run` builds inside the VM.
         let run = if p.kernel.is_empty() {
-            format!("nix-shell ~/ktest/ci/shell.nix --run \"{}\"", inner)
+            format!("nix-shell ~/{}/ci/shell.nix --run \"{}\"", ktest, inner)
         } else {
             inner
         };
@@ -502,30 +1129,23 @@ async fn run_ktest_job_inner(
         let run = format!("( {run} )", run = run);
         // -tt: force a pty so a dropped ssh hangs up and SIGHUP reaps
         // the supervisor, the kernel build, and the VM together.
-        handle.run_command(ssh_cmd(host, &run, true))
-            .await
-            .map_err(|e| TaskError::Retry(format!("running supervisor: {e}")))?;
+        let start = Instant::now();
+        let status = worker.run(handle, &run, true).await;
+        metrics.observe("ci_daemon_batch_step_seconds", &[("step", "run")],
+                        start.elapsed().as_secs_f64());
+        status.map_err(|e| TaskError::Retry(format!("running supervisor: {e}")))?;
 
         // 6. Pull the remaining subtests' result dirs back to the
         //    daemon's output_dir.
         handle.log_line("=== pull results ===".to_string());
         let dirs = remaining.iter()
             .map(|st| subtest_result_key(&p.test, st, &p.kernel, &p.env))
-            .collect::<Vec<_>>()
-            .join(" ");
-        let pull = format!(
-            "mkdir -p {dst} && ssh {opts} {host} 'cd {ws}/ktest-out/out && tar -c {dirs}' \
-             | tar -x -C {dst}",
-            dst = commit_dir.display(),
-            opts = SSH_OPTS.join(" "),
-            host = host, ws = ws, dirs = dirs,
-        );
-        let mut pull_cmd = Command::new("bash");
-        pull_cmd.arg("-c").arg(&pull);
-        let status = handle
-            .run_command(pull_cmd)
-            .await
-            .map_err(|e| TaskError::Retry(format!("pulling results: {e}")))?;
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        let status = worker.pull(handle, &ws, &dirs, &commit_dir).await;
+        metrics.observe("ci_daemon_batch_step_seconds", &[("step", "pull")],
+                        start.elapsed().as_secs_f64());
+        let status = status.map_err(|e| TaskError::Retry(format!("pulling results: {e}")))?;
         if !status.success() {
             return Err(TaskError::Retry(format!(
                 "pulling results: exited {:?}",
@@ -553,7 +1173,7 @@ async fn run_ktest_job_inner(
         let primary_path = primary_dir.join("full_log");
         let supervisor_body = std::fs::read(&primary_path).unwrap_or_default();
         let content = format_full_log(
-            host, slot, p,
+            worker.host(), slot, p,
             &remaining.iter().map(String::as_str).collect::<Vec<_>>(),
             exec_log_path, iter_offset,
             &supervisor_body,
@@ -564,10 +1184,23 @@ async fn run_ktest_job_inner(
             handle.log_line(format!("brotli {}: {}", primary_path.display(), e));
         }
 
-        // Brotli per-subtest "log" files (one per test the supervisor
-        // reached).
+        // Pick KTAP case results and, for failures, a signature out of
+        // each per-subtest "log" (one per test the supervisor reached),
+        // then brotli it. Passes get checked against their mean
+        // duration.
+        let durations = std::fs::read(p.output_dir.join("test_durations.capnp")).ok();
         for st in &remaining {
-            let d = commit_dir.join(subtest_result_key(&p.test, st, &p.kernel, &p.env));
+            let key = subtest_result_key(&p.test, st, &p.kernel, &p.env);
+            let d = commit_dir.join(&key);
+            if let Err(e) = ktap::ingest(&d) {
+                handle.log_line(format!("ktap {}: {}", d.display(), e));
+            }
+            if let Err(e) = signature::ingest(&d) {
+                handle.log_line(format!("signature {}: {}", d.display(), e));
+            }
+            if let Err(e) = flag_slow(&d, &key, durations.as_deref(), p.slow_factor) {
+                handle.log_line(format!("slow {}: {}", d.display(), e));
+            }
             let path = d.join("log");
             if path.exists() {
                 if let Err(e) = brotli_compress(&path) {
@@ -601,10 +1234,9 @@ async fn run_ktest_job_inner(
         for st in &remaining {
             let key = subtest_result_key(&p.test, st, &p.kernel, &p.env);
             let dir = commit_dir.join(&key);
-            let r = read_test_result(&dir).unwrap_or(TestResult {
-                status: TestStatus::Inprogress,
-                starttime: now,
-                duration: 0,
+            let r = read_test_result(&dir).unwrap_or_else(|| TestResult {
+                attempts: read_test_attempts(&dir),
+                ..TestResult::new(TestStatus::Inprogress, 0)
             });
             if r.status == TestStatus::Inprogress {
                 next.push(st.clone());
@@ -626,7 +1258,7 @@ async fn run_ktest_job_inner(
                 did_clean = true;
                 handle.log_line("=== run completed no subtests -- git clean + retry ===".to_string());
                 let clean = format!("git -C ~/{ws}/{repo} clean -fdqx", ws = ws, repo = p.repo);
-                handle.run_command(ssh_cmd(host, &clean, false))
+                worker.run(handle, &clean, false)
                     .await
                     .map_err(|e| TaskError::Retry(format!("git clean: {e}")))?;
                 continue;
@@ -640,28 +1272,199 @@ async fn run_ktest_job_inner(
     Ok(())
 }
 
+/// Wait without claiming work. Goes through run_command() like
+/// everything else an executor does, so it needs nothing of whatever
+/// runtime jobkit drives the executor on.
+async fn sleep(handle: &ExecutorHandle<JobParams>, d: Duration) {
+    let mut c = Command::new("sleep");
+    c.arg(d.as_secs().to_string());
+    let _ = handle.run_command(c).await;
+}
+
+/// Check that a drained slot's worker could run a batch again: reachable
+/// (over ssh, unless local), kvm present, and its workspace writable.
+async fn probe(handle: &ExecutorHandle<JobParams>, worker: &impl Transport, slot: usize) -> bool {
+    let ws = format!("ktest-ci/{}", slot);
+    let check = format!(
+        "test -c /dev/kvm && mkdir -p {ws} && touch {ws}/.probe && rm {ws}/.probe"
+    );
+    handle.log_line(format!("=== probe {}:{} ===", worker.host(), slot));
+    matches!(worker.run(handle, &check, false).await, Ok(s) if s.success())
+}
+
 /// One executor's body: claim a duration-bounded batch of subtests, run
 /// them in one VM, report each job's outcome. Loops until the Choir is
-/// dropped. `budget` is subtest_duration_max — the most VM-time of work
-/// to pack into a boot.
+/// dropped, or until a config reload retires the slot. A slot that keeps
+/// failing for infrastructure reasons is drained (see Health) and only
+/// probes until it recovers. `budget` is subtest_duration_max — the most
+/// VM-time of work to pack into a boot.
 async fn run_executor(
     mut handle: ExecutorHandle<JobParams>,
-    host: String,
+    worker: impl Transport,
     slot: usize,
     results: Arc<TestResultsStore>,
+    metrics: Arc<Metrics>,
+    farm: Arc<Farm>,
     budget: f64,
 ) {
-    while let Some(batch) = handle.claim(budget).await {
-        let outcome = match run_ktest_job(&handle, &host, slot, &results, &batch).await {
-            Ok(()) => JobOutcome::Completed,
-            Err(e) => JobOutcome::Failed(e.to_string()),
+    let host = worker.host();
+    let name = format!("{}:{}", host, slot);
+    loop {
+        if farm.leaving(&name) {
+            eprintln!("ci-daemon: executor {} retired", name);
+            farm.health.remove(host, slot);
+            return;
+        }
+
+        // A drained slot doesn't claim; it probes every probe_interval
+        // until the worker looks usable again.
+        if farm.health.is_drained(host, slot) {
+            sleep(&handle, farm.probe_interval()).await;
+            if probe(&handle, &worker, slot).await {
+                eprintln!("ci-daemon: {}:{} probe ok, back in service", host, slot);
+                farm.health.recovered(host, slot);
+            }
+            continue;
+        }
+
+        let Some(batch) = handle.claim(budget).await else { break };
+        // Retired while waiting for work: hand the batch back untouched.
+        // Nothing was recorded for it yet, so once these jobs are
+        // reaped, the next refill submits the subtests again.
+        if farm.leaving(&name) {
+            eprintln!("ci-daemon: executor {} retired", name);
+            farm.health.remove(host, slot);
+            for j in &batch {
+                handle.report(j.id, JobOutcome::Failed("executor retired".to_string()));
+            }
+            return;
+        }
+        farm.running.lock().unwrap().extend(batch.iter().map(|j| j.id));
+        let outcome = match run_ktest_job(
+            &handle, &metrics, &farm, &worker, slot, &results, &batch,
+        ).await {
+            Ok(()) => {
+                farm.health.success(host, slot);
+                JobOutcome::Completed
+            }
+            Err(e) => {
+                if let TaskError::Retry(msg) = &e {
+                    metrics.inc("ci_daemon_retries_total", &[("host", host)]);
+                    if farm.health.failure(host, slot, msg) {
+                        eprintln!("ci-daemon: draining {}:{} after repeated infra failures: {}",
+                                  host, slot, msg);
+                    }
+                }
+                JobOutcome::Failed(e.to_string())
+            }
+        };
+        let outcome_str = match outcome {
+            JobOutcome::Completed => "completed",
+            _ => "failed",
         };
         for j in &batch {
+            metrics.inc("ci_daemon_jobs_finished_total",
+                        &[("user", &j.payload.user), ("outcome", outcome_str)]);
             handle.report(j.id, outcome.clone());
         }
+        let mut running = farm.running.lock().unwrap();
+        for j in &batch {
+            running.remove(&j.id);
+        }
+    }
+}
+
+/// Bring the Choir's executors in line with rc.ktest.executors (see
+/// Farm::sync_slots()). Retired executors exit before their next batch
+/// (see Farm::leaving()); called again every status tick, so a slot
+/// retired to be restarted differently is, as soon as its old executor
+/// is gone. Hosts that get new ssh executors have their master
+/// connection opened first.
+fn sync_executors(
+    choir: &Choir<JobParams>,
+    rc: &CiConfig,
+    results: &Arc<TestResultsStore>,
+    metrics: &Arc<Metrics>,
+    farm: &Arc<Farm>,
+) {
+    let added = farm.sync_slots(&rc.ktest.executors);
+
+    let ssh_hosts: BTreeSet<&str> = added
+        .iter()
+        .filter(|(_, ex, _)| !ex.local)
+        .map(|(host, _, _)| host.as_str())
+        .collect();
+    if !ssh_hosts.is_empty() {
+        eprintln!("ci-daemon: prewarming ssh masters to {} hosts", ssh_hosts.len());
+        prewarm_ssh_masters(&ssh_hosts);
+        eprintln!("ci-daemon: ssh master prewarm complete");
+    }
+
+    // The claim budget is subtest_duration_max — the most VM-time of
+    // work to pack into one boot.
+    let budget = rc.ktest.subtest_duration_max.unwrap_or(600) as f64;
+    for (host, ex, slot) in added {
+        if ex.local {
+            let ktest_dir = rc.ktest.ktest_dir.to_string_lossy().into_owned();
+            add_executor(choir, Local { host, ktest_dir }, slot, results, metrics, farm, budget);
+        } else {
+            add_executor(choir, Ssh { host }, slot, results, metrics, farm, budget);
+        }
     }
 }
 
+/// Start the executor `<host>:<slot>`, reaching its worker through
+/// `worker`.
+fn add_executor<T: Transport + 'static>(
+    choir: &Choir<JobParams>,
+    worker: T,
+    slot: usize,
+    results: &Arc<TestResultsStore>,
+    metrics: &Arc<Metrics>,
+    farm: &Arc<Farm>,
+    budget: f64,
+) {
+    let cfg = ExecutorConfig {
+        name: format!("{}:{}", worker.host(), slot),
+    };
+    let results = Arc::clone(results);
+    let metrics = Arc::clone(metrics);
+    let farm = Arc::clone(farm);
+    choir.add_executor(cfg, move |_cfg, handle| {
+        run_executor(handle, worker, slot, results, metrics, farm, budget)
+    });
+}
+
+/// Log what check_config() finds: the daemon runs with the config as
+/// it is, but a problem means some of its tests never get scheduled.
+fn report_config_problems(rc: &CiConfig) {
+    for p in check_config(rc) {
+        eprintln!("ci-daemon: config: {}", p);
+    }
+}
+
+/// Modification times of the config files — ktest-ci.json5 and every
+/// user config — so an edit is noticed without a SIGHUP.
+fn config_stamp(rc: &CiConfig) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files: Vec<PathBuf> = ktestrc_path().into_iter().collect();
+    if let Some(users_dir) = &rc.ktest.users_dir {
+        if let Ok(entries) = users_dir.read_dir() {
+            files.extend(entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("json5")));
+        }
+    }
+    files.sort();
+    files
+        .into_iter()
+        .map(|f| {
+            let mtime = std::fs::metadata(&f).and_then(|m| m.modified()).ok();
+            (f, mtime)
+        })
+        .collect()
+}
+
 /// Build the jobkit JobSpec for a desired job. Subtests of one test
 /// file at the same (user, repo, branch, commit, kernel, env) share a
 /// batch key, so an executor claims and runs them together in one VM.
@@ -673,6 +1476,7 @@ fn make_job_spec(rc: &CiConfig, job: &Job) -> JobSpec<JobParams> {
     // contributor's fetched-but-never-pushed commits actually live.
     let repo_url = rc.ktest.repo_path_url(&k.repo).map(String::from).unwrap_or_default();
     let params = JobParams {
+        user: k.user.clone(),
         repo: k.repo.clone(),
         commit: k.commit.clone(),
         kernel: k.kernel.clone(),
@@ -682,6 +1486,9 @@ fn make_job_spec(rc: &CiConfig, job: &Job) -> JobSpec<JobParams> {
         repo_url,
         ktest_url: rc.ktest.ktest_url.clone().unwrap_or_default(),
         output_dir: rc.ktest.output_dir.clone(),
+        slow_factor: rc.ktest.slow_factor.unwrap_or(SLOW_FACTOR),
+        p90: job.p90,
+        timeout: job.timeout,
     };
     let nice = job.nice + rc.ktest.user_nice.get(&k.user).copied().unwrap_or(0);
     // Mirror the old user_stats_select_fair multiplier: higher nice =
@@ -704,14 +1511,17 @@ fn make_job_spec(rc: &CiConfig, job: &Job) -> JobSpec<JobParams> {
 
 /// Top the job window back up. Finished jobs are dropped from the choir
 /// first — a still-desired one (e.g. a failed infra step) is then free
-/// to be re-submitted on this same pass. Then submit the newest desired
-/// jobs not already tracked; desired_jobs() caps itself at `window`, so
-/// the choir never holds much more than that.
+/// to be re-submitted on this same pass. Jobs for commits no branch
+/// wants any more are cancelled (see cancel_obsolete()). Then submit
+/// the newest desired jobs not already tracked; desired_jobs() caps
+/// itself at `window`, so the choir never holds much more than that.
 fn refill(
     choir: &Choir<JobParams>,
     job_map: &mut HashMap<JobKey, JobId>,
     rc: &CiConfig,
     results: &TestResultsStore,
+    metrics: &Metrics,
+    farm: &Farm,
     window: usize,
 ) {
     choir.remove(|_| true);
@@ -725,8 +1535,20 @@ fn refill(
     // subtests.
     let existing = choir.job_ids();
     job_map.retain(|_, id| existing.contains(id));
+    cancel_obsolete(choir, job_map, rc, farm);
 
+    let start = Instant::now();
     let desired = desired_jobs(rc, results, window);
+    metrics.observe("ci_daemon_desired_jobs_seconds", &[], start.elapsed().as_secs_f64());
+
+    for (commit, key) in &desired.first_bad {
+        eprintln!("bisected: {} first failed at {}", key, short_commit(commit));
+        if let Err(e) = results.mark_first_bad(commit, key) {
+            eprintln!("error marking {} first bad at {}: {}", key, commit, e);
+        }
+    }
+    let desired = desired.jobs;
+
     let mut submitted = 0;
     for job in &desired {
         if job_map.contains_key(&job.key) {
@@ -736,6 +1558,9 @@ fn refill(
         job_map.insert(job.key.clone(), id);
         submitted += 1;
     }
+    metrics.inc("ci_daemon_refills_total", &[]);
+    metrics.set("ci_daemon_refill_desired", &[], desired.len() as f64);
+    metrics.set("ci_daemon_refill_submitted", &[], submitted as f64);
     eprintln!(
         "refill: {} desired, {} submitted, {} tracked",
         desired.len(),
@@ -744,21 +1569,329 @@ fn refill(
     );
 }
 
-/// Write the Choir's status snapshot to the file the cgi reads.
-/// Written via a temp file + rename so the cgi never sees a partial.
-fn write_status(choir: &Choir<JobParams>, rc: &CiConfig) {
-    let json = match serde_json::to_string_pretty(&choir.status()) {
-        Ok(j) => j,
+/// Cancel the tracked jobs whose commit has dropped off every branch
+/// (live_commits()) — a force-push or rebase orphans them, and they'd
+/// otherwise keep the farm busy with a series nobody wants. Jobs of a
+/// branch that couldn't be read this time are kept. Pending jobs are
+/// cancelled in the choir; a running batch is only abandoned with
+/// cancel_running_obsolete set, at its next step (Farm::check()).
+fn cancel_obsolete(
+    choir: &Choir<JobParams>,
+    job_map: &mut HashMap<JobKey, JobId>,
+    rc: &CiConfig,
+    farm: &Farm,
+) {
+    let live = live_commits(rc);
+    let running = farm.running.lock().unwrap().clone();
+    let mut obsolete_running = HashSet::new();
+    let mut cancelled = 0;
+
+    job_map.retain(|key, id| {
+        if live.is_live(key) {
+            return true;
+        }
+        if running.contains(id) {
+            if rc.ktest.cancel_running_obsolete {
+                obsolete_running.insert(key.commit.clone());
+            }
+            return true;
+        }
+        choir.cancel(*id);
+        cancelled += 1;
+        false
+    });
+
+    if cancelled != 0 || !obsolete_running.is_empty() {
+        eprintln!(
+            "refill: cancelled {} obsolete pending jobs, abandoning running batches for {} commits",
+            cancelled,
+            obsolete_running.len(),
+        );
+    }
+    *farm.obsolete.lock().unwrap() = obsolete_running;
+}
+
+/// Write the Choir's status snapshot to the file the cgi reads, and the
+/// metrics (with the job and executor gauges refreshed from the same
+/// snapshot) to the Prometheus textfile. Both are written via a temp
+/// file + rename so readers never see a partial.
+///
+/// The snapshot gets a `drained` map alongside what jobkit reports:
+/// executor name → {since, error, host} for every drained slot.
+fn write_status(choir: &Choir<JobParams>, rc: &CiConfig, metrics: &Metrics, farm: &Farm) {
+    let mut status = match serde_json::to_value(choir.status()) {
+        Ok(s) => s,
         Err(e) => {
             eprintln!("status serialize failed: {}", e);
             return;
         }
     };
+    status["drained"] = farm.health.status();
+    let json = serde_json::to_string_pretty(&status).unwrap();
     let path = rc.ktest.output_dir.join("ci-daemon-status.json");
     let tmp = path.with_extension("json.new");
     if let Err(e) = std::fs::write(&tmp, &json).and_then(|()| std::fs::rename(&tmp, &path)) {
         eprintln!("writing {}: {}", path.display(), e);
     }
+
+    record_status_metrics(metrics, &status);
+    let path = rc.ktest.metrics_path.clone()
+        .unwrap_or_else(|| rc.ktest.output_dir.join("ci-daemon-metrics.prom"));
+    if let Err(e) = metrics.write(&path) {
+        eprintln!("writing {}: {}", path.display(), e);
+    }
+}
+
+/// Job and executor gauges, recomputed from the status snapshot (the
+/// same JSON the cgi's live page reads): pending per user from
+/// pending_by_group, running per user from the jobs list, busy/idle/
+/// drained slots per host from the executors.
+fn record_status_metrics(metrics: &Metrics, status: &serde_json::Value) {
+    let mut jobs: HashMap<(String, &str), u64> = HashMap::new();
+    if let Some(pending) = status["pending_by_group"].as_object() {
+        for (user, n) in pending {
+            jobs.insert((user.clone(), "pending"), n.as_u64().unwrap_or(0));
+        }
+    }
+    for j in status["jobs"].as_array().into_iter().flatten() {
+        if j["status"] == "running" {
+            let user = j["group"].as_str().unwrap_or("").to_string();
+            *jobs.entry((user, "running")).or_default() += 1;
+        }
+    }
+
+    metrics.clear("ci_daemon_jobs");
+    for ((user, state), n) in &jobs {
+        metrics.set("ci_daemon_jobs", &[("user", user), ("state", state)], *n as f64);
+    }
+
+    // Executor names are "host:slot" (see main()).
+    let mut executors: HashMap<(String, &str), u64> = HashMap::new();
+    for e in status["executors"].as_array().into_iter().flatten() {
+        let name = e["name"].as_str().unwrap_or("");
+        let host = name.split(':').next().unwrap_or(name).to_string();
+        let busy = e["current_jobs"].as_array().is_some_and(|j| !j.is_empty());
+        let state = if busy {
+            "busy"
+        } else if status["drained"].get(name).is_some() {
+            "drained"
+        } else {
+            "idle"
+        };
+        for s in ["busy", "idle", "drained"] {
+            executors.entry((host.clone(), s)).or_default();
+        }
+        *executors.entry((host, state)).or_default() += 1;
+    }
+
+    metrics.clear("ci_daemon_executors");
+    for ((host, state), n) in &executors {
+        metrics.set("ci_daemon_executors", &[("host", host), ("state", state)], *n as f64);
+    }
+}
+
+/// One finished tip's notifications, for the notifier thread.
+struct Outgoing {
+    userbranch: String,
+    commit: String,
+    via: Delivery,
+    sends: Vec<(String, Notification)>,
+}
+
+/// Failure-notification bookkeeping, shared by the main loop, which
+/// works out what to send, and the thread that sends it.
+struct NotifyState {
+    path: PathBuf,
+    /// user/branch -> the last tip whose notifications all went out, as
+    /// in ci-daemon-notified.json
+    notified: BTreeMap<String, String>,
+    /// Whether ci-daemon-notified.json existed; see Notifier
+    seeded: bool,
+    /// user/branch -> tip handed to the thread and not yet done with
+    queued: BTreeMap<String, String>,
+    /// (tip, target) already sent, or given up on, for tips whose other
+    /// targets failed
+    sent: BTreeSet<(String, String)>,
+    /// (tip, target) that failed to send: how often, and when to retry
+    retrying: BTreeMap<(String, String), (u32, Instant)>,
+}
+
+impl NotifyState {
+    fn save(&self) {
+        let tmp = self.path.with_extension("json.new");
+        let json = serde_json::to_string_pretty(&self.notified).unwrap();
+        if let Err(e) = std::fs::write(&tmp, json).and_then(|()| std::fs::rename(&tmp, &self.path)) {
+            eprintln!("writing {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Sends failure notifications for branch tips that have finished
+/// testing: subtests Failed at the tip that Passed at its parent, minus
+/// quarantined ones, to the branch's and test groups' `notify` targets.
+///
+/// Each (user, branch) is notified at most once per tip, tracked in
+/// ci-daemon-notified.json. Without that file (first run), finished tips
+/// are only recorded - so starting the daemon doesn't resend the state
+/// of the world.
+///
+/// Sending happens on a thread of its own, since an unreachable relay
+/// or webhook can take notify's full timeout per target. A tip is only
+/// recorded once every target has it, or has been given up on: a target
+/// that failed is retried with backoff (NOTIFY_RETRIES) for as long as
+/// it's the branch's tip, unless the failure was permanent — a bad
+/// target or config, or a refusal.
+struct Notifier {
+    state: Arc<Mutex<NotifyState>>,
+    tx: std::sync::mpsc::Sender<Outgoing>,
+}
+
+impl Notifier {
+    fn new(rc: &CiConfig) -> Notifier {
+        let path = rc.ktest.output_dir.join("ci-daemon-notified.json");
+        let state = Arc::new(Mutex::new(NotifyState {
+            seeded: path.exists(),
+            notified: std::fs::read(&path)
+                .ok()
+                .and_then(|buf| serde_json::from_slice(&buf).ok())
+                .unwrap_or_default(),
+            path,
+            queued: BTreeMap::new(),
+            sent: BTreeSet::new(),
+            retrying: BTreeMap::new(),
+        }));
+
+        let (tx, rx) = std::sync::mpsc::channel::<Outgoing>();
+        let thread_state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            for o in rx {
+                for (target, n) in &o.sends {
+                    let result = n.send(&o.via, target);
+                    let mut st = thread_state.lock().unwrap();
+                    let id = (o.commit.clone(), target.clone());
+                    match result {
+                        Ok(()) => {
+                            eprintln!(
+                                "notify: {} new failures at {} {} -> {}",
+                                n.failures.len(), o.userbranch, short_commit(&o.commit), target,
+                            );
+                        }
+                        Err(e) if e.permanent => {
+                            eprintln!("notify {}: {}; not retrying", target, e);
+                        }
+                        Err(e) => {
+                            let tries = st.retrying.get(&id).map_or(0, |(n, _)| *n) + 1;
+                            if tries <= NOTIFY_RETRIES {
+                                let wait = NOTIFY_BACKOFF * 2u32.pow(tries - 1);
+                                eprintln!("notify {}: {}; retrying in {}s", target, e, wait.as_secs());
+                                st.retrying.insert(id, (tries, Instant::now() + wait));
+                                continue;
+                            }
+                            eprintln!("notify {}: {}; dropped after {} retries", target, e, NOTIFY_RETRIES);
+                        }
+                    }
+                    st.retrying.remove(&id);
+                    st.sent.insert(id);
+                }
+
+                let mut st = thread_state.lock().unwrap();
+                st.queued.remove(&o.userbranch);
+                if !st.retrying.keys().any(|(commit, _)| *commit == o.commit) {
+                    st.sent.retain(|(commit, _)| *commit != o.commit);
+                    st.notified.insert(o.userbranch, o.commit);
+                    st.save();
+                }
+            }
+        });
+
+        Notifier { state, tx }
+    }
+
+    /// Queue notifications for tips that have finished since the last
+    /// pass, or whose failed sends are due a retry.
+    fn notify_finished_tips(&self, rc: &CiConfig, results: &TestResultsStore) {
+        let tips = branch_tips(rc, results);
+        let mut st = self.state.lock().unwrap();
+        let mut changed = false;
+
+        st.sent.retain(|(commit, _)| tips.iter().any(|t| t.commit == *commit));
+        st.retrying.retain(|(commit, _), _| tips.iter().any(|t| t.commit == *commit));
+        let now = Instant::now();
+
+        for tip in tips {
+            let userbranch = format!("{}/{}", tip.user, tip.branch);
+            if !tip.done
+                || st.notified.get(&userbranch) == Some(&tip.commit)
+                || st.queued.contains_key(&userbranch)
+            {
+                continue;
+            }
+
+            let sends: Vec<_> = if st.seeded { tip_notifications(rc, results, &tip) } else { Vec::new() }
+                .into_iter()
+                .filter(|(target, _)| !st.sent.contains(&(tip.commit.clone(), target.clone())))
+                .collect();
+            if sends.is_empty() {
+                st.notified.insert(userbranch, tip.commit);
+                changed = true;
+                continue;
+            }
+            let sends: Vec<_> = sends
+                .into_iter()
+                .filter(|(target, _)| {
+                    st.retrying.get(&(tip.commit.clone(), target.clone())).is_none_or(|(_, at)| *at <= now)
+                })
+                .collect();
+            if sends.is_empty() {
+                continue;
+            }
+
+            st.queued.insert(userbranch.clone(), tip.commit.clone());
+            let _ = self.tx.send(Outgoing {
+                userbranch,
+                commit: tip.commit,
+                via: Delivery::new(&rc.ktest),
+                sends,
+            });
+        }
+
+        if changed || !st.seeded {
+            st.save();
+            st.seeded = true;
+        }
+    }
+
+    /// Wait for everything queued to be sent, or to fail.
+    fn flush(&self) {
+        while !self.state.lock().unwrap().queued.is_empty() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// A finished tip's notifications, by target; none if nothing newly
+/// fails there.
+fn tip_notifications(
+    rc: &CiConfig,
+    results: &TestResultsStore,
+    tip: &BranchTip,
+) -> Vec<(String, Notification)> {
+    let Some(parent) = &tip.parent else { return Vec::new() };
+    let Some(Ok(userrc)) = rc.users.get(&tip.user) else { return Vec::new() };
+
+    let quarantine = userrc.branch_quarantine(&tip.branch);
+    let failures: Vec<String> = new_failures(
+        &results.commit_results(&tip.commit).unwrap_or_default(),
+        &results.commit_results(parent).unwrap_or_default(),
+    )
+    .into_iter()
+    .filter(|key| tip.keys.contains_key(key) && !is_quarantined(&quarantine, key))
+    .collect();
+
+    recipients(userrc, tip, &failures)
+        .into_iter()
+        .map(|(target, keys)| (target, Notification::new(&rc.ktest, tip, &keys)))
+        .collect()
 }
 
 /// Run a periodic-maintenance binary (gc-results, gen-avg-duration),
@@ -775,17 +1908,15 @@ fn run_maintenance(name: &str) {
 /// per-step ssh calls then multiplex over it (ControlMaster=auto in
 /// SSH_OPTS); without this, 50 executors each open a fresh connection
 /// at once and storm the workers' sshd MaxStartups.
-fn prewarm_ssh_masters(rc: &CiConfig) {
+fn prewarm_ssh_masters(hosts: &BTreeSet<&str>) {
     use std::process::Stdio;
     // Spawn every master connection at once, then collect them — a
     // serial pre-warm would cost one full `timeout` (30s) per down host.
     // `timeout` caps a wedged ssh so it can't hang startup; stdio is
     // detached so the persisted master doesn't hold the daemon's
     // streams open.
-    let spawned: Vec<_> = rc
-        .ktest
-        .executors
-        .keys()
+    let spawned: Vec<_> = hosts
+        .iter()
         .filter_map(|host| {
             let child = std::process::Command::new("timeout")
                 .arg("30")
@@ -815,19 +1946,73 @@ fn prewarm_ssh_masters(rc: &CiConfig) {
     }
 }
 
+/// A branch the fetcher keeps current: (user, branch, repo path, fetch
+/// args).
+type FetchBranch = (String, String, PathBuf, String);
+
+fn fetch_list(rc: &CiConfig) -> Vec<FetchBranch> {
+    let mut branches = Vec::new();
+    for (user, userconfig) in &rc.users {
+        let Ok(userconfig) = userconfig else { continue };
+        for (branch, bc) in &userconfig.branches {
+            match rc.ktest.repo_path(&bc.repo) {
+                Some(path) =>
+                    branches.push((user.clone(), branch.clone(), path.to_path_buf(), bc.fetch.clone())),
+                None =>
+                    eprintln!("ci-daemon: repo-fetcher: no path for repo {}", bc.repo),
+            }
+        }
+    }
+    branches
+}
+
+fn fetch_spool(rc: &CiConfig) -> PathBuf {
+    rc.ktest.fetch_spool.clone()
+        .unwrap_or_else(|| rc.ktest.output_dir.join("fetch-requests"))
+}
+
+/// Hand the fetcher the reloaded config's branches. Branches it didn't
+/// have before are also queued as a spool request, so they're fetched
+/// (and tested) now rather than at the next sweep.
+fn reload_fetch_list(rc: &CiConfig, branches: &Mutex<Vec<FetchBranch>>) {
+    let new = fetch_list(rc);
+    let added: Vec<String> = {
+        let mut branches = branches.lock().unwrap();
+        let added = new.iter()
+            .filter(|b| !branches.contains(b))
+            .map(|(user, branch, ..)| format!("{}/{}\n", user, branch))
+            .collect();
+        *branches = new;
+        added
+    };
+    if added.is_empty() {
+        return;
+    }
+
+    // Written under a dotfile name and renamed into place, as a hook
+    // would: the fetcher never reads a partial request.
+    let spool = fetch_spool(rc);
+    let tmp = spool.join(format!(".reload-{}", std::process::id()));
+    let path = spool.join(format!("reload-{}", std::process::id()));
+    if let Err(e) = std::fs::write(&tmp, added.concat()).and_then(|()| std::fs::rename(&tmp, &path)) {
+        eprintln!("ci-daemon: writing {}: {}", path.display(), e);
+    }
+}
+
 /// `git fetch <fetch>` in `path`, then point the local ref
-/// `<user>/<branch>` at the freshly-fetched FETCH_HEAD.
+/// `<user>/<branch>` at the freshly-fetched FETCH_HEAD. Returns whether
+/// the ref moved.
 fn fetch_branch(
     path: &std::path::Path,
     user: &str,
     branch: &str,
     fetch: &str,
-) -> Result<()> {
+) -> Result<bool> {
     let status = std::process::Command::new("git")
         .arg("-C")
         .arg(path)
         .arg("fetch")
-        .args(fetch.split_whitespace())
+        .args(fetch_args(fetch)?)
         .status()?;
     if !status.success() {
         anyhow::bail!("git fetch {} in {}: {}", fetch, path.display(), status);
@@ -835,8 +2020,85 @@ fn fetch_branch(
 
     let repo = git2::Repository::open(path)?;
     let fetch_head = repo.revparse_single("FETCH_HEAD")?.peel_to_commit()?;
-    repo.branch(&format!("{}/{}", user, branch), &fetch_head, true)?;
-    Ok(())
+    let name = format!("{}/{}", user, branch);
+    let old = repo
+        .find_branch(&name, git2::BranchType::Local)
+        .ok()
+        .and_then(|b| b.get().target());
+    repo.branch(&name, &fetch_head, true)?;
+    Ok(old != Some(fetch_head.id()))
+}
+
+/// inotify watch on the fetch spool dir, so a request is picked up the
+/// moment it's written rather than on the next poll.
+struct SpoolWatch {
+    fd: libc::c_int,
+}
+
+impl SpoolWatch {
+    fn new(dir: &std::path::Path) -> std::io::Result<SpoolWatch> {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let watch = SpoolWatch { fd };
+        let wd = unsafe {
+            libc::inotify_add_watch(fd, path.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO)
+        };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(watch)
+    }
+
+    /// Wait until something is written into the dir, or `timeout`. The
+    /// events themselves are discarded — the caller rescans the dir.
+    fn wait(&self, timeout: Duration) {
+        let mut pfd = libc::pollfd { fd: self.fd, events: libc::POLLIN, revents: 0 };
+        let ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        if unsafe { libc::poll(&mut pfd, 1, ms) } <= 0 {
+            return;
+        }
+        let mut buf = [0u8; 4096];
+        while unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+    }
+}
+
+impl Drop for SpoolWatch {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Consume the fetch requests in `spool`: every file's `<user>/<branch>`
+/// lines, deduplicated; anything else is logged and dropped. Dotfiles
+/// are skipped, so a writer can create `.tmp` and rename it into place.
+fn take_fetch_requests(spool: &std::path::Path) -> Vec<String> {
+    let mut requests = Vec::new();
+    let Ok(entries) = spool.read_dir() else { return requests };
+    for e in entries.filter_map(|e| e.ok()) {
+        if e.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = e.path();
+        let contents = std::fs::read_to_string(&path).unwrap_or_default();
+        if let Err(e) = std::fs::remove_file(&path) {
+            eprintln!("ci-daemon: removing {}: {}", path.display(), e);
+        }
+        for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let well_formed = line.split_once('/')
+                .is_some_and(|(u, b)| !u.is_empty() && !b.is_empty() && !line.contains(char::is_whitespace));
+            if !well_formed {
+                eprintln!("ci-daemon: {}: bad fetch request {:?}", path.display(), line);
+            } else if !requests.iter().any(|r| r == line) {
+                requests.push(line.to_string());
+            }
+        }
+    }
+    requests
 }
 
 /// Background thread: keep the CI's git refs current. `refill` / jobs.rs
@@ -847,36 +2109,99 @@ fn fetch_branch(
 /// configured `git fetch <remote> <ref>` — targeted, never `--all`, so CI
 /// bookkeeping tags (`origin/master_<date>` etc.) can't trip refname
 /// conflicts — then point `<user>/<branch>` at FETCH_HEAD.
-fn spawn_repo_fetcher(rc: &CiConfig) {
-    // Snapshot (user, branch, repo path, fetch args); the thread outlives `rc`.
-    let mut branches: Vec<(String, String, std::path::PathBuf, String)> = Vec::new();
-    for (user, userconfig) in &rc.users {
-        let Ok(userconfig) = userconfig else { continue };
-        for (branch, bc) in &userconfig.branches {
-            match rc.ktest.repo_path(&bc.repo) {
-                Some(path) =>
-                    branches.push((user.clone(), branch.clone(), path.to_path_buf(), bc.fetch.clone())),
-                None =>
-                    eprintln!("ci-daemon: repo-fetcher: no path for repo {}", bc.repo),
-            }
-        }
+///
+/// Every branch is fetched each fetch_interval; in between, a branch
+/// named in a fetch_spool request (a push hook's "I just pushed") is
+/// fetched immediately. Whenever a ref moves, `refill_now` is set so the
+/// main loop submits the new tip's jobs without waiting for the window
+/// to drain.
+///
+/// `branches` is shared with main(), which replaces it on config reload
+/// (see reload_fetch_list()).
+fn spawn_repo_fetcher(
+    rc: &CiConfig,
+    branches: Arc<Mutex<Vec<FetchBranch>>>,
+    metrics: Arc<Metrics>,
+    refill_now: Arc<AtomicBool>,
+) {
+    // 0 would sweep continuously; check_ktestrc() reports it.
+    let interval = match rc.ktest.fetch_interval {
+        Some(0) | None => FETCH_INTERVAL,
+        Some(i) => i,
+    };
+    let interval = Duration::from_secs(interval);
+    let spool = fetch_spool(rc);
+    if let Err(e) = std::fs::create_dir_all(&spool) {
+        eprintln!("ci-daemon: creating {}: {}", spool.display(), e);
     }
+    let watch = SpoolWatch::new(&spool)
+        .map_err(|e| eprintln!("ci-daemon: watching {}: {} - polling instead", spool.display(), e))
+        .ok();
 
-    std::thread::spawn(move || loop {
-        for (user, branch, path, fetch) in &branches {
-            if let Err(e) = fetch_branch(path, user, branch, fetch) {
-                eprintln!("ci-daemon: fetch {}/{}: {}", user, branch, e);
+    std::thread::spawn(move || {
+        let do_fetch = |(user, branch, path, fetch): &FetchBranch| {
+            match fetch_branch(path, user, branch, fetch) {
+                Ok(true) => refill_now.store(true, Ordering::Relaxed),
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("ci-daemon: fetch {}/{}: {}", user, branch, e);
+                    metrics.inc("ci_daemon_fetch_failures_total",
+                                &[("user", user), ("branch", branch)]);
+                }
+            }
+        };
+        let mut last_sweep: Option<Instant> = None;
+
+        loop {
+            if last_sweep.is_none_or(|t| t.elapsed() >= interval) {
+                let sweep = branches.lock().unwrap().clone();
+                for b in &sweep {
+                    do_fetch(b);
+                }
+                last_sweep = Some(Instant::now());
+            }
+
+            for req in take_fetch_requests(&spool) {
+                let b = branches.lock().unwrap().iter()
+                    .find(|(u, b, ..)| req == format!("{}/{}", u, b))
+                    .cloned();
+                match b {
+                    Some(b) => {
+                        eprintln!("ci-daemon: fetch requested for {}", req);
+                        do_fetch(&b);
+                    }
+                    None => eprintln!("ci-daemon: fetch request for unknown branch {}", req),
+                }
+            }
+
+            let timeout = interval.saturating_sub(last_sweep.map_or(interval, |t| t.elapsed()));
+            match &watch {
+                Some(w) => w.wait(timeout),
+                None => std::thread::sleep(timeout.min(SPOOL_POLL)),
             }
         }
-        std::thread::sleep(std::time::Duration::from_secs(60));
     });
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
     eprintln!("ci-daemon: starting");
-    let rc = ciconfig_read()?;
+    let mut rc = ciconfig_read()?;
+    if args.check_config {
+        let problems = check_config(&rc);
+        for p in &problems {
+            println!("{}", p);
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("{} config problems", problems.len());
+        }
+        println!("config ok");
+        return Ok(());
+    }
+    let mut stamp = config_stamp(&rc);
+    report_config_problems(&rc);
     eprintln!("ci-daemon: config loaded");
+    unsafe { libc::signal(libc::SIGHUP, on_sighup as *const () as libc::sighandler_t) };
 
     // Load the test result store. IN PROGRESS entries from a previous
     // daemon are stale (no longer running) and dropped — desired_jobs()
@@ -889,42 +2214,31 @@ fn main() -> Result<()> {
         rc.ktest.output_dir.display()
     );
     let load_start = std::time::Instant::now();
-    let results = Arc::new(TestResultsStore::load(
+    let mut results = TestResultsStore::load(
         rc.ktest.output_dir.clone(),
         args.clear_failed_to_run,
-    ));
+    );
+    if let Some(path) = &rc.ktest.results_index {
+        match ResultsIndex::open(path) {
+            Ok(index) => results = results.with_index(index),
+            Err(e) => eprintln!("ci-daemon: results index {}: {:#}", path.display(), e),
+        }
+    }
+    let results = Arc::new(results);
     eprintln!(
         "ci-daemon: test results loaded in {:.1}s",
         load_start.elapsed().as_secs_f64()
     );
 
     let choir: Choir<JobParams> = Choir::new(rc.ktest.output_dir.join("ci-daemon-logs"));
-
-    // Pre-open an ssh master per host so the executors' per-step ssh
-    // calls multiplex over it instead of storming sshd MaxStartups.
-    eprintln!(
-        "ci-daemon: prewarming ssh masters to {} hosts",
-        rc.ktest.executors.len()
-    );
-    prewarm_ssh_masters(&rc);
-    eprintln!("ci-daemon: ssh master prewarm complete");
+    let metrics = Arc::new(Metrics::new(METRICS));
+    let farm = Arc::new(Farm::new(&rc));
 
     // One executor per slot. The body claims a duration-bounded batch
-    // of one test file's subtests and runs them in a single VM.
-    let budget = rc.ktest.subtest_duration_max.unwrap_or(600) as f64;
-    for (host, ex) in &rc.ktest.executors {
-        for slot in 0..ex.slots {
-            let cfg = ExecutorConfig {
-                name: format!("{}:{}", host, slot),
-            };
-            let host = host.clone();
-            let slot = slot as usize;
-            let results = Arc::clone(&results);
-            choir.add_executor(cfg, move |_cfg, handle| {
-                run_executor(handle, host, slot, results, budget)
-            });
-        }
-    }
+    // of one test file's subtests and runs them in a single VM. Each
+    // host's ssh master is opened first, so the executors' per-step ssh
+    // calls multiplex over it instead of storming sshd MaxStartups.
+    sync_executors(&choir, &rc, &results, &metrics, &farm);
     let total_slots: u32 = rc.ktest.executors.values().map(|e| e.slots).sum();
     eprintln!(
         "ci-daemon: {} hosts, {} executors",
@@ -933,19 +2247,26 @@ fn main() -> Result<()> {
     );
 
     // Keep the CI repos current; the job matrix is a pure read of local refs.
-    spawn_repo_fetcher(&rc);
+    let refill_now = Arc::new(AtomicBool::new(false));
+    let fetch_branches = Arc::new(Mutex::new(fetch_list(&rc)));
+    spawn_repo_fetcher(&rc, Arc::clone(&fetch_branches), Arc::clone(&metrics),
+                       Arc::clone(&refill_now));
 
+    let notifier = Notifier::new(&rc);
     let mut job_map: HashMap<JobKey, JobId> = HashMap::new();
     let window = args.limit.unwrap_or(WINDOW);
     let mut last_maintenance: Option<std::time::Instant> = None;
 
     loop {
-        refill(&choir, &mut job_map, &rc, &results, window);
-        write_status(&choir, &rc);
+        refill(&choir, &mut job_map, &rc, &results, &metrics, &farm, window);
+        write_status(&choir, &rc, &metrics, &farm);
+        notifier.notify_finished_tips(&rc, &results);
 
         if args.once {
             choir.join_all();
-            write_status(&choir, &rc);
+            write_status(&choir, &rc, &metrics, &farm);
+            notifier.notify_finished_tips(&rc, &results);
+            notifier.flush();
             return Ok(());
         }
 
@@ -957,10 +2278,34 @@ fn main() -> Result<()> {
         }
 
         // Rewrite the status snapshot every STATUS_INTERVAL; refill once
-        // the window has drained low enough to want topping up.
+        // the window has drained low enough to want topping up, as soon
+        // as the fetcher has moved a branch, or after a config reload.
         loop {
             std::thread::sleep(STATUS_INTERVAL);
-            write_status(&choir, &rc);
+            sync_executors(&choir, &rc, &results, &metrics, &farm);
+            write_status(&choir, &rc, &metrics, &farm);
+            if refill_now.swap(false, Ordering::Relaxed) {
+                break;
+            }
+
+            let new_stamp = config_stamp(&rc);
+            if RELOAD.swap(false, Ordering::Relaxed) || new_stamp != stamp {
+                stamp = new_stamp;
+                // A broken edit keeps the running config; fix it and
+                // save again (or SIGHUP) to retry.
+                match ciconfig_read() {
+                    Ok(new_rc) => {
+                        eprintln!("ci-daemon: config reloaded");
+                        rc = new_rc;
+                        report_config_problems(&rc);
+                        farm.configure(&rc);
+                        sync_executors(&choir, &rc, &results, &metrics, &farm);
+                        reload_fetch_list(&rc, &fetch_branches);
+                        break;
+                    }
+                    Err(e) => eprintln!("ci-daemon: config reload failed, keeping the old one: {:#}", e),
+                }
+            }
             let pending: usize = choir.status().pending_by_group.values().sum();
             if pending <= window / 4 {
                 break;
@@ -968,3 +2313,422 @@ fn main() -> Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+#[path = "../test_util.rs"]
+mod test_util;
+
+#[cfg(test)]
+mod fetch_request_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn requests_are_consumed_and_deduplicated() {
+        let spool = TempDir::new("spool");
+        std::fs::write(spool.join("a"), "u/one\n\n  u/two  \nu/one\n").unwrap();
+        std::fs::write(spool.join("b"), "u/two\nnot a branch\nu/\nbranch\n").unwrap();
+        // Still being written
+        std::fs::write(spool.join(".tmp"), "u/three\n").unwrap();
+
+        let mut requests = take_fetch_requests(&spool);
+        requests.sort();
+        assert_eq!(requests, ["u/one", "u/two"]);
+
+        let left: Vec<_> = spool.read_dir().unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(left, [".tmp"]);
+        assert!(take_fetch_requests(&spool).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod health_tests {
+    use super::*;
+
+    #[test]
+    fn one_bad_slot_drains_only_itself() {
+        let health = Health::new(3, &BTreeMap::new());
+        assert!(!health.failure("h", 0, "e"));
+        assert!(!health.failure("h", 0, "e"));
+        assert!(health.failure("h", 0, "e"));
+        assert!(health.is_drained("h", 0));
+        assert!(!health.is_drained("h", 1));
+        // It keeps failing its probes; still only the slot.
+        assert!(!health.failure("h", 0, "e"));
+        assert!(!health.is_drained("h", 1));
+    }
+
+    #[test]
+    fn failures_across_slots_drain_the_host() {
+        let health = Health::new(3, &BTreeMap::new());
+        assert!(!health.failure("h", 0, "e"));
+        assert!(!health.failure("h", 1, "e"));
+        assert!(health.failure("h", 0, "e"));
+        assert!(health.is_drained("h", 2));
+        assert!(!health.is_drained("other", 0));
+
+        // A success anywhere on the host resets its count.
+        let health = Health::new(3, &BTreeMap::new());
+        health.failure("h", 0, "e");
+        health.failure("h", 1, "e");
+        health.success("h", 2);
+        health.failure("h", 0, "e");
+        assert!(!health.is_drained("h", 2));
+    }
+
+    #[test]
+    fn probe_success_recovers() {
+        let health = Health::new(2, &BTreeMap::new());
+        health.failure("h", 0, "e");
+        health.failure("h", 1, "e");
+        health.failure("h", 0, "e");
+        assert!(health.is_drained("h", 0) && health.is_drained("h", 1));
+        health.recovered("h", 0);
+        assert!(!health.is_drained("h", 0) && !health.is_drained("h", 1));
+    }
+
+    #[test]
+    fn threshold_zero_never_drains() {
+        let health = Health::new(0, &BTreeMap::new());
+        for _ in 0..10 {
+            assert!(!health.failure("h", 0, "e"));
+        }
+        assert!(!health.is_drained("h", 0));
+    }
+}
+
+#[cfg(test)]
+mod executor_tests {
+    use super::*;
+
+    fn farm() -> Farm {
+        Farm {
+            executors: Mutex::default(),
+            running: Mutex::default(),
+            obsolete: Mutex::default(),
+            health: Health::new(0, &BTreeMap::new()),
+            probe_interval: Mutex::new(Duration::from_secs(1)),
+        }
+    }
+
+    fn hosts(hosts: &[(&str, u32, bool)]) -> BTreeMap<String, ExecutorHost> {
+        hosts
+            .iter()
+            .map(|&(host, slots, local)| (host.to_string(), ExecutorHost { slots, local }))
+            .collect()
+    }
+
+    fn started(added: Vec<(String, ExecutorHost, usize)>) -> Vec<(String, bool, usize)> {
+        added.into_iter().map(|(host, ex, slot)| (host, ex.local, slot)).collect()
+    }
+
+    #[test]
+    fn changed_host_entry_restarts_its_slots() {
+        let farm = farm();
+        assert_eq!(started(farm.sync_slots(&hosts(&[("a", 2, false), ("b", 1, false)]))), [
+            ("a".to_string(), false, 0),
+            ("a".to_string(), false, 1),
+            ("b".to_string(), false, 0),
+        ]);
+        assert!(farm.sync_slots(&hosts(&[("a", 2, false), ("b", 1, false)])).is_empty());
+
+        // "a" becomes local: its executors are retired, and nothing new
+        // starts in their slots until they're gone.
+        let changed = hosts(&[("a", 2, true), ("b", 1, false)]);
+        assert!(farm.sync_slots(&changed).is_empty());
+        assert!(!farm.leaving("b:0"));
+        assert!(farm.leaving("a:0"));
+        assert_eq!(started(farm.sync_slots(&changed)), [("a".to_string(), true, 0)]);
+        assert!(farm.leaving("a:1"));
+        assert_eq!(started(farm.sync_slots(&changed)), [("a".to_string(), true, 1)]);
+        assert!(!farm.leaving("a:0"));
+
+        // Fewer slots only retires the ones dropped.
+        assert!(farm.sync_slots(&hosts(&[("a", 1, true), ("b", 1, false)])).is_empty());
+        assert!(!farm.leaving("a:0"));
+        assert!(farm.leaving("a:1"));
+
+        // Dropped and re-added before its executor noticed: kept.
+        assert!(farm.sync_slots(&hosts(&[("a", 1, true)])).is_empty());
+        assert!(farm.sync_slots(&hosts(&[("a", 1, true), ("b", 1, false)])).is_empty());
+        assert!(!farm.leaving("b:0"));
+    }
+}
+
+#[cfg(test)]
+mod process_group_tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn dropping_kills_the_whole_group() {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg("sleep 60 & echo $!; wait");
+        c.stdout(std::process::Stdio::piped());
+        let group = ProcessGroup::new().unwrap().attach(&mut c);
+        let mut child = c.spawn().unwrap();
+        let mut sleep = String::new();
+        std::io::BufReader::new(child.stdout.take().unwrap()).read_line(&mut sleep).unwrap();
+
+        drop(group);
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+        // The background sleep went too: gone, or a zombie until reaped.
+        let stat = format!("/proc/{}/stat", sleep.trim());
+        let dead = || std::fs::read_to_string(&stat).map_or(true, |s| s.contains(") Z "));
+        let start = Instant::now();
+        while !dead() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(dead());
+    }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::collections::VecDeque;
+    use std::os::unix::process::ExitStatusExt;
+
+    const COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+    const TEST: &str = "fs/fake.ktest";
+
+    /// An in-process worker: every command succeeds unless it contains
+    /// `fail`, and each supervisor run (the tty command) reaches only
+    /// the subtests its entry in `runs` gives a verdict — the rest are
+    /// left IN PROGRESS, as if the VM died first.
+    #[derive(Clone)]
+    struct Fake {
+        state: Arc<Mutex<FakeState>>,
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        runs: VecDeque<BTreeMap<String, TestStatus>>,
+        reached: BTreeMap<String, TestStatus>,
+        commands: Vec<String>,
+        fail: Option<&'static str>,
+    }
+
+    impl Fake {
+        fn new(runs: &[&[(&str, TestStatus)]], fail: Option<&'static str>) -> Fake {
+            let runs = runs
+                .iter()
+                .map(|r| r.iter().map(|(st, s)| (key(st), *s)).collect())
+                .collect();
+            Fake { state: Arc::new(Mutex::new(FakeState { runs, fail, ..Default::default() })) }
+        }
+
+        fn commands(&self) -> Vec<String> {
+            self.state.lock().unwrap().commands.clone()
+        }
+    }
+
+    impl Transport for Fake {
+        fn host(&self) -> &str {
+            "fake"
+        }
+
+        async fn run(
+            &self,
+            _handle: &ExecutorHandle<JobParams>,
+            remote: &str,
+            tty: bool,
+        ) -> std::io::Result<ExitStatus> {
+            let mut state = self.state.lock().unwrap();
+            state.commands.push(remote.to_string());
+            if state.fail.is_some_and(|f| remote.contains(f)) {
+                return Ok(ExitStatus::from_raw(1 << 8));
+            }
+            if tty {
+                state.reached = state.runs.pop_front().unwrap_or_default();
+            }
+            Ok(ExitStatus::from_raw(0))
+        }
+
+        async fn pull(
+            &self,
+            _handle: &ExecutorHandle<JobParams>,
+            _ws: &str,
+            dirs: &[String],
+            dst: &Path,
+        ) -> std::io::Result<ExitStatus> {
+            let state = self.state.lock().unwrap();
+            for d in dirs {
+                let status = state.reached.get(d).copied().unwrap_or(TestStatus::Inprogress);
+                std::fs::create_dir_all(dst.join(d))?;
+                // As the supervisor spells it: "PASSED", "IN PROGRESS"...
+                std::fs::write(dst.join(d).join("status"),
+                               format!("{}\n", status.to_str().to_uppercase()))?;
+            }
+            Ok(ExitStatus::from_raw(0))
+        }
+
+        async fn cleanup(&self, _handle: &ExecutorHandle<JobParams>, _ws: &str) {
+            self.state.lock().unwrap().commands.push("cleanup".to_string());
+        }
+    }
+
+    fn key(subtest: &str) -> String {
+        subtest_result_key(TEST, subtest, "", "")
+    }
+
+    /// Run `subtests` as one batch on `fake`; returns each subtest's
+    /// resulting status.
+    fn run_batch(name: &str, subtests: &[&str], fake: &Fake) -> Vec<Option<TestStatus>> {
+        run_batch_with(name, subtests, fake, |_, _, _| ()).0
+    }
+
+    /// run_batch(), with `setup` run on the output dir, results store
+    /// and farm first. Also returns the output dir, for a look at what
+    /// was left on disk.
+    fn run_batch_with(
+        name: &str,
+        subtests: &[&str],
+        fake: &Fake,
+        setup: impl FnOnce(&Path, &TestResultsStore, &Farm),
+    ) -> (Vec<Option<TestStatus>>, TempDir) {
+        let output_dir = TempDir::new(name);
+        let results = Arc::new(TestResultsStore::load(output_dir.to_path_buf(), false));
+        let metrics = Arc::new(Metrics::new(METRICS));
+        let farm = Arc::new(Farm {
+            executors: Mutex::default(),
+            running: Mutex::default(),
+            obsolete: Mutex::default(),
+            health: Health::new(0, &BTreeMap::new()),
+            probe_interval: Mutex::new(Duration::from_secs(1)),
+        });
+        setup(&output_dir, &results, &farm);
+
+        let choir: Choir<JobParams> = Choir::new(output_dir.join("ci-daemon-logs"));
+        // Submitted before the executor exists, so it claims them as
+        // one batch.
+        for st in subtests {
+            let params = JobParams {
+                user: "user".to_string(),
+                repo: "repo".to_string(),
+                commit: COMMIT.to_string(),
+                kernel: String::new(),
+                env: String::new(),
+                test: TEST.to_string(),
+                subtest: st.to_string(),
+                repo_url: "git://fake/repo".to_string(),
+                ktest_url: String::new(),
+                output_dir: output_dir.to_path_buf(),
+                slow_factor: SLOW_FACTOR,
+                p90: Some(5),
+                timeout: BatchTimeout { factor: 3.0, min: 300, max: 7200 },
+            };
+            choir.submit(JobSpec::new(st.to_string(), params)
+                .batch_key(TEST.to_string())
+                .cost(1.0)
+                .group("user".to_string()));
+        }
+        add_executor(&choir, fake.clone(), 0, &results, &metrics, &farm, 600.0);
+        choir.join_all();
+        drop(choir);
+
+        let statuses = subtests.iter().map(|st| results.lookup(COMMIT, &key(st))).collect();
+        (statuses, output_dir)
+    }
+
+    /// Give `subtest` an earlier verdict, on disk and in the store.
+    fn earlier_verdict(dir: &Path, results: &TestResultsStore, subtest: &str, status: TestStatus) {
+        let d = dir.join(COMMIT).join(key(subtest));
+        std::fs::create_dir_all(&d).unwrap();
+        std::fs::write(d.join("status"), format!("{}\n", status.to_str().to_uppercase())).unwrap();
+        results.update_one(COMMIT, key(subtest), read_test_result(&d).unwrap());
+    }
+
+    fn supervisor_runs(fake: &Fake) -> usize {
+        fake.commands().iter().filter(|c| c.contains("lib/supervisor -T")).count()
+    }
+
+    #[test]
+    fn vm_death_resumes_unreached_subtests() {
+        let fake = Fake::new(&[
+            &[("a", TestStatus::Passed)],
+            &[("b", TestStatus::Failed)],
+        ], None);
+        assert_eq!(
+            run_batch("resume", &["a", "b"], &fake),
+            vec![Some(TestStatus::Passed), Some(TestStatus::Failed)]
+        );
+        assert_eq!(supervisor_runs(&fake), 2);
+        // The second VM only gets the subtest the first never reached.
+        let last = fake.commands().into_iter().rfind(|c| c.contains("lib/supervisor -T")).unwrap();
+        assert!(last.contains("fs/fake.ktest b\""), "{}", last);
+    }
+
+    #[test]
+    fn zero_progress_cleans_and_retries_once() {
+        let fake = Fake::new(&[
+            &[],
+            &[("a", TestStatus::Passed), ("b", TestStatus::Passed)],
+        ], None);
+        assert_eq!(
+            run_batch("clean", &["a", "b"], &fake),
+            vec![Some(TestStatus::Passed), Some(TestStatus::Passed)]
+        );
+        assert!(fake.commands().iter().any(|c| c.contains("clean -fdqx")));
+        assert_eq!(supervisor_runs(&fake), 2);
+    }
+
+    #[test]
+    fn zero_progress_twice_is_failed_to_run() {
+        let fake = Fake::new(&[&[], &[]], None);
+        assert_eq!(
+            run_batch("noprogress", &["a"], &fake),
+            vec![Some(TestStatus::FailedToRun)]
+        );
+    }
+
+    #[test]
+    fn failed_step_marks_failed_to_run() {
+        let fake = Fake::new(&[], Some("make -C ~/ktest/lib supervisor"));
+        assert_eq!(
+            run_batch("failedstep", &["a", "b"], &fake),
+            vec![Some(TestStatus::FailedToRun), Some(TestStatus::FailedToRun)]
+        );
+        assert_eq!(supervisor_runs(&fake), 0);
+        assert_eq!(fake.commands().last().map(String::as_str), Some("cleanup"));
+    }
+
+    #[test]
+    fn rerun_that_fails_to_run_keeps_the_verdict() {
+        let fake = Fake::new(&[], Some("make -C ~/ktest/lib supervisor"));
+        let (statuses, dir) = run_batch_with("rerunfailed", &["a"], &fake, |dir, results, _| {
+            earlier_verdict(dir, results, "a", TestStatus::Failed)
+        });
+        assert_eq!(statuses, vec![Some(TestStatus::Failed)]);
+        let r = read_test_result(&dir.join(COMMIT).join(key("a"))).unwrap();
+        assert_eq!(r.status, TestStatus::Failed);
+        assert_eq!(r.attempts.iter().map(|a| a.status).collect::<Vec<_>>(),
+                   [TestStatus::FailedToRun]);
+        assert_eq!(r.flaky(), None);
+    }
+
+    #[test]
+    fn abandoned_rerun_keeps_earlier_attempts() {
+        let fake = Fake::new(&[], None);
+        let (statuses, dir) = run_batch_with("abandoned", &["a", "b"], &fake, |dir, results, farm| {
+            // a: failed, then passed a re-run; b: never ran before.
+            let d = dir.join(COMMIT).join(key("a"));
+            std::fs::create_dir_all(&d).unwrap();
+            std::fs::write(d.join("status"), "FAILED\n").unwrap();
+            archive_test_attempt(&d).unwrap();
+            earlier_verdict(dir, results, "a", TestStatus::Passed);
+            farm.obsolete.lock().unwrap().insert(COMMIT.to_string());
+        });
+        assert_eq!(statuses, vec![Some(TestStatus::Passed), None]);
+        let r = read_test_result(&dir.join(COMMIT).join(key("a"))).unwrap();
+        assert_eq!(r.status, TestStatus::Passed);
+        assert_eq!(r.attempts.iter().map(|a| a.status).collect::<Vec<_>>(),
+                   [TestStatus::Failed]);
+        assert!(!dir.join(COMMIT).join(key("b")).exists());
+        assert_eq!(supervisor_runs(&fake), 0);
+    }
+}
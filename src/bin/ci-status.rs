@@ -1,10 +1,12 @@
 use ci_cgi::{
-    api, branch_entries, branch_get_results, commitdir_get_results_full,
-    format_duration, ktestrc_read, Ktestrc, BranchEntry,
-    TestStatus,
+    api, branch_entries, check, branch_get_results, branch_regressions, branch_test_history,
+    commitdir_get_results_full, compare_commits, decompress_brotli, first_bad_commit, format_duration, ktestrc_read, resolve_commit_prefix,
+    results_matrix, short_commit, Ktestrc, BranchEntry, CommitResults, Quarantine, TestStatus,
+    COMPARE_DURATION_THRESHOLD,
 };
+use ci_cgi::junit::commit_junit;
+use ci_cgi::known_issues::{self, KnownIssue, KnownIssues};
 use clap::{Parser, Subcommand};
-use std::io::Read;
 
 #[derive(Parser)]
 #[command(about = "CLI interface for bcachefs CI test results")]
@@ -30,8 +32,9 @@ struct Args {
     #[arg(long, global = true, default_value = "https://evilpiepirate.org/~testdashboard/ci")]
     dashboard: String,
 
-    /// Branch context for `show` in --user mode (the server resolves
-    /// commits against the branch's repo)
+    /// Branch context for `show`: in --user mode the server resolves
+    /// commits against the branch's repo; locally it picks the repo
+    /// walked for first-bad-commit annotations
     #[arg(long, short, global = true)]
     branch: Option<String>,
 
@@ -42,7 +45,7 @@ struct Args {
 
 // ---- server mode: the dashboard's format=json API (ci_cgi::api) ------------
 
-fn server_get<T: serde::de::DeserializeOwned>(url: &str) -> anyhow::Result<T> {
+fn server_fetch(url: &str) -> anyhow::Result<Vec<u8>> {
     let resp = reqwest::blocking::get(url)?;
     let status = resp.status();
     let body = resp.bytes()?;
@@ -52,6 +55,11 @@ fn server_get<T: serde::de::DeserializeOwned>(url: &str) -> anyhow::Result<T> {
         }
         anyhow::bail!("{} fetching {}", status, url);
     }
+    Ok(body.to_vec())
+}
+
+fn server_get<T: serde::de::DeserializeOwned>(url: &str) -> anyhow::Result<T> {
+    let body = server_fetch(url)?;
     if body.starts_with(b"<") {
         anyhow::bail!(
             "server returned HTML, not JSON — dashboard at {} predates format=json?",
@@ -68,6 +76,40 @@ fn server_log(dashboard: &str, user: &str, branch: &str) -> anyhow::Result<Vec<B
     ))
 }
 
+fn server_regressions(dashboard: &str, user: &str, branch: &str) -> anyhow::Result<api::Regressions> {
+    server_get(&format!(
+        "{}?user={}&branch={}&view=regressions&format=json",
+        dashboard, user, branch
+    ))
+}
+
+fn server_history(
+    dashboard: &str,
+    user: &str,
+    branch: &str,
+    test: &str,
+) -> anyhow::Result<api::TestHistory> {
+    // Result keys carry `=` and `,` (env); the cgi undoes %XX.
+    let test: String = test
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    server_get(&format!(
+        "{}?user={}&branch={}&view=history&test={}&format=json",
+        dashboard, user, branch, test
+    ))
+}
+
+fn server_compare(dashboard: &str, a: &str, b: &str, threshold: f64) -> anyhow::Result<api::Comparison> {
+    server_get(&format!(
+        "{}?compare={}..{}&threshold={}&format=json",
+        dashboard, a, b, threshold
+    ))
+}
+
 fn server_show(
     dashboard: &str,
     user: &str,
@@ -80,6 +122,26 @@ fn server_show(
     ))
 }
 
+fn server_matrix(
+    dashboard: &str,
+    user: &str,
+    branch: &str,
+    commit: &str,
+) -> anyhow::Result<api::Matrix> {
+    server_get(&format!(
+        "{}?user={}&branch={}&commit={}&view=matrix&format=json",
+        dashboard, user, branch, commit
+    ))
+}
+
+fn server_junit(dashboard: &str, user: &str, branch: &str, commit: &str) -> anyhow::Result<String> {
+    let body = server_fetch(&format!(
+        "{}?user={}&branch={}&commit={}&format=junit",
+        dashboard, user, branch, commit
+    ))?;
+    Ok(String::from_utf8(body)?)
+}
+
 fn server_branches(dashboard: &str, user: &str) -> anyhow::Result<api::UserBranches> {
     server_get(&format!("{}?user={}&format=json", dashboard, user))
 }
@@ -96,6 +158,42 @@ enum Command {
     Show {
         /// Commit hash (prefix ok)
         commit: String,
+        /// Pivot: a row per test/subtest, a column per kernel/env variant
+        #[arg(long)]
+        matrix: bool,
+        /// JUnit XML, failures carrying the tail of their log
+        #[arg(long, conflicts_with = "matrix")]
+        junit: bool,
+    },
+    /// Results at a commit flagged slow: passes that took several times
+    /// their typical duration
+    Slow {
+        /// Commit hash (prefix ok)
+        commit: String,
+    },
+    /// Tests failing at the branch tip that passed at an older commit
+    Regressions {
+        /// Git ref, as for `log`
+        branch: String,
+    },
+    /// One test's results down a branch: pass/fail strip, pass rate,
+    /// mean duration
+    History {
+        /// Git ref, as for `log`
+        branch: String,
+        /// Result key, as `show` lists it
+        test: String,
+    },
+    /// What changed between two tested commits, on any branch or repo:
+    /// newly failing/passing, missing, and duration changes
+    Diff {
+        /// Commit hash (prefix ok)
+        a: String,
+        /// Commit hash (prefix ok)
+        b: String,
+        /// Report a duration change past this fraction of the longer run
+        #[arg(long, default_value_t = COMPARE_DURATION_THRESHOLD)]
+        threshold: f64,
     },
     /// List branches from CI user config
     Branches,
@@ -112,8 +210,39 @@ enum Command {
     },
     /// Fetch CI user config from server
     PullConfig,
-    /// Push CI user config to server
+    /// Push CI user config to server (refused if check-config fails)
     PushConfig,
+    /// Validate the CI user config and ktest-ci.json5: tests exist and
+    /// list subtests, repos resolve, env/fetch/kernels are well-formed
+    CheckConfig,
+    /// Edit the known-issues list in output_dir: failures matching an
+    /// entry are annotated with its bug link
+    KnownIssues {
+        #[command(subcommand)]
+        action: KnownIssuesAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum KnownIssuesAction {
+    /// List entries, numbered for `rm`
+    List,
+    /// Add an entry
+    Add {
+        /// Glob over result keys (`/` accepted for `.`)
+        tests: String,
+        /// Regex the failure signature or log must match
+        log: String,
+        /// Bug report URL
+        bug: String,
+        /// Short description
+        #[arg(long, default_value = "")]
+        note: String,
+    },
+    /// Remove an entry by its `list` number
+    Rm {
+        index: usize,
+    },
 }
 
 // ANSI color helpers
@@ -166,30 +295,6 @@ fn open_branch_repo(ktest: &Ktestrc, branch: &str) -> anyhow::Result<git2::Repos
     Ok(git2::Repository::open(repo_path)?)
 }
 
-/// Expand a short commit prefix to a full hash by matching against the
-/// result dirs in output_dir — the authoritative set of tested commits,
-/// and the same place commitdir_get_results_full reads from. Avoids
-/// guessing which git repo a bare commit hash belongs to.
-fn resolve_commit_prefix(ktest: &Ktestrc, prefix: &str) -> anyhow::Result<String> {
-    if prefix.len() >= 40 {
-        return Ok(prefix.to_string());
-    }
-
-    let mut matches: Vec<String> = std::fs::read_dir(&ktest.output_dir)?
-        .filter_map(|d| d.ok())
-        .filter_map(|d| d.file_name().into_string().ok())
-        .filter(|name| name.len() == 40 && name.starts_with(prefix))
-        .collect();
-    matches.sort();
-    matches.dedup();
-
-    match matches.len() {
-        0 => anyhow::bail!("no test results for commit {}", prefix),
-        1 => Ok(matches.pop().unwrap()),
-        n => anyhow::bail!("ambiguous commit prefix {} ({} matches)", prefix, n),
-    }
-}
-
 fn cmd_log(
     branch: &str,
     ktest: &Ktestrc,
@@ -206,10 +311,30 @@ fn cmd_log(
     let results = branch_get_results(&repo, ktest, None, None, Some(&gitref), &all)
         .map_err(|e| anyhow::anyhow!(e))?;
 
-    let entries = branch_entries(results);
+    let known = load_known_issues(ktest);
+    let entries = branch_entries(results, &branch_quarantine(ktest, branch), Some(&known));
     render_log(&entries, branch, json)
 }
 
+/// The known-issues list, or none if it's broken: a bad entry shouldn't
+/// keep results from being shown, but does get a warning.
+fn load_known_issues(ktest: &Ktestrc) -> KnownIssues {
+    KnownIssues::load(&ktest.output_dir).unwrap_or_else(|e| {
+        eprintln!("ci-status: warning: ignoring known issues: {:#}", e);
+        KnownIssues::default()
+    })
+}
+
+/// The branch's quarantine patterns, from the local copy of the user
+/// config; none if there isn't one.
+fn branch_quarantine(ktest: &Ktestrc, branch: &str) -> Quarantine {
+    std::fs::read_to_string(user_config_path(ktest))
+        .ok()
+        .and_then(|config| ci_cgi::users::userrc_read_str(&config).ok())
+        .map(|userrc| userrc.branch_quarantine(branch))
+        .unwrap_or_default()
+}
+
 fn render_log(entries: &[BranchEntry], branch: &str, json: bool) -> anyhow::Result<()> {
     if json {
         println!("{}", serde_json::to_string_pretty(entries)?);
@@ -222,23 +347,25 @@ fn render_log(entries: &[BranchEntry], branch: &str, json: bool) -> anyhow::Resu
     }
 
     // Header
-    println!("{:<14} {:>6} {:>6} {:>6} {:>6} {:>8}  {}",
-        "COMMIT", "PASS", "FAIL", "FTRUN", "INPRO", "DURATION", "MESSAGE");
-    println!("{}", "-".repeat(80));
+    println!("{:<14} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>8}  {}",
+        "COMMIT", "PASS", "FAIL", "KNOWN", "QUAR", "FTRUN", "INPRO", "DURATION", "MESSAGE");
+    println!("{}", "-".repeat(94));
 
     for e in entries {
         let subject = e.message.lines().next().unwrap_or("");
         let subject = if subject.len() > 50 { &subject[..50] } else { subject };
 
-        let commit = if e.commit_id.len() >= 12 { &e.commit_id[..12] } else { &e.commit_id };
+        let commit = short_commit(&e.commit_id);
 
         let pass_s = format!("{}", e.passed);
         let fail_s = format!("{}", e.failed);
 
-        println!("{:<14} {:>6} {:>6} {:>6} {:>6} {:>8}  {}",
+        println!("{:<14} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>8}  {}",
             commit,
             if e.passed > 0 { color_passed(&pass_s) } else { pass_s },
             if e.failed > 0 { color_failed(&fail_s) } else { fail_s },
+            e.known,
+            e.quarantined,
             e.failed_to_run,
             e.inprogress,
             format_duration(e.duration),
@@ -249,28 +376,337 @@ fn render_log(entries: &[BranchEntry], branch: &str, json: bool) -> anyhow::Resu
     Ok(())
 }
 
+fn cmd_slow(commit: &str, ktest: &Ktestrc, json: bool) -> anyhow::Result<()> {
+    let commit = resolve_commit_prefix(ktest, commit)?;
+    let full = commitdir_get_results_full(ktest, &commit)?;
+
+    let slow: Vec<_> = full
+        .tests
+        .iter()
+        .filter(|(_, r)| r.slow.is_some())
+        .map(|(name, r)| api::TestEntry {
+            name: name.clone(),
+            status: r.status.to_str().to_string(),
+            duration: r.duration,
+            first_bad: None,
+            reruns: None,
+            failed_cases: Vec::new(),
+            signature: None,
+            known_issue: None,
+            slow_mean: r.slow,
+        })
+        .collect();
+    render_slow(&commit, &slow, json)
+}
+
+fn render_slow(commit: &str, slow: &[api::TestEntry], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(slow)?);
+        return Ok(());
+    }
+
+    if slow.is_empty() {
+        println!("No slow results for {}", short_commit(commit));
+        return Ok(());
+    }
+
+    let factor = |t: &api::TestEntry| t.duration as f64 / t.slow_mean.unwrap_or(1).max(1) as f64;
+    let mut slow: Vec<_> = slow.iter().collect();
+    slow.sort_by(|a, b| factor(b).total_cmp(&factor(a)));
+
+    println!("{:<60} {:>8} {:>8} {:>6}", "TEST", "DURATION", "USUALLY", "FACTOR");
+    println!("{}", "-".repeat(85));
+    for t in slow {
+        println!("{:<60} {:>8} {:>8} {:>5.1}x",
+            t.name,
+            color_inprog(&format_duration(t.duration)),
+            format_duration(t.slow_mean.unwrap_or(0)),
+            factor(t),
+        );
+    }
+    Ok(())
+}
+
+fn cmd_regressions(branch: &str, ktest: &Ktestrc, json: bool) -> anyhow::Result<()> {
+    unsafe {
+        git2::opts::set_verify_owner_validation(false)
+            .expect("set_verify_owner_validation should never fail");
+    }
+
+    let repo = open_branch_repo(ktest, branch)?;
+    let gitref = resolve_branch(&repo, ktest, branch)?;
+    let all = regex::Regex::new("").unwrap();
+    let results = branch_get_results(&repo, ktest, None, None, Some(&gitref), &all)
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    render_regressions(&branch_regressions(&results), branch, json)
+}
+
+fn render_regressions(r: &api::Regressions, branch: &str, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(r)?);
+        return Ok(());
+    }
+
+    if r.commit.is_empty() {
+        println!("No results for {}", branch);
+        return Ok(());
+    }
+    if r.regressions.is_empty() {
+        println!("No regressions at {}", short_commit(&r.commit));
+        return Ok(());
+    }
+
+    println!("{:<60} {:<12} {:>5}  {}", "TEST", "LAST PASSED", "AGO", "FIRST BAD");
+    println!("{}", "-".repeat(96));
+
+    for t in &r.regressions {
+        println!("{:<60} {:<12} {:>5}  {}",
+            t.name,
+            short_commit(&t.last_passed),
+            t.age,
+            t.first_bad.as_deref().map(short_commit).unwrap_or(""),
+        );
+    }
+
+    println!();
+    println!("{} regressions at {}", r.regressions.len(), short_commit(&r.commit));
+
+    Ok(())
+}
+
+fn cmd_history(branch: &str, test: &str, ktest: &Ktestrc, json: bool) -> anyhow::Result<()> {
+    unsafe {
+        git2::opts::set_verify_owner_validation(false)
+            .expect("set_verify_owner_validation should never fail");
+    }
+
+    let repo = open_branch_repo(ktest, branch)?;
+    let gitref = resolve_branch(&repo, ktest, branch)?;
+    let h = branch_test_history(&repo, ktest, None, None, Some(&gitref), test)
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    render_history(&h, branch, json)
+}
+
+fn render_history(h: &api::TestHistory, branch: &str, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(h)?);
+        return Ok(());
+    }
+
+    let stats = h.stats();
+    if h.commits.iter().all(|c| c.result.is_none()) {
+        println!("No results for {} on {}", h.test, branch);
+        return Ok(());
+    }
+
+    // Oldest on the left, so it reads like a timeline.
+    let strip: String = h.commits
+        .iter()
+        .rev()
+        .map(|c| match c.result.as_ref().map(|r| TestStatus::from_str(&r.status)) {
+            Some(TestStatus::Passed) => color_passed("+"),
+            Some(TestStatus::Failed) => color_failed("x"),
+            Some(TestStatus::Inprogress) => color_inprog("~"),
+            Some(_) => "?".to_string(),
+            None => color_dim("."),
+        })
+        .collect();
+
+    println!("{} on {}", h.test, branch);
+    println!("{}  (oldest → newest, {} commits)", strip, h.commits.len());
+    if stats.completed == 0 {
+        println!("no completed runs");
+    } else {
+        println!("{}/{} passed ({:.1}%), mean duration {}",
+            stats.passed,
+            stats.completed,
+            stats.passed as f64 * 100.0 / stats.completed as f64,
+            format_duration(stats.mean_duration),
+        );
+    }
+    if let Some(c) = h.commits.iter().find(|c| {
+        c.result.as_ref().is_some_and(|r| TestStatus::from_str(&r.status) == TestStatus::Failed)
+    }) {
+        println!("last failed at {} {}", short_commit(&c.commit), c.subject);
+    }
+
+    Ok(())
+}
+
+fn render_matrix(m: &api::Matrix, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(m)?);
+        return Ok(());
+    }
+
+    if m.rows.is_empty() {
+        println!("No test results for {}", m.commit);
+        return Ok(());
+    }
+
+    // Column labels are long (kernel + env): number them, with a legend.
+    for (i, c) in m.columns.iter().enumerate() {
+        println!("[{}] {}", i + 1, c.label());
+    }
+    println!();
+
+    let width = m.rows
+        .iter()
+        .map(|r| r.test.len() + r.subtest.len() + 1)
+        .max()
+        .unwrap_or(0)
+        .min(70);
+    let header: String = (1..=m.columns.len()).map(|i| format!(" {:>6}", format!("[{}]", i))).collect();
+    println!("{:<width$}{}", "TEST", header, width = width);
+    println!("{}", "-".repeat(width + 7 * m.columns.len()));
+
+    for r in &m.rows {
+        let cells: String = r.cells
+            .iter()
+            .map(|c| match c {
+                // pad before colouring: the escapes would count as width
+                Some(c) => {
+                    let status = TestStatus::from_str(&c.status);
+                    let short = match status {
+                        TestStatus::Passed => "pass",
+                        TestStatus::Failed => "FAIL",
+                        TestStatus::Inprogress => "run",
+                        TestStatus::FailedToRun => "ftrun",
+                        TestStatus::Notrun => "notrun",
+                        TestStatus::Unknown => "?",
+                    };
+                    let s = format!(" {:>6}", short);
+                    match status {
+                        TestStatus::Passed => color_passed(&s),
+                        TestStatus::Failed => color_failed(&s),
+                        TestStatus::Inprogress => color_inprog(&s),
+                        _ => color_dim(&s),
+                    }
+                }
+                None => color_dim(&format!(" {:>6}", "-")),
+            })
+            .collect();
+        println!("{:<width$}{}", format!("{}.{}", r.test, r.subtest), cells, width = width);
+    }
+
+    Ok(())
+}
+
+fn render_diff(c: &api::Comparison, json: bool) -> anyhow::Result<()> {
+    use api::ChangeKind;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(c)?);
+        return Ok(());
+    }
+
+    println!("{} → {}", short_commit(&c.a), short_commit(&c.b));
+    if c.groups.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
+
+    let side = |s: &Option<api::CompareSide>| match s {
+        Some(s) => format!("{} {}", color_status(TestStatus::from_str(&s.status)),
+                           format_duration(s.duration)),
+        None => color_dim("-"),
+    };
+
+    for g in &c.groups {
+        println!();
+        let quals: Vec<&str> = [g.kernel.as_str(), g.env.as_str()]
+            .into_iter()
+            .filter(|q| !q.is_empty())
+            .collect();
+        if quals.is_empty() {
+            println!("{}", g.test);
+        } else {
+            println!("{} @ {}", g.test, quals.join(" "));
+        }
+        for ch in &g.changes {
+            let kind = match ch.kind {
+                ChangeKind::NewlyFailing => color_failed(ch.kind.describe()),
+                ChangeKind::NewlyPassing => color_passed(ch.kind.describe()),
+                _ => ch.kind.describe().to_string(),
+            };
+            println!("  {:<50} {:<14} {} → {}", ch.subtest, kind, side(&ch.a), side(&ch.b));
+        }
+    }
+
+    let counts: Vec<String> = [
+        ChangeKind::NewlyFailing,
+        ChangeKind::NewlyPassing,
+        ChangeKind::Changed,
+        ChangeKind::Missing,
+        ChangeKind::Added,
+        ChangeKind::Slower,
+        ChangeKind::Faster,
+    ]
+    .into_iter()
+    .filter_map(|k| match c.count(k) {
+        0 => None,
+        n => Some(format!("{} {}", n, k.describe())),
+    })
+    .collect();
+    println!();
+    println!("{}", counts.join(", "));
+
+    Ok(())
+}
+
+/// The commit's history as the branch walk sees it (newest-first,
+/// starting at `commit`), for first_bad_commit(). Best-effort: a commit
+/// the repo doesn't know just gets no first-bad annotations.
+fn commit_history(ktest: &Ktestrc, branch: Option<&str>, commit: &str) -> Vec<CommitResults> {
+    unsafe {
+        git2::opts::set_verify_owner_validation(false)
+            .expect("set_verify_owner_validation should never fail");
+    }
+
+    let Ok(repo) = open_branch_repo(ktest, branch.unwrap_or("")) else {
+        return Vec::new();
+    };
+    let all = regex::Regex::new("").unwrap();
+    branch_get_results(&repo, ktest, None, None, Some(commit), &all).unwrap_or_default()
+}
+
 fn cmd_show(
     commit: &str,
+    branch: Option<&str>,
     ktest: &Ktestrc,
     json: bool,
 ) -> anyhow::Result<()> {
     let commit = resolve_commit_prefix(ktest, commit)?;
 
     let full = commitdir_get_results_full(ktest, &commit)?;
-
-    let detail = api::CommitTests {
-        commit,
-        message: full.message.clone(),
-        tests: full
-            .tests
-            .iter()
-            .map(|(name, r)| api::TestEntry {
-                name: name.clone(),
-                status: r.status.to_str().to_string(),
-                duration: r.duration,
-            })
-            .collect(),
+    let history = if full.tests.values().any(|r| r.status == TestStatus::Failed) {
+        commit_history(ktest, branch, &commit)
+    } else {
+        Vec::new()
     };
+
+    let known = load_known_issues(ktest);
+    let tests = full
+        .tests
+        .iter()
+        .map(|(name, r)| api::TestEntry {
+            name: name.clone(),
+            status: r.status.to_str().to_string(),
+            duration: r.duration,
+            first_bad: first_bad_commit(&history, name).map(String::from),
+            reruns: api::Reruns::from_result(r),
+            failed_cases: r.failed_cases().into_iter().map(String::from).collect(),
+            signature: r.signature.clone(),
+            known_issue: known
+                .matching(name, r, || fetch_log(ktest, &commit, name, false).ok())
+                .cloned(),
+            slow_mean: r.slow,
+        })
+        .collect();
+
+    let detail = api::CommitTests { commit, message: full.message.clone(), tests };
     render_show(&detail, json)
 }
 
@@ -302,27 +738,56 @@ fn render_show(detail: &api::CommitTests, json: bool) -> anyhow::Result<()> {
         TestStatus::Passed     => 5,
     });
 
-    println!("{:<60} {:>12} {:>8}", "TEST", "STATUS", "DURATION");
-    println!("{}", "-".repeat(82));
+    println!("{:<60} {:>12} {:>8}  {}", "TEST", "STATUS", "DURATION", "NOTES");
+    println!("{}", "-".repeat(96));
 
     for t in &tests {
-        println!("{:<60} {:>12} {:>8}",
+        let mut notes = Vec::new();
+        if let Some(r) = &t.reruns {
+            let d = r.describe();
+            if r.is_flaky() {
+                notes.push(color_inprog(&d));
+            } else if !d.is_empty() {
+                notes.push(d);
+            }
+        }
+        if let Some(c) = &t.first_bad {
+            notes.push(format!("first bad {}", short_commit(c)));
+        }
+        if !t.failed_cases.is_empty() {
+            notes.push(format!("failed: {}", t.failed_cases.join(" ")));
+        }
+        println!("{:<60} {:>12} {:>8}  {}",
             t.name,
             color_status(TestStatus::from_str(&t.status)),
             format_duration(t.duration),
+            notes.join(", "),
         );
+        if let Some(sig) = &t.signature {
+            println!("    {}", sig);
+        }
+        if let Some(issue) = &t.known_issue {
+            println!("    known issue: {} {}", issue.bug, issue.note);
+        }
+        if let Some(mean) = t.slow_mean {
+            println!("    slow: usually {}", format_duration(mean));
+        }
     }
 
     fn count(tests: &[api::TestEntry], s: TestStatus) -> usize {
         tests.iter().filter(|t| TestStatus::from_str(&t.status) == s).count()
     }
     let total_duration: u64 = detail.tests.iter().map(|t| t.duration).sum();
+    let flaky = detail.tests.iter()
+        .filter(|t| t.reruns.as_ref().is_some_and(|r| r.is_flaky()))
+        .count();
 
     println!();
-    println!("{} total: {} passed, {} failed, {} in progress, {}",
+    println!("{} total: {} passed, {} failed, {} flaky, {} in progress, {}",
         detail.tests.len(),
         color_passed(&count(&detail.tests, TestStatus::Passed).to_string()),
         color_failed(&count(&detail.tests, TestStatus::Failed).to_string()),
+        flaky,
         count(&detail.tests, TestStatus::Inprogress),
         format_duration(total_duration),
     );
@@ -358,12 +823,42 @@ fn cmd_pull_config(ktest: &Ktestrc) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Everything check-config finds wrong with the local user config,
+/// one line per problem.
+fn config_problems(ktest: &Ktestrc) -> anyhow::Result<Vec<String>> {
+    let path = user_config_path(ktest);
+    let config = std::fs::read_to_string(&path)
+        .map_err(|_| anyhow::anyhow!("no local config at {} — run pull-config first", path.display()))?;
+    let mut problems = check::check_ktestrc(ktest);
+    match ci_cgi::users::userrc_read_str(&config) {
+        Ok(userrc) => problems.extend(check::check_userrc(ktest, &userrc)),
+        Err(e) => problems.push(format!("{}: {:#}", path.display(), e)),
+    }
+    Ok(problems)
+}
+
+fn cmd_check_config(ktest: &Ktestrc) -> anyhow::Result<()> {
+    let problems = config_problems(ktest)?;
+    for p in &problems {
+        println!("{}", p);
+    }
+    if !problems.is_empty() {
+        anyhow::bail!("{} config problems", problems.len());
+    }
+    println!("Config ok");
+    Ok(())
+}
+
 fn cmd_push_config(ktest: &Ktestrc) -> anyhow::Result<()> {
     let remote = ci_scp_path(ktest)?;
     let src = user_config_path(ktest);
 
-    if !src.exists() {
-        anyhow::bail!("no local config at {} — run pull-config first", src.display());
+    let problems = config_problems(ktest)?;
+    if !problems.is_empty() {
+        for p in &problems {
+            eprintln!("{}", p);
+        }
+        anyhow::bail!("not pushing: {} config problems (see check-config)", problems.len());
     }
 
     let status = std::process::Command::new("scp")
@@ -379,6 +874,40 @@ fn cmd_push_config(ktest: &Ktestrc) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn cmd_known_issues(ktest: &Ktestrc, action: KnownIssuesAction, json: bool) -> anyhow::Result<()> {
+    let mut issues = known_issues::read(&ktest.output_dir)?;
+
+    match action {
+        KnownIssuesAction::List => {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&issues)?);
+                return Ok(());
+            }
+            if issues.is_empty() {
+                println!("No known issues");
+            }
+            for (i, issue) in issues.iter().enumerate() {
+                println!("{:>3}. {} /{}/", i, issue.tests, issue.log);
+                println!("     {} {}", issue.bug, issue.note);
+            }
+            return Ok(());
+        }
+        KnownIssuesAction::Add { tests, log, bug, note } => {
+            issues.push(KnownIssue { tests, log, bug, note });
+        }
+        KnownIssuesAction::Rm { index } => {
+            if index >= issues.len() {
+                anyhow::bail!("no known issue {} (there are {})", index, issues.len());
+            }
+            issues.remove(index);
+        }
+    }
+
+    known_issues::write(&ktest.output_dir, &issues)?;
+    println!("{} known issues in {}", issues.len(), ktest.output_dir.join(known_issues::FILE).display());
+    Ok(())
+}
+
 fn cmd_branches(ktest: &Ktestrc, json: bool) -> anyhow::Result<()> {
     let config_path = user_config_path(ktest);
     let config = std::fs::read_to_string(&config_path)
@@ -405,13 +934,6 @@ fn cmd_branches(ktest: &Ktestrc, json: bool) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn decompress_brotli(data: &[u8]) -> anyhow::Result<String> {
-    let mut decoder = brotli::Decompressor::new(data, 4096);
-    let mut output = String::new();
-    decoder.read_to_string(&mut output)?;
-    Ok(output)
-}
-
 fn fetch_log(ktest: &Ktestrc, commit: &str, test: &str, full: bool) -> anyhow::Result<String> {
     let filename = if full { "full_log.br" } else { "log.br" };
 
@@ -446,6 +968,7 @@ fn cmd_logs(
     let commit = resolve_commit_prefix(ktest, commit)?;
 
     let results = commitdir_get_results_full(ktest, &commit)?;
+    let known = load_known_issues(ktest);
 
     match test {
         Some(filter) => {
@@ -455,7 +978,7 @@ fn cmd_logs(
                 .collect();
 
             if matches.is_empty() {
-                anyhow::bail!("no test matching '{}' for commit {}", filter, &commit[..12]);
+                anyhow::bail!("no test matching '{}' for commit {}", filter, short_commit(&commit));
             }
 
             if matches.len() > 1 {
@@ -488,15 +1011,21 @@ fn cmd_logs(
             failed.sort_by_key(|(name, _)| (*name).clone());
 
             if failed.is_empty() {
-                println!("No failed tests for {}", &commit[..12]);
+                println!("No failed tests for {}", short_commit(&commit));
                 return Ok(());
             }
 
-            println!("{} failed tests for {}:\n", failed.len(), &commit[..12]);
+            println!("{} failed tests for {}:\n", failed.len(), short_commit(&commit));
             for (i, (name, r)) in failed.iter().enumerate() {
                 println!("  {:>3}. {} ({}s)", i + 1, name, r.duration);
+                if let Some(sig) = &r.signature {
+                    println!("       {}", sig);
+                }
+                if let Some(issue) = known.matching(name, r, || fetch_log(ktest, &commit, name, false).ok()) {
+                    println!("       known issue: {} {}", issue.bug, issue.note);
+                }
             }
-            println!("\nUse: ci-status logs {} <test-name-or-substring>", &commit[..12]);
+            println!("\nUse: ci-status logs {} <test-name-or-substring>", short_commit(&commit));
         }
     }
 
@@ -524,13 +1053,41 @@ fn run() -> anyhow::Result<()> {
                 let entries = server_log(&args.dashboard, user, branch)?;
                 render_log(&entries, branch, args.json)
             }
-            Command::Show { ref commit } => {
+            Command::Show { ref commit, matrix, junit } => {
                 let branch = args.branch.as_deref().ok_or_else(|| {
                     anyhow::anyhow!("show --user needs --branch (the server resolves commits per-branch)")
                 })?;
+                if junit {
+                    print!("{}", server_junit(&args.dashboard, user, branch, commit)?);
+                    return Ok(());
+                }
+                if matrix {
+                    let m = server_matrix(&args.dashboard, user, branch, commit)?;
+                    return render_matrix(&m, args.json);
+                }
                 let detail = server_show(&args.dashboard, user, branch, commit)?;
                 render_show(&detail, args.json)
             }
+            Command::Slow { ref commit } => {
+                let branch = args.branch.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!("slow --user needs --branch (the server resolves commits per-branch)")
+                })?;
+                let detail = server_show(&args.dashboard, user, branch, commit)?;
+                let slow: Vec<_> = detail.tests.into_iter().filter(|t| t.slow_mean.is_some()).collect();
+                render_slow(&detail.commit, &slow, args.json)
+            }
+            Command::Regressions { ref branch } => {
+                let r = server_regressions(&args.dashboard, user, branch)?;
+                render_regressions(&r, branch, args.json)
+            }
+            Command::History { ref branch, ref test } => {
+                let h = server_history(&args.dashboard, user, branch, test)?;
+                render_history(&h, branch, args.json)
+            }
+            Command::Diff { ref a, ref b, threshold } => {
+                let c = server_compare(&args.dashboard, a, b, threshold)?;
+                render_diff(&c, args.json)
+            }
             Command::Branches => {
                 let b = server_branches(&args.dashboard, user)?;
                 if args.json {
@@ -542,7 +1099,7 @@ fn run() -> anyhow::Result<()> {
                 }
                 Ok(())
             }
-            _ => anyhow::bail!("--user mode supports log, show, and branches"),
+            _ => anyhow::bail!("--user mode supports log, show, slow, regressions, history, diff, and branches"),
         };
     }
 
@@ -557,8 +1114,33 @@ fn run() -> anyhow::Result<()> {
         Command::Log { branch } => {
             cmd_log(&branch, &ktest, args.json)
         }
-        Command::Show { commit } => {
-            cmd_show(&commit, &ktest, args.json)
+        Command::Show { commit, junit: true, .. } => {
+            let commit = resolve_commit_prefix(&ktest, &commit)?;
+            let full = commitdir_get_results_full(&ktest, &commit)?;
+            print!("{}", commit_junit(&commit, &full.tests, |key| {
+                fetch_log(&ktest, &commit, key, false).ok()
+            }));
+            Ok(())
+        }
+        Command::Show { commit, matrix: true, .. } => {
+            let commit = resolve_commit_prefix(&ktest, &commit)?;
+            let full = commitdir_get_results_full(&ktest, &commit)?;
+            render_matrix(&results_matrix(&commit, &full.tests), args.json)
+        }
+        Command::Show { commit, .. } => {
+            cmd_show(&commit, args.branch.as_deref(), &ktest, args.json)
+        }
+        Command::Slow { commit } => {
+            cmd_slow(&commit, &ktest, args.json)
+        }
+        Command::Regressions { branch } => {
+            cmd_regressions(&branch, &ktest, args.json)
+        }
+        Command::History { branch, test } => {
+            cmd_history(&branch, &test, &ktest, args.json)
+        }
+        Command::Diff { a, b, threshold } => {
+            render_diff(&compare_commits(&ktest, &a, &b, threshold)?, args.json)
         }
         Command::Logs { commit, test, full } => {
             cmd_logs(&commit, test.as_deref(), full, &ktest)
@@ -572,5 +1154,11 @@ fn run() -> anyhow::Result<()> {
         Command::PushConfig => {
             cmd_push_config(&ktest)
         }
+        Command::CheckConfig => {
+            cmd_check_config(&ktest)
+        }
+        Command::KnownIssues { action } => {
+            cmd_known_issues(&ktest, action, args.json)
+        }
     }
 }
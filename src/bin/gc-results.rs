@@ -1,4 +1,5 @@
 extern crate libc;
+use ci_cgi::index::ResultsIndex;
 use ci_cgi::{ciconfig_read, git_get_commit, CiConfig};
 use clap::Parser;
 use std::collections::HashSet;
@@ -112,6 +113,12 @@ fn main() {
     let now = std::time::SystemTime::now();
     let older_than = now.checked_sub(std::time::Duration::new(3600, 0)).unwrap();
 
+    let index = rc.ktest.results_index.as_ref().and_then(|path| {
+        ResultsIndex::open(path)
+            .map_err(|e| eprintln!("results index {}: {:#}", path.display(), e))
+            .ok()
+    });
+
     for d in rc
         .ktest
         .output_dir
@@ -126,9 +133,18 @@ fn main() {
 
         if !args.dry_run {
             if d.is_dir() {
-                std::fs::remove_dir_all(d).ok();
+                std::fs::remove_dir_all(&d).ok();
             } else {
-                std::fs::remove_file(d).ok();
+                std::fs::remove_file(&d).ok();
+            }
+
+            // <commit> and <commit>.capnp both go; drop the rows once.
+            if let (Some(index), Some(commit)) =
+                (&index, d.file_name().and_then(|f| f.to_str()).filter(|f| f.len() == 40))
+            {
+                if let Err(e) = index.delete_commit(commit) {
+                    eprintln!("results index: {:#}", e);
+                }
             }
         }
     }
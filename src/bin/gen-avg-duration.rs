@@ -1,73 +1,240 @@
-use ci_cgi::{commitdir_get_results, ktestrc_read, Ktestrc, TestStatus};
-use std::collections::BTreeMap;
+use ci_cgi::index::ResultsIndex;
+use ci_cgi::{
+    commitdir_get_results, cross_kernel_key, ktestrc_read, parse_result_key, DurationSample,
+    Ktestrc, TestStats, TestStatus,
+};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::process;
 
-#[derive(Default, Debug)]
-struct TestDuration {
-    secs: u64,
-    nr: u64,
-    nr_passed: u64,
-    nr_failed: u64,
+type SampleMap = BTreeMap<String, Vec<DurationSample>>;
+
+/// What one <commit>.capnp contributed, and its mtime when it was read.
+struct CommitSamples {
+    mtime: i64,
+    samples: Vec<(String, DurationSample)>,
 }
 
-type TestDurationMap = BTreeMap<String, TestDuration>;
+const CACHE: &str = "test_durations.cache.capnp";
 
-fn read_duration_sums(rc: &Ktestrc) -> TestDurationMap {
-    let mut durations: BTreeMap<String, TestDuration> = BTreeMap::new();
+/// From the results index, when configured and synced: one query
+/// instead of a capnp per commit. An index the daemon hasn't synced
+/// would overwrite the durations with nothing, or with stale stats.
+fn read_samples_indexed(rc: &Ktestrc) -> Option<SampleMap> {
+    let samples = rc.results_index.as_ref().map(|path| {
+        let index = ResultsIndex::open(path)?;
+        if !index.is_synced()? {
+            anyhow::bail!("{} not synced", path.display());
+        }
+        index.samples()
+    })?;
+    match samples {
+        Ok(samples) => Some(samples),
+        Err(e) => {
+            eprintln!("gen-avg-duration: results index: {:#}; scanning {}",
+                      e, rc.output_dir.display());
+            None
+        }
+    }
+}
+
+fn capnp_mtime(rc: &Ktestrc, commit: &str) -> Option<i64> {
+    let m = std::fs::metadata(rc.output_dir.join(format!("{}.capnp", commit))).ok()?;
+    let t = m.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(t.as_nanos() as i64)
+}
+
+/// The cache from the last run; empty if there's none or it can't be
+/// read, which just means rereading everything.
+fn read_cache(rc: &Ktestrc) -> HashMap<String, CommitSamples> {
+    use ci_cgi::durations_capnp::durations_cache;
+
+    let mut cache = HashMap::new();
+    let Ok(buf) = std::fs::read(rc.output_dir.join(CACHE)) else { return cache };
+
+    let options = capnp::message::ReaderOptions {
+        nesting_limit: 64,
+        traversal_limit_in_words: None,
+    };
+    let mut read = || -> anyhow::Result<()> {
+        let message = capnp::serialize::read_message_from_flat_slice(&mut buf.as_slice(), options)?;
+        let root: durations_cache::Reader = message.get_root()?;
+        let keys: Vec<String> = root.get_keys()?
+            .iter()
+            .map(|k| Ok(k?.to_string()?))
+            .collect::<anyhow::Result<_>>()?;
+
+        for c in root.get_commits()? {
+            let samples = c.get_samples()?
+                .iter()
+                .filter_map(|s| {
+                    let key = keys.get(s.get_key() as usize)?;
+                    let status = match (s.get_passed(), s.get_failed()) {
+                        (true, _) => TestStatus::Passed,
+                        (_, true) => TestStatus::Failed,
+                        // Only passed/failed matter past nr
+                        _ => TestStatus::Unknown,
+                    };
+                    Some((key.clone(), DurationSample {
+                        duration: s.get_duration(),
+                        status,
+                        starttime: s.get_starttime(),
+                    }))
+                })
+                .collect();
+            cache.insert(c.get_commit()?.to_string()?, CommitSamples { mtime: c.get_mtime(), samples });
+        }
+        Ok(())
+    };
+    if let Err(e) = read() {
+        eprintln!("gen-avg-duration: reading {}: {:#}; rescanning", CACHE, e);
+        cache.clear();
+    }
+    cache
+}
+
+fn write_cache(rc: &Ktestrc, cache: &HashMap<String, CommitSamples>) -> anyhow::Result<()> {
+    use ci_cgi::durations_capnp::durations_cache;
+
+    // Result keys repeat across nearly every commit: store each once.
+    let mut key_idx: BTreeMap<&str, u32> = BTreeMap::new();
+    for c in cache.values() {
+        for (key, _) in &c.samples {
+            let n = key_idx.len() as u32;
+            key_idx.entry(key).or_insert(n);
+        }
+    }
+
+    let mut message = capnp::message::Builder::new_default();
+    let mut root: durations_cache::Builder = message.init_root();
+    {
+        let mut keys = root.reborrow().init_keys(key_idx.len() as u32);
+        for (key, idx) in &key_idx {
+            keys.set(*idx, *key);
+        }
+    }
+    let mut commits = root.init_commits(cache.len() as u32);
+    for (i, (commit, c)) in cache.iter().enumerate() {
+        let mut out = commits.reborrow().get(i as u32);
+        out.set_commit(commit);
+        out.set_mtime(c.mtime);
+        let mut samples = out.init_samples(c.samples.len() as u32);
+        for (j, (key, s)) in c.samples.iter().enumerate() {
+            let mut out = samples.reborrow().get(j as u32);
+            out.set_key(key_idx[key.as_str()]);
+            out.set_duration(s.duration);
+            out.set_starttime(s.starttime);
+            out.set_passed(s.status == TestStatus::Passed);
+            out.set_failed(s.status == TestStatus::Failed);
+        }
+    }
+
+    let fname = rc.output_dir.join(CACHE);
+    let fname_new = rc.output_dir.join(format!("{}.new", CACHE));
+    let mut out = File::create(&fname_new)?;
+    capnp::serialize::write_message(&mut out, &message)?;
+    drop(out);
+    std::fs::rename(fname_new, fname)?;
+    Ok(())
+}
+
+/// Every commit's results, rereading only the capnps that changed since
+/// the last run.
+fn read_samples(rc: &Ktestrc) -> SampleMap {
+    let mut samples = SampleMap::new();
 
     let entries = match std::fs::read_dir(&rc.output_dir) {
         Ok(d) => d,
         Err(e) => {
             eprintln!("gen-avg-duration: reading {}: {}",
                       rc.output_dir.display(), e);
-            return durations;
+            return samples;
         }
     };
+
+    let mut cache = read_cache(rc);
+    let mut live = HashMap::new();
+    let mut nr_read = 0;
+
     // metadata() can race with concurrent gc / result-dir cleanup — the
     // dir entry was listed but is gone by the time we stat it. Skip
     // anything that's vanished or isn't a dir.
-    for i in entries
+    for commit in entries
         .filter_map(|e| e.ok())
         .filter(|e| e.metadata().map(|m| m.is_dir()).unwrap_or(false))
         .filter_map(|e| e.file_name().into_string().ok())
-        .filter_map(|e| commitdir_get_results(rc, &e).ok())
     {
-        for (test, result) in i {
-            let mut t = durations.get_mut(&test);
+        let Some(mtime) = capnp_mtime(rc, &commit) else { continue };
 
-            if t.is_none() {
-                durations.insert(test.clone(), Default::default());
-                t = durations.get_mut(&test);
+        let c = match cache.remove(&commit) {
+            Some(c) if c.mtime == mtime => c,
+            _ => {
+                let Ok(results) = commitdir_get_results(rc, &commit) else { continue };
+                nr_read += 1;
+                CommitSamples {
+                    mtime,
+                    samples: results.into_iter()
+                        .map(|(key, r)| (key, DurationSample {
+                            duration: r.duration,
+                            status: r.status,
+                            starttime: r.starttime.timestamp(),
+                        }))
+                        .collect(),
+                }
             }
-            let t = t.unwrap();
+        };
+        live.insert(commit, c);
+    }
+
+    // Whatever's left in the cache is for commits that are gone.
+    println!("read {} of {} commits ({} dropped)", nr_read, live.len(), cache.len());
+
+    if let Err(e) = write_cache(rc, &live) {
+        eprintln!("gen-avg-duration: writing {}: {:#}", CACHE, e);
+    }
 
-            t.secs += result.duration;
-            t.nr += 1;
-            t.nr_passed += (result.status == TestStatus::Passed) as u64;
-            t.nr_failed += (result.status == TestStatus::Failed) as u64;
+    for c in live.into_values() {
+        for (key, s) in c.samples {
+            samples.entry(key).or_default().push(s);
         }
     }
+    samples
+}
 
-    durations
+/// Add each per-kernel key's runs to its cross_kernel_key() too, for
+/// lookups that don't care which kernel ran it.
+fn add_cross_kernel(samples: &mut SampleMap) {
+    let mut cross: SampleMap = BTreeMap::new();
+    for (key, s) in samples.iter() {
+        if let Some(k) = parse_result_key(key) {
+            cross.entry(cross_kernel_key(k.test, k.subtest)).or_default().extend(s);
+        }
+    }
+    samples.extend(cross);
 }
 
-fn write_durations_capnp(rc: &Ktestrc, durations_in: TestDurationMap) {
+fn write_durations_capnp(rc: &Ktestrc, samples: SampleMap) {
     use capnp::serialize;
     use ci_cgi::durations_capnp::durations;
 
     let mut message = capnp::message::Builder::new_default();
     let root: durations::Builder = message.init_root();
-    let mut entries = root.init_entries(durations_in.len().try_into().unwrap());
+    let mut entries = root.init_entries(samples.len().try_into().unwrap());
 
-    for (idx, (name, duration_in)) in durations_in.iter().enumerate() {
+    for (idx, (name, samples)) in samples.iter().enumerate() {
         let mut duration_out = entries.reborrow().get(idx.try_into().unwrap());
+        let stats = TestStats::from_samples(samples);
 
         duration_out.set_test(name);
-        duration_out.set_nr(duration_in.nr);
-        duration_out.set_passed(duration_in.nr_passed);
-        duration_out.set_failed(duration_in.nr_failed);
-        duration_out.set_duration(duration_in.secs / duration_in.nr);
+        duration_out.set_nr(stats.nr);
+        duration_out.set_passed(stats.passed);
+        duration_out.set_failed(stats.failed);
+        duration_out.set_duration(stats.duration);
+        duration_out.set_p50(stats.p50);
+        duration_out.set_p90(stats.p90);
+        duration_out.set_max(stats.max);
+        duration_out.set_recent_failure_rate(stats.recent_failure_rate);
+        duration_out.set_last_seen(stats.last_seen);
     }
 
     let fname = rc.output_dir.join("test_durations.capnp");
@@ -81,7 +248,7 @@ fn write_durations_capnp(rc: &Ktestrc, durations_in: TestDurationMap) {
 
     println!(
         "wrote durations for {} tests to {}",
-        durations_in.len(),
+        samples.len(),
         fname.display()
     );
 }
@@ -94,6 +261,32 @@ fn main() {
     }
     let ktestrc = ktestrc.unwrap();
 
-    let durations = read_duration_sums(&ktestrc);
-    write_durations_capnp(&ktestrc, durations);
+    let mut samples = read_samples_indexed(&ktestrc)
+        .unwrap_or_else(|| read_samples(&ktestrc));
+    add_cross_kernel(&mut samples);
+    write_durations_capnp(&ktestrc, samples);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(duration: u64) -> DurationSample {
+        DurationSample { duration, status: TestStatus::Passed, starttime: 0 }
+    }
+
+    #[test]
+    fn cross_kernel_leaves_real_keys_alone() {
+        let mut samples = SampleMap::new();
+        samples.insert("fs.ec.one".to_string(), vec![sample(10)]);
+        samples.insert("fs.ec@upstream.one".to_string(), vec![sample(100)]);
+        samples.insert("fs.ec@upstream@X=1.one".to_string(), vec![sample(200)]);
+
+        add_cross_kernel(&mut samples);
+
+        // The unqualified key is the default kernel's own result key.
+        assert_eq!(TestStats::from_samples(&samples["fs.ec.one"]).max, 10);
+        assert_eq!(samples["fs.ec@upstream.one"].len(), 1);
+        assert_eq!(samples[&cross_kernel_key("fs.ec", "one")].len(), 2);
+    }
 }
@@ -1,5 +1,7 @@
 use anyhow::{anyhow, Context, Result};
+use ci_cgi::index::ResultsIndex;
 use ci_cgi::{ciconfig_read, commit_update_results};
+use std::collections::HashMap;
 use clap::Parser;
 use glob::Pattern;
 use std::collections::HashSet;
@@ -172,16 +174,19 @@ fn main() -> Result<()> {
         results.len()
     );
 
-    let mut affected_commits: HashSet<String> = HashSet::new();
+    let mut affected_commits: HashMap<String, Vec<String>> = HashMap::new();
 
     for path in &results {
         println!("  {}", path.display());
         if !args.dry_run {
-            // Track affected commit (parent directory name)
-            if let Some(commit_dir) = path.parent() {
-                if let Some(commit) = commit_dir.file_name() {
-                    affected_commits.insert(commit.to_string_lossy().into_owned());
-                }
+            // Track affected commit (parent directory name) and key
+            if let (Some(commit), Some(key)) =
+                (path.parent().and_then(|d| d.file_name()), path.file_name())
+            {
+                affected_commits
+                    .entry(commit.to_string_lossy().into_owned())
+                    .or_default()
+                    .push(key.to_string_lossy().into_owned());
             }
             if let Err(e) = remove_path(path) {
                 eprintln!("  error: {}", e);
@@ -194,9 +199,16 @@ fn main() -> Result<()> {
     } else {
         // Update capnp summaries for affected commits
         eprintln!("Updating {} commit summaries...", affected_commits.len());
-        for commit in &affected_commits {
+        for commit in affected_commits.keys() {
             commit_update_results(&output_dir, commit);
         }
+        if let Some(path) = rc.as_ref().and_then(|r| r.ktest.results_index.as_ref()) {
+            let index = ResultsIndex::open(path)
+                .with_context(|| format!("opening results index {}", path.display()))?;
+            for (commit, keys) in &affected_commits {
+                index.delete(commit, keys)?;
+            }
+        }
         eprintln!(
             "Deleted {} results; the CI daemon will re-run them.",
             results.len()
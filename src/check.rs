@@ -0,0 +1,179 @@
+//! Whole-matrix config validation: everything build_test_specs() would
+//! otherwise skip past with an eprintln — a test that doesn't exist, a
+//! repo name with no path, an env that can't be encoded, a fetch line
+//! or kernel id that can't work. Checked up front (ci-status
+//! check-config, push-config, ci-daemon --check-config and at daemon
+//! startup), so a typo is an error instead of tests that never run.
+
+use crate::jobs::get_subtests;
+use crate::users::fetch_args;
+use crate::{encode_env, CiConfig, Ktestrc, Userrc};
+
+/// A kernel-store id: `<distro>/<release>[/<arch>]`, as ktest's
+/// resolve_kernel_name() takes it (e.g. "upstream/stable-kasan").
+pub fn kernel_id_valid(kernel: &str) -> bool {
+    let parts: Vec<&str> = kernel.split('/').collect();
+    (2..=3).contains(&parts.len())
+        && parts.iter().all(|p| {
+            !p.is_empty()
+                && !p.starts_with('.')
+                && p.chars().all(|c| c.is_ascii_alphanumeric() || "._+-".contains(c))
+        })
+}
+
+/// Problems with ktest-ci.json5 itself.
+pub fn check_ktestrc(ktest: &Ktestrc) -> Vec<String> {
+    let mut problems = Vec::new();
+    let tests_dir = ktest.ktest_dir.join("tests");
+    if !tests_dir.is_dir() {
+        problems.push(format!("ktest_dir: {} is not a directory", tests_dir.display()));
+    }
+    if ktest.fetch_interval == Some(0) {
+        problems.push("fetch_interval: 0 would fetch continuously".to_string());
+    }
+    for (host, ex) in &ktest.executors {
+        if ex.slots == 0 {
+            problems.push(format!("executors: {} has no slots", host));
+        }
+    }
+    // Local slots' workspaces are ~/ktest-ci/<slot> on the one machine.
+    let local: Vec<&str> = ktest.executors.iter()
+        .filter(|(_, ex)| ex.local)
+        .map(|(host, _)| host.as_str())
+        .collect();
+    if local.len() > 1 {
+        problems.push(format!(
+            "executors: local hosts {} would share workspaces; make it one host with more slots",
+            local.join(", ")
+        ));
+    }
+    problems
+}
+
+/// Problems with one user's config, against `ktest`: each one a line
+/// naming the branch or test group it's in.
+pub fn check_userrc(ktest: &Ktestrc, userrc: &Userrc) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (name, b) in &userrc.branches {
+        if ktest.repo_path(&b.repo).is_none() {
+            problems.push(format!("branch {}: repo {:?} is not configured", name, b.repo));
+        }
+        if let Err(e) = fetch_args(&b.fetch) {
+            problems.push(format!("branch {}: {:#}", name, e));
+        }
+    }
+
+    let tests_dir = ktest.ktest_dir.join("tests");
+    for (name, tg) in &userrc.test_groups {
+        if let Err(e) = encode_env(&tg.env) {
+            problems.push(format!("test_group {}: {:#}", name, e));
+        }
+        for k in tg.kernels.iter().filter(|k| !kernel_id_valid(k)) {
+            problems.push(format!(
+                "test_group {}: kernel {:?} is not a store id (<distro>/<release>[/<arch>])",
+                name, k
+            ));
+        }
+        for test in &tg.tests {
+            let path = tests_dir.join(test);
+            if !path.is_file() {
+                problems.push(format!(
+                    "test_group {}: test {} not found under {}",
+                    name, test.display(), tests_dir.display()
+                ));
+            } else if get_subtests(path).is_empty() {
+                problems.push(format!("test_group {}: test {} lists no subtests", name, test.display()));
+            }
+        }
+    }
+    problems
+}
+
+/// Problems across the whole CI config, each prefixed with the user
+/// config it's in; a user config that doesn't parse is one problem.
+pub fn check_config(rc: &CiConfig) -> Vec<String> {
+    let mut problems = check_ktestrc(&rc.ktest);
+    for (user, userrc) in &rc.users {
+        match userrc {
+            Ok(u) => problems.extend(
+                check_userrc(&rc.ktest, u)
+                    .into_iter()
+                    .map(|p| format!("{}: {}", user, p)),
+            ),
+            Err(e) => problems.push(format!("{}: {:#}", user, e)),
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::users::userrc_read_str;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn ktestrc(ktest_dir: &std::path::Path) -> Ktestrc {
+        json_five::from_str(&format!(
+            r#"{{ linux_repo: "/nonexistent", output_dir: "/nonexistent",
+                  ktest_dir: "{}" }}"#,
+            ktest_dir.display()
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn kernel_ids() {
+        assert!(kernel_id_valid("upstream/stable-kasan"));
+        assert!(kernel_id_valid("debian/forky/amd64"));
+        assert!(!kernel_id_valid("stable-kasan"));
+        assert!(!kernel_id_valid("upstream/"));
+        assert!(!kernel_id_valid("../etc/passwd"));
+        assert!(!kernel_id_valid("a/b/c/d"));
+    }
+
+    #[test]
+    fn zero_fetch_interval() {
+        let mut ktest = ktestrc(std::path::Path::new("/nonexistent"));
+        assert!(!check_ktestrc(&ktest).iter().any(|p| p.starts_with("fetch_interval")));
+        ktest.fetch_interval = Some(0);
+        assert!(check_ktestrc(&ktest).iter().any(|p| p.starts_with("fetch_interval")));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let dir = crate::test_util::TempDir::new("check");
+        let tests = dir.join("tests/fs");
+        std::fs::create_dir_all(&tests).unwrap();
+        for (name, listing) in [("good.ktest", "a b"), ("empty.ktest", "")] {
+            let path = tests.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\necho {}\n", listing)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let userrc = userrc_read_str(r#"{
+            test_groups: {
+                ok: { tests: ["fs/good.ktest"], kernels: ["upstream/stable-default"] },
+                bad: {
+                    tests: ["fs/good.ktest", "fs/missing.ktest", "fs/empty.ktest"],
+                    kernels: ["stable-default"],
+                    env: { "A B": "1" },
+                },
+            },
+            branches: {
+                fine: { fetch: "linux master", test_groups: ["ok"] },
+                broken: { fetch: "--all", repo: "nope", test_groups: ["ok"] },
+            },
+        }"#).unwrap();
+        let problems = check_userrc(&ktestrc(&dir), &userrc);
+
+        let has = |s: &str| problems.iter().any(|p| p.contains(s));
+        assert!(has("branch broken: repo \"nope\""), "{:?}", problems);
+        assert!(has("branch broken: fetch"), "{:?}", problems);
+        assert!(has("test_group bad: env key"), "{:?}", problems);
+        assert!(has("kernel \"stable-default\""), "{:?}", problems);
+        assert!(has("fs/missing.ktest not found"), "{:?}", problems);
+        assert!(has("fs/empty.ktest lists no subtests"), "{:?}", problems);
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+    }
+}
@@ -0,0 +1,258 @@
+//! Optional SQLite index of every recorded result, one row per (commit,
+//! result key).
+//!
+//! The per-commit `<commit>.capnp` files answer "what happened at this
+//! commit"; anything across commits — one subtest's history, pass
+//! rates, durations — means opening one file per commit. With
+//! `results_index` set in ktest-ci.json5 the daemon's TestResultsStore
+//! keeps this index alongside the capnps (resynced in full at load,
+//! then row by row from update() and delete()); rm-results and
+//! gc-results drop what they delete. gen-avg-duration and the cgi and
+//! ci-status history views read it while it's marked synced. The capnp
+//! files stay as they are — the index is derived, and deleting it just
+//! costs a resync.
+
+use crate::api::HistoryResult;
+use crate::{parse_result_key, DurationSample, TestResultsMap, TestStatus};
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS results (
+    commit_id   TEXT NOT NULL,
+    key         TEXT NOT NULL,
+    kernel      TEXT NOT NULL,
+    env         TEXT NOT NULL,
+    status      TEXT NOT NULL,
+    duration    INTEGER NOT NULL,
+    starttime   INTEGER NOT NULL,
+    PRIMARY KEY (commit_id, key)
+);
+CREATE INDEX IF NOT EXISTS results_by_key ON results (key, starttime);
+CREATE TABLE IF NOT EXISTS synced (
+    at          INTEGER NOT NULL
+);
+";
+
+pub struct ResultsIndex {
+    db: Mutex<Connection>,
+}
+
+impl ResultsIndex {
+    /// Open (creating if need be) the index at `path`. WAL, so the cgi
+    /// and ci-status can read while the daemon writes.
+    pub fn open(path: &Path) -> anyhow::Result<ResultsIndex> {
+        let db = Connection::open(path)?;
+        db.busy_timeout(Duration::from_secs(10))?;
+        db.pragma_update(None, "journal_mode", "WAL")?;
+        db.execute_batch(SCHEMA)?;
+        Ok(ResultsIndex { db: Mutex::new(db) })
+    }
+
+    /// Insert or replace `commit`'s rows for every key in `results`.
+    pub fn update(&self, commit: &str, results: &TestResultsMap) -> anyhow::Result<()> {
+        self.write(|tx| insert(tx, commit, results))
+    }
+
+    pub fn delete(&self, commit: &str, keys: &[String]) -> anyhow::Result<()> {
+        self.write(|tx| remove(tx, commit, keys))
+    }
+
+    /// Bring `commit`'s rows for a set of keys in line with the store:
+    /// `results` replaced, `gone` dropped, in one transaction.
+    pub fn replace(&self, commit: &str, results: &TestResultsMap, gone: &[String]) -> anyhow::Result<()> {
+        self.write(|tx| {
+            insert(tx, commit, results)?;
+            remove(tx, commit, gone)
+        })
+    }
+
+    /// Drop every row for `commit`.
+    pub fn delete_commit(&self, commit: &str) -> anyhow::Result<()> {
+        self.write(|tx| tx.execute("DELETE FROM results WHERE commit_id = ?1", params![commit]).map(|_| ()))
+    }
+
+    /// Run `f` in a transaction. If it fails the index no longer matches
+    /// the capnps, so the synced mark goes too: readers fall back to the
+    /// capnps until the daemon's next resync.
+    fn write(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<()>) -> anyhow::Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let ret = db.transaction().and_then(|tx| {
+            f(&tx)?;
+            tx.commit()
+        });
+        if ret.is_err() {
+            if let Err(e) = db.execute("DELETE FROM synced", []) {
+                eprintln!("results index: clearing synced mark: {:#}", e);
+            }
+        }
+        Ok(ret?)
+    }
+
+    /// Replace the whole index with `by_commit`, in one transaction, and
+    /// mark it synced. The mark is dropped first, on its own: if the
+    /// resync fails, the index is left marked as stale.
+    pub fn sync(&self, by_commit: &HashMap<String, TestResultsMap>) -> anyhow::Result<()> {
+        let mut db = self.db.lock().unwrap();
+        db.execute("DELETE FROM synced", [])?;
+        let tx = db.transaction()?;
+        tx.execute("DELETE FROM results", [])?;
+        for (commit, results) in by_commit {
+            insert(&tx, commit, results)?;
+        }
+        tx.execute("INSERT INTO synced (at) VALUES (?1)", params![Utc::now().timestamp()])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Whether the index has been synced with the capnps — not so if
+    /// it was just created, or the daemon's last resync failed. Readers
+    /// should fall back to the capnps if not.
+    pub fn is_synced(&self) -> anyhow::Result<bool> {
+        let db = self.db.lock().unwrap();
+        Ok(db.query_row("SELECT COUNT(*) FROM synced", [], |r| r.get::<_, i64>(0))? != 0)
+    }
+
+    /// Every run per result key, over every commit — what
+    /// gen-avg-duration otherwise gets by reading every capnp.
+    pub fn samples(&self) -> anyhow::Result<BTreeMap<String, Vec<DurationSample>>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt = db.prepare_cached("SELECT key, status, duration, starttime FROM results")?;
+        let mut rows = stmt.query([])?;
+        let mut samples: BTreeMap<String, Vec<DurationSample>> = BTreeMap::new();
+        while let Some(r) = rows.next()? {
+            samples.entry(r.get(0)?).or_default().push(DurationSample {
+                status: TestStatus::from_str(&r.get::<_, String>(1)?),
+                duration: r.get::<_, i64>(2)? as u64,
+                starttime: r.get(3)?,
+            });
+        }
+        Ok(samples)
+    }
+
+    /// Every recorded run of one result key, by commit — a test's
+    /// history without reading a capnp per commit.
+    pub fn history(&self, key: &str) -> anyhow::Result<HashMap<String, HistoryResult>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt = db.prepare_cached(
+            "SELECT commit_id, status, duration, starttime FROM results WHERE key = ?1")?;
+        let mut rows = stmt.query(params![key])?;
+        let mut history = HashMap::new();
+        while let Some(r) = rows.next()? {
+            history.insert(r.get(0)?, HistoryResult {
+                status: r.get(1)?,
+                duration: r.get::<_, i64>(2)? as u64,
+                starttime: r.get(3)?,
+            });
+        }
+        Ok(history)
+    }
+}
+
+fn remove(db: &Connection, commit: &str, keys: &[String]) -> rusqlite::Result<()> {
+    let mut stmt = db.prepare_cached("DELETE FROM results WHERE commit_id = ?1 AND key = ?2")?;
+    for k in keys {
+        stmt.execute(params![commit, k])?;
+    }
+    Ok(())
+}
+
+fn insert(db: &Connection, commit: &str, results: &TestResultsMap) -> rusqlite::Result<()> {
+    let mut stmt = db.prepare_cached(
+        "INSERT OR REPLACE INTO results
+         (commit_id, key, kernel, env, status, duration, starttime)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for (key, r) in results {
+        let (kernel, env) = parse_result_key(key).map_or(("", ""), |k| (k.kernel, k.env));
+        stmt.execute(params![
+            commit,
+            key,
+            kernel,
+            env,
+            r.status.to_str(),
+            r.duration as i64,
+            r.starttime.timestamp(),
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::TestResult;
+
+    fn results(entries: &[(&str, TestStatus, u64)]) -> TestResultsMap {
+        entries
+            .iter()
+            .map(|(key, status, duration)| {
+                (key.to_string(), TestResult::new(*status, *duration))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tracks_updates_and_deletes() {
+        let dir = TempDir::new("index");
+        let index = ResultsIndex::open(&dir.join("results.db")).unwrap();
+
+        let mut by_commit = HashMap::new();
+        by_commit.insert("c1".to_string(), results(&[
+            ("t@upstream_stable@A=1.sub", TestStatus::Passed, 10),
+            ("t.other", TestStatus::Failed, 5),
+        ]));
+        assert!(!index.is_synced().unwrap());
+        index.sync(&by_commit).unwrap();
+        assert!(index.is_synced().unwrap());
+        index.update("c2", &results(&[("t@upstream_stable@A=1.sub", TestStatus::Failed, 20)])).unwrap();
+
+        let samples = index.samples().unwrap();
+        let s = crate::TestStats::from_samples(&samples["t@upstream_stable@A=1.sub"]);
+        assert_eq!((s.nr, s.passed, s.failed, s.duration, s.max), (2, 1, 1, 15, 20));
+        assert_eq!(samples["t.other"].len(), 1);
+
+        index.delete("c1", &["t.other".to_string()]).unwrap();
+        index.delete_commit("c2").unwrap();
+        let samples = index.samples().unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples["t@upstream_stable@A=1.sub"].len(), 1);
+
+        // A resync replaces everything.
+        index.sync(&HashMap::new()).unwrap();
+        assert!(index.samples().unwrap().is_empty());
+    }
+
+    #[test]
+    fn history_is_per_commit() {
+        let dir = TempDir::new("index");
+        let index = ResultsIndex::open(&dir.join("results.db")).unwrap();
+        index.update("c1", &results(&[("t.a", TestStatus::Passed, 10), ("t.b", TestStatus::Failed, 1)])).unwrap();
+        index.update("c2", &results(&[("t.a", TestStatus::Failed, 20)])).unwrap();
+        index.replace("c1", &results(&[("t.a", TestStatus::Passed, 12)]), &["t.b".to_string()]).unwrap();
+
+        let h = index.history("t.a").unwrap();
+        assert_eq!(h.len(), 2);
+        assert_eq!((h["c1"].status.as_str(), h["c1"].duration), ("Passed", 12));
+        assert_eq!((h["c2"].status.as_str(), h["c2"].duration), ("Failed", 20));
+        assert!(index.history("t.b").unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_write_drops_synced() {
+        let dir = TempDir::new("index");
+        let path = dir.join("results.db");
+        let index = ResultsIndex::open(&path).unwrap();
+        index.sync(&HashMap::new()).unwrap();
+        assert!(index.is_synced().unwrap());
+
+        Connection::open(&path).unwrap().execute("DROP TABLE results", []).unwrap();
+        assert!(index.update("c1", &results(&[("t.a", TestStatus::Passed, 1)])).is_err());
+        assert!(!index.is_synced().unwrap());
+    }
+}
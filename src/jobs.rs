@@ -8,15 +8,21 @@
 // a bounded window of the *newest* commits' work without materializing
 // the whole (potentially millions-of-jobs) matrix.
 //
+// On top of the window, desired_jobs() bisects new failures: a result
+// key that went Passed -> Failed across commits nobody tested (holes
+// left by max_commits or age weighting) gets a job at the midpoint of
+// the gap, so successive refills narrow it down to the first bad commit
+// — which desired_jobs() then hands back for the daemon to mark.
+//
 // Pure read of (config, git refs, results) — it does not fetch git or
 // refresh result caches; the daemon owns those.
 
 use crate::{
-    encode_env, git_get_commit, subtest_result_key, test_stats, CiConfig, RcTestGroup,
-    TestResultsMap, TestResultsStore, TestStats, TestStatus,
+    cross_kernel_stats, encode_env, git_get_commit, subtest_result_key, test_stats, CiConfig,
+    RcTestGroup, TestResult, TestResultsMap, TestResultsStore, TestStats, TestStatus,
 };
 use memmap::MmapOptions;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::{LazyLock, Mutex};
 use std::time::SystemTime;
@@ -47,6 +53,50 @@ pub struct Job {
     pub nice: i64,
     /// Expected runtime in seconds, from historical durations.
     pub duration: u64,
+    /// Historical 90th-percentile runtime in seconds; None if it's never
+    /// run.
+    pub p90: Option<u64>,
+    /// The test group's supervisor timeout settings.
+    pub timeout: BatchTimeout,
+}
+
+/// Supervisor timeout counted for a subtest with no history: the fixed
+/// timeout every batch got before it was derived from durations.
+pub const NEW_SUBTEST_TIMEOUT: u64 = 1200;
+
+/// A test group's batch_timeout_* settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchTimeout {
+    pub factor: f64,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl BatchTimeout {
+    pub fn new(tg: &RcTestGroup) -> BatchTimeout {
+        BatchTimeout {
+            factor: tg.batch_timeout_factor,
+            min: tg.batch_timeout_min,
+            max: tg.batch_timeout_max,
+        }
+    }
+
+    /// The supervisor's `-T` for a batch whose subtests have these p90
+    /// durations. -T is a per-test watchdog — the supervisor re-arms it
+    /// as each test starts — so it's the longest subtest's p90 times
+    /// `factor`, not the sum, clamped to [min, max]. A subtest with no
+    /// history counts as NEW_SUBTEST_TIMEOUT, still within the clamp.
+    pub fn secs(&self, p90s: impl IntoIterator<Item = Option<u64>>) -> u64 {
+        let longest = p90s
+            .into_iter()
+            .map(|p| match p {
+                Some(p) => (p as f64 * self.factor).ceil() as u64,
+                None => NEW_SUBTEST_TIMEOUT,
+            })
+            .max()
+            .unwrap_or(NEW_SUBTEST_TIMEOUT);
+        longest.clamp(self.min, self.max)
+    }
 }
 
 /// List the subtests of a .ktest file. Cached — the same test shows up
@@ -60,7 +110,7 @@ pub struct Job {
 /// renamed or removed bumps it (any commit/rebase/pull that rewrites the
 /// file does), so the matrix re-enumerates instead of requesting a stale
 /// name until the daemon restarts. One stat per lookup buys that.
-fn get_subtests(test_path: PathBuf) -> Vec<String> {
+pub(crate) fn get_subtests(test_path: PathBuf) -> Vec<String> {
     static CACHE: LazyLock<Mutex<HashMap<PathBuf, (SystemTime, Vec<String>)>>> =
         LazyLock::new(|| Mutex::new(HashMap::new()));
 
@@ -139,9 +189,24 @@ fn job_wanted(status: Option<TestStatus>) -> bool {
     }
 }
 
+/// Whether a subtest with a verdict should run again at the same commit
+/// to tell a flake from a real failure: some run failed, and fewer than
+/// `1 + reruns` runs have been made. Every run is kept as an attempt, so
+/// the latest run passing doesn't stop the re-runs — the point is the
+/// k-of-N failure count, not a retry until green.
+fn rerun_wanted(result: Option<&TestResult>, reruns: u64) -> bool {
+    match result {
+        Some(r) => matches!(r.status, TestStatus::Passed | TestStatus::Failed)
+            && r.failures() != 0
+            && r.runs() <= reruns,
+        None => false,
+    }
+}
+
 /// Niceness for one subtest: the test_group's base nice plus the
 /// historical-stats adjustments — nice down tests that consistently
-/// pass-or-fail, and long-running tests.
+/// pass-or-fail, and long-running tests (by median, so one hung run
+/// doesn't nice a test down for good).
 fn job_nice(tg: &RcTestGroup, stats: Option<&TestStats>) -> i64 {
     let mut nice = tg.nice as i64;
     if let Some(stats) = stats {
@@ -152,12 +217,25 @@ fn job_nice(tg: &RcTestGroup, stats: Option<&TestStats>) -> i64 {
             nice += tg.test_always_passes_nice as i64;
         }
         if tg.test_duration_nice != 0 {
-            nice += (stats.duration / tg.test_duration_nice) as i64;
+            nice += (stats.typical_duration() / tg.test_duration_nice) as i64;
         }
     }
     nice
 }
 
+/// A subtest's expected runtime on one kernel, for batch sizing, and
+/// its p90, for the batch timeout. A test_durations.capnp from before
+/// there were percentiles has no p90 — treated as no history.
+fn job_durations(rc: &CiConfig, durations: Option<&[u8]>, spec: &TestSpec,
+                 subtest: &str, kernel: &str) -> (u64, Option<u64>) {
+    let stats = test_stats(durations, &spec.test, subtest, kernel, &spec.env);
+    let duration = stats.as_ref()
+        .map(|s| s.typical_duration())
+        .unwrap_or(rc.ktest.subtest_duration_def.unwrap_or(30));
+    let p90 = stats.filter(|s| s.max != 0).map(|s| s.p90);
+    (duration, p90)
+}
+
 /// One (user, branch, test_group, test) and its branch's commit list —
 /// enough to emit that test's jobs commit-by-commit.
 struct TestSpec<'a> {
@@ -170,6 +248,9 @@ struct TestSpec<'a> {
     kernels: Vec<String>,
     /// Commit ids newest-first, capped at tg.max_commits.
     commits: Vec<String>,
+    /// Commit ids newest-first, capped at tg.bisect_commits — the
+    /// history bisect_next() searches for the last passing result.
+    history: Vec<String>,
 }
 
 /// The branch's commit ids, newest-first, capped at `max`.
@@ -229,10 +310,15 @@ fn build_test_specs<'a>(rc: &'a CiConfig) -> Vec<TestSpec<'a>> {
                     }
                 };
                 let userbranch = format!("{}/{}", user, branch);
-                let commits = match branch_commits(&git, &userbranch, tg.max_commits as usize) {
+                let depth = tg.max_commits.max(tg.bisect_commits) as usize;
+                let history = match branch_commits(&git, &userbranch, depth) {
                     Some(c) => c,
                     None => continue,
                 };
+                let commits: Vec<String> =
+                    history.iter().take(tg.max_commits as usize).cloned().collect();
+                let history: Vec<String> =
+                    history.into_iter().take(tg.bisect_commits as usize).collect();
                 let kernels = if tg.kernels.is_empty() {
                     vec![String::new()]
                 } else {
@@ -253,6 +339,7 @@ fn build_test_specs<'a>(rc: &'a CiConfig) -> Vec<TestSpec<'a>> {
                         subtests,
                         kernels: kernels.clone(),
                         commits: commits.clone(),
+                        history: history.clone(),
                     });
                 }
             }
@@ -261,6 +348,195 @@ fn build_test_specs<'a>(rc: &'a CiConfig) -> Vec<TestSpec<'a>> {
     specs
 }
 
+/// Every commit some configured branch still wants tested: its newest
+/// max(max_commits, bisect_commits) commits, over the branch's test
+/// groups — the same depth build_test_specs() walks. A job for any other
+/// commit is obsolete (its branch was force-pushed or rebased away) —
+/// unless it's one live_commits() couldn't check; see is_live().
+#[derive(Default)]
+pub struct LiveCommits {
+    pub commits: HashSet<String>,
+    /// Users whose config didn't load: any of their jobs might be live.
+    pub unresolved_users: HashSet<String>,
+    /// (user, repo) with a branch whose repo or ref couldn't be read.
+    /// Jobs don't record their branch, so this covers the repo's others.
+    pub unresolved_repos: HashSet<(String, String)>,
+}
+
+impl LiveCommits {
+    /// Whether a job for `key` is, or might be, still wanted.
+    pub fn is_live(&self, key: &JobKey) -> bool {
+        self.commits.contains(&key.commit)
+            || self.unresolved_users.contains(&key.user)
+            || self.unresolved_repos.contains(&(key.user.clone(), key.repo.clone()))
+    }
+}
+
+pub fn live_commits(rc: &CiConfig) -> LiveCommits {
+    let mut live = LiveCommits::default();
+    for (user, userconfig) in &rc.users {
+        let Ok(userconfig) = userconfig else {
+            live.unresolved_users.insert(user.clone());
+            continue;
+        };
+        for (branch, branchconfig) in &userconfig.branches {
+            let depth = branchconfig
+                .test_groups
+                .iter()
+                .filter_map(|tg| userconfig.test_groups.get(tg))
+                .map(|tg| tg.max_commits.max(tg.bisect_commits))
+                .max()
+                .unwrap_or(0);
+            let commits = rc.ktest.repo_path(&branchconfig.repo)
+                .and_then(|path| git2::Repository::open(path).ok())
+                .and_then(|git| branch_commits(&git, &format!("{}/{}", user, branch), depth as usize));
+            match commits {
+                Some(commits) => live.commits.extend(commits),
+                None => {
+                    live.unresolved_repos.insert((user.clone(), branchconfig.repo.clone()));
+                }
+            }
+        }
+    }
+    live
+}
+
+/// A branch tip and the result keys its test groups run there.
+pub struct BranchTip {
+    pub user: String,
+    pub branch: String,
+    pub commit: String,
+    pub subject: String,
+    /// First parent; None for a root commit.
+    pub parent: Option<String>,
+    /// Every result key the branch's test groups run at the tip, with
+    /// the names of the groups that run it.
+    pub keys: BTreeMap<String, Vec<String>>,
+    /// Testing of the tip is finished: every key has a verdict, and no
+    /// re-runs are still owed (see rerun_wanted()).
+    pub done: bool,
+}
+
+/// Every configured branch's tip, for acting on a tip once its testing
+/// is finished (the daemon's failure notifications). Pure read of
+/// (config, git refs, results), like desired_jobs().
+pub fn branch_tips(rc: &CiConfig, results: &TestResultsStore) -> Vec<BranchTip> {
+    let mut tips = Vec::new();
+    for (user, userconfig) in &rc.users {
+        let Ok(userconfig) = userconfig else { continue };
+        for (branch, branchconfig) in &userconfig.branches {
+            let Some(repo_path) = rc.ktest.repo_path(&branchconfig.repo) else { continue };
+            let Ok(git) = git2::Repository::open(repo_path) else { continue };
+            let Ok(tip) = git_get_commit(&git, format!("{}/{}", user, branch)) else { continue };
+            let commit = tip.id().to_string();
+            let tip_results = results.commit_results(&commit).unwrap_or_default();
+
+            let mut keys: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            let mut done = true;
+            for tg_name in &branchconfig.test_groups {
+                let Some(tg) = userconfig.test_groups.get(tg_name) else { continue };
+                let Ok(env) = encode_env(&tg.env) else { continue };
+                let kernels = if tg.kernels.is_empty() {
+                    vec![String::new()]
+                } else {
+                    tg.kernels.clone()
+                };
+                for test in &tg.tests {
+                    let test = test.to_string_lossy();
+                    for subtest in get_subtests(rc.ktest.ktest_dir.join("tests").join(&*test)) {
+                        for kernel in &kernels {
+                            let key = subtest_result_key(&test, &subtest, kernel, &env);
+                            let result = tip_results.get(&key);
+                            if !result.is_some_and(|r| result_is_done(r.status))
+                                || rerun_wanted(result, tg.rerun_failed)
+                            {
+                                done = false;
+                            }
+                            keys.entry(key).or_default().push(tg_name.clone());
+                        }
+                    }
+                }
+            }
+
+            tips.push(BranchTip {
+                user: user.clone(),
+                branch: branch.clone(),
+                subject: tip.summary().ok().flatten().unwrap_or("").to_string(),
+                parent: tip.parent_id(0).ok().map(|id| id.to_string()),
+                commit,
+                done: done && !keys.is_empty(),
+                keys,
+            });
+        }
+    }
+    tips
+}
+
+/// Where bisect_next() got to for one result key; indexes into its
+/// `commits`.
+#[derive(Debug, PartialEq)]
+enum Bisect {
+    /// Test `commit` next; `newest` is the newest Failed, for scheduling.
+    Next { newest: usize, commit: usize },
+    /// The gap is closed: this is the first bad commit.
+    Found(usize),
+}
+
+/// Where to bisect next for one result key. `commits` is a branch's
+/// history newest-first; `status` looks up the key's recorded status at
+/// a commit (None = never run there).
+///
+/// Only the newest regression is bisected: the newest recorded verdict
+/// must be Failed, and the search runs back to the first Passed. Next
+/// is the untested commit closest to the middle of the gap between the
+/// oldest Failed and that Passed; Found, once there's none left, is
+/// that oldest Failed. None when there is nothing to do yet: a job in
+/// the gap is still running, or the newest verdict isn't a failure.
+/// Anything above the oldest Failed doesn't matter. Unknown results in
+/// the gap are garbled, not verdicts, so are tested again; Notrun /
+/// FailedToRun are skipped over, like `git bisect skip`.
+fn bisect_next(
+    commits: &[String],
+    mut status: impl FnMut(&str) -> Option<TestStatus>,
+) -> Option<Bisect> {
+    let mut newest_bad: Option<usize> = None;
+    let mut bad: Option<usize> = None;
+    let mut untested = Vec::new();
+    let mut running = false;
+
+    for (i, c) in commits.iter().enumerate() {
+        match status(c) {
+            None | Some(TestStatus::Unknown) => {
+                if bad.is_some() {
+                    untested.push(i);
+                }
+            }
+            Some(TestStatus::Failed) => {
+                newest_bad.get_or_insert(i);
+                bad = Some(i);
+                untested.clear();
+                running = false;
+            }
+            Some(TestStatus::Passed) => {
+                let bad = bad?;
+                if running {
+                    return None;
+                }
+                let mid = bad + (i - bad) / 2;
+                return Some(match untested.into_iter().min_by_key(|&u| u.abs_diff(mid)) {
+                    Some(next) => Bisect::Next { newest: newest_bad?, commit: next },
+                    None => Bisect::Found(bad),
+                });
+            }
+            Some(TestStatus::Inprogress) => running |= bad.is_some(),
+            Some(_) => {
+                bad?;
+            }
+        }
+    }
+    None
+}
+
 /// Per-job scheduling weight — lower runs sooner.
 ///
 /// Faithful port of gen-job-list's testjob_weight: age + nice. A
@@ -270,8 +546,16 @@ fn job_weight(j: &Job) -> i64 {
     j.age as i64 + j.nice
 }
 
+/// What desired_jobs() found to do.
+pub struct DesiredJobs {
+    pub jobs: Vec<Job>,
+    /// (commit, result key) of failures bisection has just pinned down
+    /// to their first bad commit, for TestResultsStore::mark_first_bad().
+    pub first_bad: Vec<(String, String)>,
+}
+
 /// The desired test jobs, priority-ordered (lowest weight first),
-/// capped at `limit`.
+/// capped at `limit`, and the first bad commits bisection has found.
 ///
 /// Emits the full candidate matrix, sorts by `(age + nice)` weight with
 /// commit/test/kernel/env/duration as tiebreakers (matching the old
@@ -281,7 +565,7 @@ fn job_weight(j: &Job) -> i64 {
 /// May contain duplicate `JobKey`s if the config routes the same
 /// (test, kernel, env) through two test_groups on one branch; the
 /// daemon's job map collapses those.
-pub fn desired_jobs(rc: &CiConfig, results: &TestResultsStore, limit: usize) -> Vec<Job> {
+pub fn desired_jobs(rc: &CiConfig, results: &TestResultsStore, limit: usize) -> DesiredJobs {
     // Historical per-subtest durations, for the nice/duration hints.
     let durations_map = std::fs::File::open(rc.ktest.output_dir.join("test_durations.capnp"))
         .ok()
@@ -307,19 +591,20 @@ pub fn desired_jobs(rc: &CiConfig, results: &TestResultsStore, limit: usize) ->
                 .or_insert_with(|| results.commit_results(commit).unwrap_or_default());
             for subtest in &spec.subtests {
                 // nice is computed once per subtest from a cross-kernel lookup
-                // (empty kernel/env == subtest_full_name), exactly as
-                // gen-job-list did, and summed into the weight. The per-kernel
-                // duration below is only jobkit's batch-sizing cost — it must
-                // not feed the priority ordering.
+                // (see cross_kernel_key()), as gen-job-list did, and summed
+                // into the weight. The per-kernel duration below is only
+                // jobkit's batch-sizing cost — it must not feed the
+                // priority ordering.
                 let nice = job_nice(spec.tg,
-                                    test_stats(durations, &spec.test, subtest, "", "").as_ref());
+                                    cross_kernel_stats(durations, &spec.test, subtest).as_ref());
 
                 for kernel in &spec.kernels {
-                    let duration = test_stats(durations, &spec.test, subtest, kernel, &spec.env)
-                        .map(|s| s.duration)
-                        .unwrap_or(rc.ktest.subtest_duration_def.unwrap_or(30));
+                    let (duration, p90) = job_durations(rc, durations, spec, subtest, kernel);
                     let key = subtest_result_key(&spec.test, subtest, kernel, &spec.env);
-                    if !job_wanted(results.get(&key).map(|r| r.status)) {
+                    let result = results.get(&key);
+                    if !job_wanted(result.map(|r| r.status))
+                        && !rerun_wanted(result, spec.tg.rerun_failed)
+                    {
                         continue;
                     }
                     out.push(Job {
@@ -335,12 +620,64 @@ pub fn desired_jobs(rc: &CiConfig, results: &TestResultsStore, limit: usize) ->
                         age: age as u64,
                         nice,
                         duration,
+                        p90,
+                        timeout: BatchTimeout::new(spec.tg),
                     });
                 }
             }
         }
     }
 
+    // Bisection jobs for new failures. Aged like the failing commit
+    // they're narrowing down, not their own (possibly deep) position, so
+    // a regression at the tip gets found at tip priority.
+    let mut first_bad = Vec::new();
+    for spec in &specs {
+        for subtest in &spec.subtests {
+            let nice = job_nice(spec.tg,
+                                cross_kernel_stats(durations, &spec.test, subtest).as_ref());
+
+            for kernel in &spec.kernels {
+                let key = subtest_result_key(&spec.test, subtest, kernel, &spec.env);
+                let next = bisect_next(&spec.history, |commit| {
+                    results_cache
+                        .entry(commit.to_string())
+                        .or_insert_with(|| results.commit_results(commit).unwrap_or_default())
+                        .get(&key)
+                        .map(|r| r.status)
+                });
+                let (age, idx) = match next {
+                    Some(Bisect::Next { newest, commit }) => (newest, commit),
+                    Some(Bisect::Found(idx)) => {
+                        let commit = &spec.history[idx];
+                        if !results_cache[commit][&key].first_bad {
+                            first_bad.push((commit.clone(), key));
+                        }
+                        continue;
+                    }
+                    None => continue,
+                };
+                let (duration, p90) = job_durations(rc, durations, spec, subtest, kernel);
+                out.push(Job {
+                    key: JobKey {
+                        user: spec.user.clone(),
+                        repo: spec.repo.clone(),
+                        commit: spec.history[idx].clone(),
+                        kernel: kernel.clone(),
+                        env: spec.env.clone(),
+                        test: spec.test.clone(),
+                        subtest: subtest.clone(),
+                    },
+                    age: age as u64,
+                    nice,
+                    duration,
+                    p90,
+                    timeout: BatchTimeout::new(spec.tg),
+                });
+            }
+        }
+    }
+
     out.sort_by(|a, b| {
         job_weight(a)
             .cmp(&job_weight(b))
@@ -356,7 +693,9 @@ pub fn desired_jobs(rc: &CiConfig, results: &TestResultsStore, limit: usize) ->
     let mut seen = HashSet::new();
     out.retain(|j| seen.insert(j.key.clone()));
     out.truncate(limit);
-    out
+    first_bad.sort();
+    first_bad.dedup();
+    DesiredJobs { jobs: out, first_bad }
 }
 
 #[cfg(test)]
@@ -375,6 +714,120 @@ mod tests {
         assert!(!result_is_done(TestStatus::Unknown));    // garbled status
     }
 
+    fn bisect(statuses: &[Option<TestStatus>]) -> Option<Bisect> {
+        let commits: Vec<String> = (0..statuses.len()).map(|i| i.to_string()).collect();
+        bisect_next(&commits, |c| statuses[c.parse::<usize>().unwrap()])
+    }
+
+    #[test]
+    fn bisect_picks_gap_midpoint() {
+        use TestStatus::*;
+        // F . . . . P — test the middle of the untested gap
+        assert_eq!(bisect(&[Some(Failed), None, None, None, None, Some(Passed)]),
+                   Some(Bisect::Next { newest: 0, commit: 2 }));
+        // untested commits above the newest verdict don't matter
+        assert_eq!(bisect(&[None, Some(Failed), None, None, Some(Passed)]),
+                   Some(Bisect::Next { newest: 1, commit: 2 }));
+        // narrows from the oldest Failed, still reports the newest
+        assert_eq!(bisect(&[Some(Failed), None, Some(Failed), None, None, None, Some(Passed)]),
+                   Some(Bisect::Next { newest: 0, commit: 4 }));
+        // a garbled result in the gap is tested again
+        assert_eq!(bisect(&[Some(Failed), Some(Unknown), Some(Passed)]),
+                   Some(Bisect::Next { newest: 0, commit: 1 }));
+        // so is one above the oldest Failed
+        assert_eq!(bisect(&[Some(Unknown), Some(Failed), None, Some(Passed)]),
+                   Some(Bisect::Next { newest: 1, commit: 2 }));
+    }
+
+    #[test]
+    fn bisect_stops_when_done_or_busy() {
+        use TestStatus::*;
+        // gap closed: 1 is the first bad commit
+        assert_eq!(bisect(&[Some(Failed), Some(Failed), Some(Passed)]), Some(Bisect::Found(1)));
+        // newest verdict passes — no regression to chase
+        assert_eq!(bisect(&[Some(Passed), None, Some(Failed)]), None);
+        // a bisection job is already in flight
+        assert_eq!(bisect(&[Some(Failed), Some(Inprogress), None, Some(Passed)]), None);
+        // ...but not in the gap: the tip being re-run, or a commit above
+        // the oldest Failed
+        assert_eq!(bisect(&[Some(Inprogress), Some(Failed), None, Some(Passed)]),
+                   Some(Bisect::Next { newest: 1, commit: 2 }));
+        assert_eq!(bisect(&[Some(Failed), Some(Inprogress), Some(Failed), Some(Passed)]),
+                   Some(Bisect::Found(2)));
+        // never passed within the history
+        assert_eq!(bisect(&[Some(Failed), None, None]), None);
+        // skipped verdicts are stepped over, not tested again
+        assert_eq!(bisect(&[Some(Failed), Some(FailedToRun), None, Some(Passed)]),
+                   Some(Bisect::Next { newest: 0, commit: 2 }));
+        assert_eq!(bisect(&[Some(Failed), Some(Notrun), Some(Passed)]), Some(Bisect::Found(0)));
+    }
+
+    fn result(status: TestStatus, attempts: &[TestStatus]) -> TestResult {
+        let r = TestResult::new(status, 0);
+        TestResult {
+            attempts: attempts
+                .iter()
+                .map(|&status| crate::TestAttempt { status, starttime: r.starttime, duration: 0 })
+                .collect(),
+            ..r
+        }
+    }
+
+    #[test]
+    fn reruns_failures_up_to_limit() {
+        use TestStatus::*;
+        assert!(!rerun_wanted(None, 2));
+        assert!(!rerun_wanted(Some(&result(Failed, &[])), 0));    // re-runs off
+        assert!(!rerun_wanted(Some(&result(Passed, &[])), 2));    // never failed
+        assert!(rerun_wanted(Some(&result(Failed, &[])), 2));
+        // a passing re-run doesn't end it early — we want k of N
+        assert!(rerun_wanted(Some(&result(Passed, &[Failed])), 2));
+        assert!(!rerun_wanted(Some(&result(Failed, &[Failed, Passed])), 2));
+        // a re-run in flight isn't re-emitted
+        assert!(!rerun_wanted(Some(&result(Inprogress, &[Failed])), 2));
+    }
+
+    #[test]
+    fn flaky_classification() {
+        use TestStatus::*;
+        assert_eq!(result(Failed, &[]).flaky(), None);
+        assert_eq!(result(Failed, &[Failed, Failed]).flaky(), None); // consistent
+        assert_eq!(result(Passed, &[Failed, Passed]).flaky(), Some((1, 3)));
+    }
+
+    #[test]
+    fn batch_timeout_from_longest_p90() {
+        let t = BatchTimeout { factor: 3.0, min: 300, max: 7200 };
+        assert_eq!(t.secs([Some(5), Some(5)]), 300);            // clamped up
+        assert_eq!(t.secs([Some(5), Some(400)]), 1200);         // longest, not the sum
+        assert_eq!(t.secs([Some(5), None]), NEW_SUBTEST_TIMEOUT); // never run
+        assert_eq!(t.secs([Some(10000)]), 7200);                // clamped down
+        let short = BatchTimeout { max: 600, ..t };
+        assert_eq!(short.secs([None]), 600);                    // max wins over the new-subtest default
+    }
+
+    #[test]
+    fn unresolved_branches_keep_their_jobs() {
+        let key = |user: &str, repo: &str, commit: &str| JobKey {
+            user: user.to_string(),
+            repo: repo.to_string(),
+            commit: commit.to_string(),
+            kernel: String::new(),
+            env: String::new(),
+            test: "t.ktest".to_string(),
+            subtest: "s".to_string(),
+        };
+        let mut live = LiveCommits::default();
+        live.commits.insert("live".to_string());
+        live.unresolved_users.insert("broken".to_string());
+        live.unresolved_repos.insert(("user".to_string(), "missing".to_string()));
+
+        assert!(live.is_live(&key("user", "linux", "live")));
+        assert!(!live.is_live(&key("user", "linux", "gone")));
+        assert!(live.is_live(&key("broken", "linux", "gone")));
+        assert!(live.is_live(&key("user", "missing", "gone")));
+    }
+
     #[test]
     fn inprogress_is_not_re_emitted() {
         assert!(job_wanted(None));                          // never run
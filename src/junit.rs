@@ -0,0 +1,145 @@
+//! JUnit XML rendering of one commit's results, for CI front-ends and
+//! IDE plugins that consume it (cgi `format=junit`, ci-status show
+//! --junit).
+//!
+//! Each test file is a `<testsuite>` and each result key a `<testcase>`
+//! in it. Failed carries a `<failure>` with the tail of the subtest's
+//! log, FailedToRun an `<error>`; Notrun and In progress are
+//! `<skipped>`, Unknown an `<error>`.
+
+use crate::{KeySplitter, TestResult, TestResultsMap, TestStatus};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Lines of log.br put in a `<failure>`.
+pub const LOG_TAIL_LINES: usize = 50;
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            // Not representable in XML 1.0 at all; test output has them
+            // (ANSI escapes, stray NULs).
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn tail(log: &str, lines: usize) -> &str {
+    let start = log
+        .trim_end()
+        .rmatch_indices('\n')
+        .nth(lines.saturating_sub(1))
+        .map_or(0, |(i, _)| i + 1);
+    &log[start..]
+}
+
+/// `commit`'s results as a JUnit document. `log` fetches a result key's
+/// log.br contents for failures; None just leaves the body out.
+pub fn commit_junit(
+    commit: &str,
+    tests: &TestResultsMap,
+    mut log: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let keys = KeySplitter::new(tests.keys().map(String::as_str));
+    let mut suites: BTreeMap<&str, Vec<(&String, &TestResult)>> = BTreeMap::new();
+    for (key, r) in tests {
+        suites.entry(keys.split(key).0).or_default().push((key, r));
+    }
+
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(out, r#"<testsuites name="{}">"#, escape(commit)).unwrap();
+
+    for (suite, cases) in &suites {
+        let count = |s: TestStatus| cases.iter().filter(|(_, r)| r.status == s).count();
+        writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{}">"#,
+            escape(suite),
+            cases.len(),
+            count(TestStatus::Failed),
+            count(TestStatus::FailedToRun) + count(TestStatus::Unknown),
+            count(TestStatus::Notrun) + count(TestStatus::Inprogress),
+            cases.iter().map(|(_, r)| r.duration).sum::<u64>(),
+        )
+        .unwrap();
+
+        for (key, r) in cases {
+            let open = format!(
+                r#"    <testcase name="{}" classname="{}" time="{}""#,
+                escape(key),
+                escape(suite),
+                r.duration
+            );
+            let status = escape(r.status.to_str());
+            match r.status {
+                TestStatus::Passed => writeln!(out, "{}/>", open),
+                TestStatus::Failed => {
+                    let body = log(key).map(|l| escape(tail(&l, LOG_TAIL_LINES))).unwrap_or_default();
+                    writeln!(out, "{}>", open).unwrap();
+                    writeln!(out, r#"      <failure message="{}">{}</failure>"#, status, body).unwrap();
+                    writeln!(out, "    </testcase>")
+                }
+                TestStatus::Notrun | TestStatus::Inprogress => {
+                    writeln!(out, r#"{}><skipped message="{}"/></testcase>"#, open, status)
+                }
+                TestStatus::FailedToRun | TestStatus::Unknown => {
+                    writeln!(out, r#"{}><error message="{}"/></testcase>"#, open, status)
+                }
+            }
+            .unwrap();
+        }
+
+        writeln!(out, "  </testsuite>").unwrap();
+    }
+
+    writeln!(out, "</testsuites>").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suites_per_test_file() {
+        let tests: TestResultsMap = [
+            ("fs.ec@upstream_stable.one", TestStatus::Passed),
+            ("fs.ec@upstream_stable.two", TestStatus::Failed),
+            ("fs.ec@upstream_stable@X=1.two", TestStatus::Notrun),
+            ("boot@upstream_stable.boot", TestStatus::FailedToRun),
+        ]
+        .into_iter()
+        .map(|(k, status)| (k.to_string(), TestResult::new(status, 7)))
+        .collect();
+
+        let xml = commit_junit("abc", &tests, |key| {
+            assert_eq!(key, "fs.ec@upstream_stable.two");
+            Some("early\nline 1\n<oops> & \x1b[31mred\n".to_string())
+        });
+
+        assert!(xml.contains(r#"<testsuite name="boot" tests="1" failures="0" errors="1" skipped="0" time="7">"#), "{}", xml);
+        assert!(xml.contains(r#"<testsuite name="fs.ec" tests="3" failures="1" errors="0" skipped="1" time="21">"#), "{}", xml);
+        assert!(xml.contains(r#"<testcase name="fs.ec@upstream_stable.one" classname="fs.ec" time="7"/>"#), "{}", xml);
+        assert!(xml.contains(r#"<failure message="Failed">early
+line 1
+&lt;oops&gt; &amp; [31mred
+</failure>"#), "{}", xml);
+        assert!(xml.contains(r#"<skipped message="Not run"/>"#), "{}", xml);
+    }
+
+    #[test]
+    fn tail_keeps_last_lines() {
+        assert_eq!(tail("a\nb\nc\n", 2), "b\nc\n");
+        assert_eq!(tail("a\nb\nc", 5), "a\nb\nc");
+        assert_eq!(tail("", 5), "");
+    }
+}
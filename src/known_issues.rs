@@ -0,0 +1,225 @@
+//! Known issues: failures everyone already knows about, each pointing
+//! at its bug report, so reviewers can skip them.
+//!
+//! `known-issues.json` in the output dir is a list of entries; one
+//! matches a Failed result when its `tests` glob matches the result key
+//! and its `log` regex matches the failure signature or the log. The
+//! cgi commit page and `ci-status show`/`logs` annotate such results
+//! with the bug link, and branch_entries() counts them apart from
+//! unexplained failures. `ci-status known-issues`
+//! edits the file.
+
+use crate::{TestResult, TestStatus};
+use anyhow::Context;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const FILE: &str = "known-issues.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KnownIssue {
+    /// Glob over result keys (`/` accepted for `.`, as in quarantine)
+    pub tests: String,
+    /// Regex a failed run's signature or log must match
+    pub log: String,
+    /// Bug report URL
+    pub bug: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+}
+
+/// The entries in `<output_dir>/known-issues.json`; none if there's no
+/// such file.
+pub fn read(output_dir: &Path) -> anyhow::Result<Vec<KnownIssue>> {
+    let path = output_dir.join(FILE);
+    match std::fs::read(&path) {
+        Ok(buf) => serde_json::from_slice(&buf).with_context(|| format!("parsing {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Replace `<output_dir>/known-issues.json`; every entry must compile,
+/// and link to an http(s) bug report — the cgi puts `bug` in an href.
+pub fn write(output_dir: &Path, issues: &[KnownIssue]) -> anyhow::Result<()> {
+    KnownIssues::new(output_dir, issues.to_vec())?;
+    if let Some(i) = issues.iter().find(|i| !bug_url_ok(&i.bug)) {
+        anyhow::bail!("bug {:?} is not an http(s) URL", i.bug);
+    }
+
+    let path = output_dir.join(FILE);
+    let tmp = path.with_extension("json.new");
+    std::fs::write(&tmp, serde_json::to_string_pretty(issues)?)
+        .and_then(|()| std::fs::rename(&tmp, &path))
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+fn bug_url_ok(bug: &str) -> bool {
+    bug.starts_with("https://") || bug.starts_with("http://")
+}
+
+/// Compiled known issues, for matching results against.
+#[derive(Default)]
+pub struct KnownIssues {
+    output_dir: PathBuf,
+    issues: Vec<(KnownIssue, glob::Pattern, Regex)>,
+}
+
+impl KnownIssues {
+    pub fn new(output_dir: &Path, issues: Vec<KnownIssue>) -> anyhow::Result<KnownIssues> {
+        let issues = issues
+            .into_iter()
+            .map(|i| {
+                let tests = glob::Pattern::new(&i.tests.replace('/', "."))
+                    .with_context(|| format!("invalid tests glob {:?}", i.tests))?;
+                let log = Regex::new(&i.log).with_context(|| format!("invalid log regex {:?}", i.log))?;
+                Ok((i, tests, log))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(KnownIssues { output_dir: output_dir.to_path_buf(), issues })
+    }
+
+    /// Load `<output_dir>/known-issues.json`.
+    pub fn load(output_dir: &Path) -> anyhow::Result<KnownIssues> {
+        KnownIssues::new(output_dir, read(output_dir)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The first known issue `key`'s result is an instance of, if it
+    /// failed. `log` is only called if some entry's glob matches and the
+    /// signature alone doesn't settle it.
+    pub fn matching(
+        &self,
+        key: &str,
+        r: &TestResult,
+        log: impl FnOnce() -> Option<String>,
+    ) -> Option<&KnownIssue> {
+        if r.status != TestStatus::Failed {
+            return None;
+        }
+        let candidates: Vec<_> = self.issues.iter().filter(|(_, tests, _)| tests.matches(key)).collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        if let Some(sig) = &r.signature {
+            if let Some((i, _, _)) = candidates.iter().find(|(_, _, re)| re.is_match(sig)) {
+                return Some(i);
+            }
+        }
+        let log = log()?;
+        candidates.iter().find(|(_, _, re)| re.is_match(&log)).map(|(i, _, _)| i)
+    }
+
+    /// matching(), with the log read from the result dir under the
+    /// output dir, if it's there.
+    pub fn lookup(&self, commit: &str, key: &str, r: &TestResult) -> Option<&KnownIssue> {
+        self.matching(key, r, || {
+            let dir = self.output_dir.join(commit).join(key);
+            match std::fs::read(dir.join("log.br")) {
+                Ok(buf) => crate::decompress_brotli(&buf).ok(),
+                Err(_) => std::fs::read(dir.join("log")).ok().map(|l| String::from_utf8_lossy(&l).into_owned()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn result(status: TestStatus, signature: Option<&str>) -> TestResult {
+        TestResult { signature: signature.map(String::from), ..TestResult::new(status, 1) }
+    }
+
+    #[test]
+    fn matches_glob_and_log() {
+        let known = KnownIssues::new(Path::new("/nonexistent"), vec![
+            KnownIssue {
+                tests: "fs/bcachefs/*".to_string(),
+                log: "circular locking".to_string(),
+                bug: "https://example.org/bug/1".to_string(),
+                note: String::new(),
+            },
+            KnownIssue {
+                tests: "*.fsck_*".to_string(),
+                log: "errors remain".to_string(),
+                bug: "https://example.org/bug/2".to_string(),
+                note: "flaky fsck".to_string(),
+            },
+        ])
+        .unwrap();
+
+        let lockdep = result(TestStatus::Failed, Some("WARNING: possible circular locking dependency detected"));
+        let bug = |key, r: &TestResult, log: &str| {
+            known.matching(key, r, || Some(log.to_string())).map(|i| i.bug.as_str())
+        };
+
+        // Signature matches; the log isn't needed.
+        assert_eq!(
+            known.matching("fs.bcachefs.ec@upstream_stable.one", &lockdep, || panic!()).map(|i| i.bug.as_str()),
+            Some("https://example.org/bug/1")
+        );
+        // Wrong test.
+        assert_eq!(bug("fs.ext4.one", &lockdep, ""), None);
+        // No signature, so the log decides.
+        let failed = result(TestStatus::Failed, None);
+        assert_eq!(bug("misc.fsck_thing", &failed, "...\nTEST FAILED: errors remain\n"), Some("https://example.org/bug/2"));
+        assert_eq!(bug("misc.fsck_thing", &failed, "TEST FAILED\n"), None);
+        // Only failures.
+        assert_eq!(bug("misc.fsck_thing", &result(TestStatus::Passed, None), "errors remain"), None);
+    }
+
+    #[test]
+    fn branch_counts_match_the_commit_page() {
+        let dir = TempDir::new("known-issues-branch");
+        let key = "misc.fsck_thing";
+        std::fs::create_dir_all(dir.join("c1").join(key)).unwrap();
+        std::fs::write(dir.join("c1").join(key).join("log"), "TEST FAILED: errors remain\n").unwrap();
+
+        let known = KnownIssues::new(&dir, vec![KnownIssue {
+            tests: "*.fsck_*".to_string(),
+            log: "errors remain".to_string(),
+            bug: "https://example.org/bug/2".to_string(),
+            note: String::new(),
+        }])
+        .unwrap();
+        let r = result(TestStatus::Failed, None);
+        assert!(known.lookup("c1", key, &r).is_some());
+
+        let results = vec![crate::CommitResults {
+            id: "c1".to_string(),
+            message: String::new(),
+            tests: [(key.to_string(), r)].into(),
+        }];
+        let e = &crate::branch_entries(results, &Default::default(), Some(&known))[0];
+        assert_eq!((e.failed, e.known), (0, 1));
+    }
+
+    #[test]
+    fn bad_entries_are_refused() {
+        let bad = KnownIssue {
+            tests: "*".to_string(),
+            log: "(unclosed".to_string(),
+            bug: String::new(),
+            note: String::new(),
+        };
+        assert!(KnownIssues::new(Path::new("/"), vec![bad]).is_err());
+
+        let dir = TempDir::new("known-issues");
+        let mut issue = KnownIssue {
+            tests: "*".to_string(),
+            log: "oops".to_string(),
+            bug: "javascript:alert(1)".to_string(),
+            note: String::new(),
+        };
+        assert!(write(&dir, std::slice::from_ref(&issue)).is_err());
+        issue.bug = "https://example.org/bug/3".to_string();
+        write(&dir, &[issue]).unwrap();
+        assert_eq!(read(&dir).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,157 @@
+//! KTAP (KUnit's output format) ingestion: one ktest subtest can be a
+//! whole KUnit suite, with only one verdict for all of it. After a
+//! batch is pulled, the daemon parses each subtest's log for KTAP
+//! results and writes them to a `cases` file in the result dir — one
+//! `<status>\t<name>` line per test case — which read_test_result()
+//! picks up as TestResult::cases, so the capnp carries them.
+//!
+//! Nested tests are flattened to their leaves, named by their path:
+//! `<suite>.<case>[.<param>]`.
+
+use crate::{TestCase, TestStatus};
+use regex::Regex;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// A result line, after the supervisor's elapsed-seconds prefix
+/// (`00012 `) and the kernel's console timestamp (`[  1.234] `), each
+/// optional: indentation, `ok`/`not ok`, number, optional `-`, name,
+/// and an optional `# DIRECTIVE`.
+static RESULT_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:\d+ )?(?:\[[^\]]*\] ?)?(\s*)(not )?ok \d+(?: -)?(?: ([^#]*?))?\s*(?:#\s*(\S+).*)?$").unwrap()
+});
+
+static VERSION_LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:^|\s)K?TAP version \d+").unwrap());
+
+/// Test cases reported in `log`; empty unless it contains a KTAP (or
+/// TAP) version line, so stray "ok 1" text elsewhere isn't picked up.
+pub fn parse(log: &str) -> Vec<TestCase> {
+    if !VERSION_LINE.is_match(log) {
+        return Vec::new();
+    }
+
+    // Results seen but not yet claimed by a parent, each with the
+    // leaves under it. A subtest's results come before its parent's
+    // line, indented deeper; the parent takes them all.
+    let mut open: Vec<(usize, Vec<TestCase>)> = Vec::new();
+
+    for line in log.lines() {
+        let Some(c) = RESULT_LINE.captures(line) else { continue };
+        let indent = c[1].len();
+        let name = c.get(3).map_or("", |m| m.as_str()).trim();
+        let status = match (c.get(2), c.get(4).map(|d| d.as_str().to_ascii_uppercase())) {
+            (_, Some(d)) if d == "SKIP" => TestStatus::Notrun,
+            (None, _) => TestStatus::Passed,
+            (Some(_), _) => TestStatus::Failed,
+        };
+
+        let first_child = open.iter().rposition(|(i, _)| *i <= indent).map_or(0, |p| p + 1);
+        let children: Vec<TestCase> = open
+            .drain(first_child..)
+            .flat_map(|(_, leaves)| leaves)
+            .map(|l| TestCase { name: format!("{}.{}", name, l.name), status: l.status })
+            .collect();
+
+        let leaves = if children.is_empty() {
+            vec![TestCase { name: name.to_string(), status }]
+        } else {
+            children
+        };
+        open.push((indent, leaves));
+    }
+
+    open.into_iter().flat_map(|(_, leaves)| leaves).collect()
+}
+
+/// Parse `<testdir>/log` and write `<testdir>/cases` if it reported any
+/// KTAP results. No log, or no KTAP in it, is not an error.
+pub fn ingest(testdir: &Path) -> std::io::Result<()> {
+    let log = match std::fs::read(testdir.join("log")) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let cases = parse(&String::from_utf8_lossy(&log));
+    if cases.is_empty() {
+        return Ok(());
+    }
+    let out: String = cases
+        .iter()
+        .map(|c| format!("{}\t{}\n", c.status.to_str(), c.name))
+        .collect();
+    std::fs::write(testdir.join("cases"), out)
+}
+
+/// The `cases` file ingest() wrote; empty if there is none.
+pub fn read_cases(testdir: &Path) -> Vec<TestCase> {
+    std::fs::read_to_string(testdir.join("cases"))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.split_once('\t'))
+        .map(|(status, name)| TestCase {
+            name: name.to_string(),
+            status: TestStatus::from_str(status),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(cases: &[TestCase]) -> Vec<(String, TestStatus)> {
+        cases.iter().map(|c| (c.name.clone(), c.status)).collect()
+    }
+
+    #[test]
+    fn nested_kunit_output() {
+        let log = "\
+boot noise
+[    1.000] KTAP version 1
+[    1.000] 1..2
+[    1.001]     KTAP version 1
+[    1.001]     # Subtest: example
+[    1.001]     1..3
+[    1.002]     ok 1 example_simple_test
+[    1.003]     not ok 2 example_failing_test
+[    1.004]         KTAP version 1
+[    1.004]         # Subtest: example_params_test
+[    1.004]         ok 1 example value 3
+[    1.004]         ok 2 example value 2 # SKIP unsupported
+[    1.005]     # example_params_test: pass:1 fail:0 skip:1 total:2
+[    1.005]     ok 3 example_params_test
+[    1.006] # example: pass:2 fail:1 skip:0 total:3
+[    1.006] not ok 1 example
+00002 [    1.007]     ok 1 - bcachefs_bkey_test
+00002 [    1.007] ok 2 bcachefs
+the test said ok 3 times
+";
+        use TestStatus::*;
+        assert_eq!(names(&parse(log)), [
+            ("example.example_simple_test".to_string(), Passed),
+            ("example.example_failing_test".to_string(), Failed),
+            ("example.example_params_test.example value 3".to_string(), Passed),
+            ("example.example_params_test.example value 2".to_string(), Notrun),
+            ("bcachefs.bcachefs_bkey_test".to_string(), Passed),
+        ]);
+    }
+
+    #[test]
+    fn needs_a_version_line() {
+        assert!(parse("ok 1 something\nnot ok 2 other\n").is_empty());
+    }
+
+    #[test]
+    fn cases_file_roundtrips() {
+        let dir = crate::test_util::TempDir::new("ktap");
+        std::fs::write(dir.join("log"), "TAP version 14\nok 1 a\nnot ok 2 b\n").unwrap();
+        ingest(&dir).unwrap();
+        let cases = read_cases(&dir);
+
+        assert_eq!(names(&cases), [
+            ("a".to_string(), TestStatus::Passed),
+            ("b".to_string(), TestStatus::Failed),
+        ]);
+    }
+}
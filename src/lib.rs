@@ -1,7 +1,7 @@
 use anyhow;
 use anyhow::Context;
 use serde_derive::Deserialize;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{create_dir_all, read_to_string, File};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
@@ -9,10 +9,23 @@ use std::sync::Mutex;
 
 pub mod api;
 pub mod branchlog_capnp;
+pub mod check;
 pub mod durations_capnp;
+pub mod index;
 pub mod jobs;
+pub mod junit;
+pub mod known_issues;
+pub mod ktap;
+pub mod metrics;
+pub mod notify;
+pub mod signature;
 pub mod testresult_capnp;
 pub mod users;
+
+#[cfg(test)]
+mod test_util;
+
+pub use users::Quarantine;
 pub use users::RcTestGroup;
 pub use users::Userrc;
 
@@ -46,10 +59,18 @@ pub fn git_get_commit(
 
 /// One worker host in the `executors` config: the daemon expands this
 /// into `slots` named executors (`<host>:0` … `<host>:slots-1`), each a
-/// slot it ssh's into to run jobs.
-#[derive(Deserialize, Clone, Debug)]
+/// slot it ssh's into (or, for a `local` host, runs directly) to run
+/// jobs.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct ExecutorHost {
     pub slots: u32,
+    /// Run this host's slots on the daemon's own machine instead of over
+    /// ssh: workspaces under the daemon user's ~/ktest-ci, alongside a
+    /// ktest checkout of their own synced from ktest_dir, and results
+    /// copied straight into output_dir. For single-box setups; the
+    /// host name is then just a label.
+    #[serde(default)]
+    pub local: bool,
 }
 
 /// One entry in the `repos` config, keyed by repo short name.
@@ -116,6 +137,54 @@ pub struct Ktestrc {
     /// Worker hosts for the push-mode daemon, keyed by hostname.
     #[serde(default)]
     pub executors: BTreeMap<String, ExecutorHost>,
+    /// Where ci-daemon writes its Prometheus metrics (e.g. into
+    /// node_exporter's textfile-collector dir). Defaults to
+    /// `<output_dir>/ci-daemon-metrics.prom`, scrapeable from the web.
+    #[serde(default)]
+    pub metrics_path: Option<PathBuf>,
+    /// Spool dir ci-daemon watches for fetch requests: a file containing
+    /// `<user>/<branch>` lines (e.g. written by a post-receive hook) gets
+    /// those branches fetched and the job window refilled right away.
+    /// Defaults to `<output_dir>/fetch-requests`.
+    #[serde(default)]
+    pub fetch_spool: Option<PathBuf>,
+    /// Seconds between ci-daemon's fetches of every configured branch
+    /// (default 60). With push hooks feeding `fetch_spool`, this is only
+    /// a backstop and can be much longer. Must be nonzero.
+    #[serde(default)]
+    pub fetch_interval: Option<u64>,
+    /// Also abandon running batches for commits no branch wants any
+    /// more (force-push, rebase), at their next step; by default only
+    /// their pending jobs are cancelled.
+    #[serde(default)]
+    pub cancel_running_obsolete: bool,
+    /// Consecutive infrastructure failures after which ci-daemon stops
+    /// handing work to an executor slot (or, across its slots, a whole
+    /// host) until a probe succeeds. Default 3; 0 never drains.
+    #[serde(default)]
+    pub drain_after_failures: Option<u32>,
+    /// Seconds between probes of a drained executor slot (default 300).
+    #[serde(default)]
+    pub probe_interval: Option<u64>,
+    /// SQLite index of every result, across commits (see `index`);
+    /// ci-daemon keeps it in sync with the capnps. Unset disables.
+    #[serde(default)]
+    pub results_index: Option<PathBuf>,
+    /// The dashboard cgi's URL, for links in failure notifications.
+    #[serde(default)]
+    pub dashboard_url: Option<String>,
+    /// SMTP relay ("host" or "host:port") for emailed notifications;
+    /// unset disables email.
+    #[serde(default)]
+    pub smtp_server: Option<String>,
+    /// From address of emailed notifications.
+    #[serde(default)]
+    pub notify_from: Option<String>,
+    /// A passing result taking more than this many times its typical
+    /// (median) duration in test_durations.capnp is flagged slow.
+    /// Default SLOW_FACTOR.
+    #[serde(default)]
+    pub slow_factor: Option<f64>,
 }
 
 impl Ktestrc {
@@ -150,16 +219,19 @@ impl Ktestrc {
     }
 }
 
-pub fn ktestrc_read() -> anyhow::Result<Ktestrc> {
+pub fn ktestrc_path() -> anyhow::Result<PathBuf> {
     // The cgi runs as www-data under Apache, with no usable $HOME, so
     // locate the config by walking up from our own binary until we find
     // the public_html/ktest-ci.json5 in the CI user's home directory.
     let exe = std::env::current_exe().context("locating own binary")?;
-    let path = exe
-        .ancestors()
+    exe.ancestors()
         .map(|dir| dir.join("public_html/ktest-ci.json5"))
         .find(|p| p.exists())
-        .context("public_html/ktest-ci.json5 not found above the binary")?;
+        .context("public_html/ktest-ci.json5 not found above the binary")
+}
+
+pub fn ktestrc_read() -> anyhow::Result<Ktestrc> {
+    let path = ktestrc_path()?;
     let config = read_to_string(&path)
         .with_context(|| format!("reading {}", path.display()))?;
     let ktestrc: Ktestrc = json_five::from_str(&config)
@@ -215,12 +287,12 @@ impl TestStatus {
             TestStatus::Inprogress
         } else if status.contains("PASSED") {
             TestStatus::Passed
+        } else if status.contains("FAILED TO RUN") || status.contains("NOT STARTED") {
+            TestStatus::FailedToRun
         } else if status.contains("FAILED") {
             TestStatus::Failed
         } else if status.contains("NOTRUN") {
             TestStatus::Notrun
-        } else if status.contains("FAILED TO RUN") || status.contains("NOT STARTED") {
-            TestStatus::FailedToRun
         } else {
             TestStatus::Unknown
         }
@@ -249,11 +321,100 @@ impl TestStatus {
     }
 }
 
+/// One earlier run of a subtest at the same commit, superseded by a
+/// re-run (see RcTestGroup::rerun_failed).
 #[derive(Copy, Clone, Debug)]
+pub struct TestAttempt {
+    pub status: TestStatus,
+    pub starttime: DateTime<Utc>,
+    pub duration: u64,
+}
+
+/// One KTAP test case inside a subtest's latest run (see ktap).
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    pub status: TestStatus,
+}
+
+#[derive(Clone, Debug)]
 pub struct TestResult {
     pub status: TestStatus,
     pub starttime: DateTime<Utc>,
     pub duration: u64,
+    /// Earlier runs at this commit, oldest first; the fields above are
+    /// the latest run.
+    pub attempts: Vec<TestAttempt>,
+    /// KTAP cases the latest run reported, if it ran a KUnit suite
+    pub cases: Vec<TestCase>,
+    /// Why the latest run failed, in one normalized line (see signature)
+    pub signature: Option<String>,
+    /// Set if the latest run was flagged slow (flag_slow()): the typical
+    /// duration it was judged against
+    pub slow: Option<u64>,
+    /// Set on a failure once bisection has found this is the commit that
+    /// broke it (TestResultsStore::mark_first_bad()).
+    pub first_bad: bool,
+}
+
+impl TestResult {
+    /// A single run started now, with nothing else recorded — no
+    /// earlier attempts, cases, signature, or slow or first-bad flag.
+    pub fn new(status: TestStatus, duration: u64) -> Self {
+        TestResult {
+            status,
+            starttime: Utc::now(),
+            duration,
+            attempts: Vec::new(),
+            cases: Vec::new(),
+            signature: None,
+            slow: None,
+            first_bad: false,
+        }
+    }
+
+    /// Runs recorded at this commit, the latest included. A re-run that
+    /// failed to run counts: it used up a re-run all the same.
+    pub fn runs(&self) -> u64 {
+        self.attempts.len() as u64 + 1
+    }
+
+    /// Of those, the runs that reached a verdict.
+    pub fn completed_runs(&self) -> u64 {
+        self.attempts
+            .iter()
+            .map(|a| a.status)
+            .chain(std::iter::once(self.status))
+            .filter(|s| matches!(s, TestStatus::Passed | TestStatus::Failed))
+            .count() as u64
+    }
+
+    /// How many of those runs failed.
+    pub fn failures(&self) -> u64 {
+        self.attempts
+            .iter()
+            .map(|a| a.status)
+            .chain(std::iter::once(self.status))
+            .filter(|s| *s == TestStatus::Failed)
+            .count() as u64
+    }
+
+    /// Names of the KTAP cases that failed.
+    pub fn failed_cases(&self) -> Vec<&str> {
+        self.cases
+            .iter()
+            .filter(|c| c.status == TestStatus::Failed)
+            .map(|c| c.name.as_str())
+            .collect()
+    }
+
+    /// `(failed, runs)` if the subtest was re-run and only some runs
+    /// failed — a flake rather than a consistent failure.
+    pub fn flaky(&self) -> Option<(u64, u64)> {
+        let failed = self.failures();
+        let runs = self.completed_runs();
+        (failed != 0 && failed != runs).then_some((failed, runs))
+    }
 }
 
 pub type TestResultsMap = BTreeMap<String, TestResult>;
@@ -267,7 +428,8 @@ pub type TestResultsMap = BTreeMap<String, TestResult>;
 /// and the capnp files are derived. Every `update` takes a global lock
 /// and holds it across the merge + capnp rewrite, so concurrent updates
 /// for the same commit serialise and the on-disk file always reflects
-/// the latest committed snapshot.
+/// the latest committed snapshot. The results index, if any, is written
+/// after the lock is dropped (see `index_keys`).
 ///
 /// Replaces the old read-disk-then-rewrite-capnp model, which raced when
 /// two batches finished for the same commit at the same time (an older
@@ -276,6 +438,10 @@ pub type TestResultsMap = BTreeMap<String, TestResult>;
 pub struct TestResultsStore {
     output_dir: PathBuf,
     by_commit: Mutex<HashMap<String, TestResultsMap>>,
+    index: Option<index::ResultsIndex>,
+    /// Held across each index write, so they land in the order of the
+    /// snapshots they write.
+    index_order: Mutex<()>,
 }
 
 impl TestResultsStore {
@@ -341,7 +507,21 @@ impl TestResultsStore {
         TestResultsStore {
             output_dir,
             by_commit: Mutex::new(by_commit),
+            index: None,
+            index_order: Mutex::new(()),
+        }
+    }
+
+    /// Keep `index` in sync from here on, resyncing it with everything
+    /// loaded first. If the resync fails the index is left out, and
+    /// marked unsynced for its other readers — a stale index is worse
+    /// than none.
+    pub fn with_index(mut self, index: index::ResultsIndex) -> Self {
+        match index.sync(&self.by_commit.lock().unwrap()) {
+            Ok(()) => self.index = Some(index),
+            Err(e) => eprintln!("TestResultsStore: index sync: {:#}", e),
         }
+        self
     }
 
     /// Look up one subtest's recorded status. `key` is the subtest's
@@ -366,14 +546,18 @@ impl TestResultsStore {
         if updates.is_empty() {
             return;
         }
-        let mut g = self.by_commit.lock().unwrap();
-        let entry = g.entry(commit.to_string()).or_default();
-        for (k, v) in updates {
-            entry.insert(k, v);
-        }
-        if let Err(e) = results_to_capnp(&self.output_dir, commit, None, entry) {
-            eprintln!("TestResultsStore: capnp for {}: {:#}", commit, e);
+        let keys: Vec<String> = updates.keys().cloned().collect();
+        {
+            let mut g = self.by_commit.lock().unwrap();
+            let entry = g.entry(commit.to_string()).or_default();
+            for (k, v) in updates {
+                entry.insert(k, v);
+            }
+            if let Err(e) = results_to_capnp(&self.output_dir, commit, None, entry) {
+                eprintln!("TestResultsStore: capnp for {}: {:#}", commit, e);
+            }
         }
+        self.index_keys(commit, &keys);
     }
 
     /// Convenience: update one subtest. Same semantics as `update`.
@@ -383,6 +567,19 @@ impl TestResultsStore {
         self.update(commit, m);
     }
 
+    /// Record that bisection found `commit` broke `key`: a `first_bad`
+    /// file in the result dir, for read_test_result(), and the flag in
+    /// the store and capnp. The views read it back (first_bad_commit()).
+    pub fn mark_first_bad(&self, commit: &str, key: &str) -> std::io::Result<()> {
+        let Some(mut r) = self.commit_results(commit).and_then(|mut m| m.remove(key)) else {
+            return Ok(());
+        };
+        std::fs::write(self.output_dir.join(commit).join(key).join("first_bad"), "")?;
+        r.first_bad = true;
+        self.update_one(commit, key.to_string(), r);
+        Ok(())
+    }
+
     /// Drop entries for these subtests, then rewrite the capnp. Used by
     /// the daemon when cleaning up stale IN PROGRESS markers — same lock
     /// discipline as `update`.
@@ -390,34 +587,60 @@ impl TestResultsStore {
         if keys.is_empty() {
             return;
         }
-        let mut g = self.by_commit.lock().unwrap();
-        let entry = match g.get_mut(commit) {
-            Some(e) => e,
-            None => return,
-        };
-        let mut changed = false;
-        for k in keys {
-            if entry.remove(k).is_some() {
-                changed = true;
+        {
+            let mut g = self.by_commit.lock().unwrap();
+            let entry = match g.get_mut(commit) {
+                Some(e) => e,
+                None => return,
+            };
+            let mut changed = false;
+            for k in keys {
+                if entry.remove(k).is_some() {
+                    changed = true;
+                }
+            }
+            if !changed {
+                return;
+            }
+            if let Err(e) = results_to_capnp(&self.output_dir, commit, None, entry) {
+                eprintln!("TestResultsStore: capnp for {}: {:#}", commit, e);
             }
         }
-        if !changed {
-            return;
-        }
-        if let Err(e) = results_to_capnp(&self.output_dir, commit, None, entry) {
-            eprintln!("TestResultsStore: capnp for {}: {:#}", commit, e);
+        self.index_keys(commit, keys);
+    }
+
+    /// Write `keys`' current state for `commit` to the index, outside the
+    /// store lock so index I/O doesn't stall lookups. The state is
+    /// snapshotted under `index_order`, so whichever of two racing
+    /// writers goes last writes the newer snapshot. On error the index
+    /// marks itself unsynced (ResultsIndex::write()).
+    fn index_keys(&self, commit: &str, keys: &[String]) {
+        let Some(index) = &self.index else { return };
+        let _order = self.index_order.lock().unwrap();
+        let (present, gone) = {
+            let g = self.by_commit.lock().unwrap();
+            let m = g.get(commit);
+            let mut present = TestResultsMap::new();
+            let mut gone = Vec::new();
+            for k in keys {
+                match m.and_then(|m| m.get(k)) {
+                    Some(r) => { present.insert(k.clone(), r.clone()); }
+                    None => gone.push(k.clone()),
+                }
+            }
+            (present, gone)
+        };
+        if let Err(e) = index.replace(commit, &present, &gone) {
+            eprintln!("TestResultsStore: index for {}: {:#}", commit, e);
         }
     }
 }
 
-/// Read one subtest's result dir (the `status` + `duration` files).
-/// Returns None if there's no `status` or it can't be read — caller
-/// treats that as "no result yet."
-pub fn read_test_result(testdir: &Path) -> Option<TestResult> {
+fn read_test_attempt(testdir: &Path) -> Option<TestAttempt> {
     let mut f = File::open(testdir.join("status")).ok()?;
     let mut status = String::new();
     f.read_to_string(&mut status).ok()?;
-    Some(TestResult {
+    Some(TestAttempt {
         status: TestStatus::from_str(&status),
         starttime: f.metadata().ok()?.modified().ok()?.into(),
         duration: read_to_string(testdir.join("duration"))
@@ -427,6 +650,127 @@ pub fn read_test_result(testdir: &Path) -> Option<TestResult> {
     })
 }
 
+/// Earlier runs of a re-run subtest: `<testdir>/attempts/<n>/`, each a
+/// result dir of its own (see archive_test_attempt()), oldest first.
+pub fn read_test_attempts(testdir: &Path) -> Vec<TestAttempt> {
+    let mut attempts: Vec<(u64, TestAttempt)> = testdir
+        .join("attempts")
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d.ok())
+        .filter_map(|d| {
+            let n = d.file_name().to_str()?.parse().ok()?;
+            Some((n, read_test_attempt(&d.path())?))
+        })
+        .collect();
+    attempts.sort_by_key(|(n, _)| *n);
+    attempts.into_iter().map(|(_, a)| a).collect()
+}
+
+/// Move a subtest's current result out of the way before re-running
+/// it: everything in `testdir` goes to `<testdir>/attempts/<n>/`, where
+/// read_test_attempts() finds it. Symlinks (the shared full_log.br) are
+/// dropped rather than moved — they're relative, and would dangle.
+pub fn archive_test_attempt(testdir: &Path) -> std::io::Result<()> {
+    let attempts = testdir.join("attempts");
+    let n = attempts
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d.ok())
+        .filter_map(|d| d.file_name().to_str()?.parse::<u64>().ok())
+        .max()
+        .map_or(0, |n| n + 1);
+    let dst = attempts.join(n.to_string());
+    create_dir_all(&dst)?;
+
+    for d in testdir.read_dir()?.filter_map(|d| d.ok()) {
+        if d.file_name() == "attempts" {
+            continue;
+        }
+        if d.file_type()?.is_symlink() {
+            std::fs::remove_file(d.path())?;
+        } else {
+            std::fs::rename(d.path(), dst.join(d.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Drop the run in `testdir` that never finished — its status, duration
+/// and logs — leaving its attempts/ alone.
+pub fn drop_test_run(testdir: &Path) -> std::io::Result<()> {
+    for d in testdir.read_dir()?.filter_map(|d| d.ok()) {
+        if d.file_name() == "attempts" {
+            continue;
+        }
+        if d.file_type()?.is_dir() {
+            std::fs::remove_dir_all(d.path())?;
+        } else {
+            std::fs::remove_file(d.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Undo archive_test_attempt() for a re-run that never reached a
+/// verdict: the run it moved aside becomes `testdir`'s result again,
+/// provided that one passed or failed — a re-run that couldn't run, or
+/// was abandoned, says nothing about the test and mustn't replace a real
+/// verdict. The run in `testdir` is archived as an attempt in its place
+/// if `record`, and dropped (drop_test_run()) if not.
+///
+/// Returns false, leaving `testdir` alone, if there's no such verdict to
+/// go back to.
+pub fn restore_test_attempt(testdir: &Path, record: bool) -> std::io::Result<bool> {
+    let latest = testdir
+        .join("attempts")
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d.ok())
+        .filter_map(|d| d.file_name().to_str()?.parse::<u64>().ok())
+        .max()
+        .map(|n| testdir.join("attempts").join(n.to_string()));
+    let Some(src) = latest.filter(|src| {
+        read_test_attempt(src)
+            .is_some_and(|a| matches!(a.status, TestStatus::Passed | TestStatus::Failed))
+    }) else {
+        return Ok(false);
+    };
+
+    if record {
+        archive_test_attempt(testdir)?;
+    } else {
+        drop_test_run(testdir)?;
+    }
+    for d in src.read_dir()?.filter_map(|d| d.ok()) {
+        std::fs::rename(d.path(), testdir.join(d.file_name()))?;
+    }
+    std::fs::remove_dir(&src)?;
+    Ok(true)
+}
+
+/// Read one subtest's result dir (the `status` + `duration` files, any
+/// KTAP `cases`, failure `signature`, `slow` and `first_bad` flags, and
+/// any earlier attempts).
+/// Returns None if there's no `status` or it can't be read — caller
+/// treats that as "no result yet."
+pub fn read_test_result(testdir: &Path) -> Option<TestResult> {
+    let latest = read_test_attempt(testdir)?;
+    Some(TestResult {
+        status: latest.status,
+        starttime: latest.starttime,
+        duration: latest.duration,
+        attempts: read_test_attempts(testdir),
+        cases: ktap::read_cases(testdir),
+        signature: signature::read_signature(testdir),
+        slow: read_to_string(testdir.join("slow")).ok().and_then(|s| s.trim().parse().ok()),
+        first_bad: testdir.join("first_bad").exists(),
+    })
+}
+
 fn commitdir_get_results_fs(output_dir: &Path, commit_id: &str) -> TestResultsMap {
     let mut results = BTreeMap::new();
 
@@ -465,7 +809,33 @@ fn results_to_capnp(
 
         result.set_name(name);
         result.set_duration(result_in.duration.try_into().unwrap());
+        result.set_starttime(result_in.starttime.timestamp());
         result.set_status(result_in.status);
+        if let Some(sig) = &result_in.signature {
+            result.set_signature(sig);
+        }
+        result.set_slow_mean(result_in.slow.unwrap_or(0));
+        result.set_first_bad(result_in.first_bad);
+
+        if !result_in.attempts.is_empty() {
+            let mut attempts =
+                result.reborrow().init_attempts(result_in.attempts.len().try_into().unwrap());
+            for (idx, a) in result_in.attempts.iter().enumerate() {
+                let mut attempt = attempts.reborrow().get(idx.try_into().unwrap());
+                attempt.set_starttime(a.starttime.timestamp());
+                attempt.set_duration(a.duration);
+                attempt.set_status(a.status);
+            }
+        }
+
+        if !result_in.cases.is_empty() {
+            let mut cases = result.init_cases(result_in.cases.len().try_into().unwrap());
+            for (idx, c) in result_in.cases.iter().enumerate() {
+                let mut case = cases.reborrow().get(idx.try_into().unwrap());
+                case.set_name(&c.name);
+                case.set_status(c.status);
+            }
+        }
     }
 
     // Unique temp name per call: many jobs for one commit can finish at
@@ -601,10 +971,34 @@ fn parse_test_results(f: &[u8]) -> anyhow::Result<CommitResultsCapnp> {
 
     let mut results = BTreeMap::new();
     for e in entries {
+        let mut attempts = Vec::new();
+        for a in e.get_attempts()? {
+            attempts.push(TestAttempt {
+                status: a.get_status()?,
+                starttime: Utc.timestamp_opt(a.get_starttime(), 0).unwrap(),
+                duration: a.get_duration(),
+            });
+        }
+
+        let mut cases = Vec::new();
+        for c in e.get_cases()? {
+            cases.push(TestCase {
+                name: c.get_name()?.to_string()?,
+                status: c.get_status()?,
+            });
+        }
+
         let r = TestResult {
             status: e.get_status()?,
             starttime: Utc.timestamp_opt(e.get_starttime(), 0).unwrap(),
             duration: e.get_duration(),
+            attempts,
+            cases,
+            signature: e.get_signature().ok()
+                .and_then(|s| s.to_string().ok())
+                .filter(|s| !s.is_empty()),
+            slow: Some(e.get_slow_mean()).filter(|m| *m != 0),
+            first_bad: e.get_first_bad(),
         };
 
         results.insert(e.get_name()?.to_string()?, r);
@@ -703,6 +1097,14 @@ fn commit_read_capnp(ktestrc: &Ktestrc, commit_id: &str) -> anyhow::Result<Vec<u
     Ok(std::fs::read(ktestrc.output_dir.join(format!("{}.capnp", commit_id)))?)
 }
 
+/// Decompress a log.br.
+pub fn decompress_brotli(data: &[u8]) -> anyhow::Result<String> {
+    let mut decoder = brotli::Decompressor::new(data, 4096);
+    let mut output = String::new();
+    decoder.read_to_string(&mut output)?;
+    Ok(output)
+}
+
 pub fn commitdir_get_results(ktestrc: &Ktestrc, commit_id: &str) -> anyhow::Result<TestResultsMap> {
     Ok(parse_test_results(&commit_read_capnp(ktestrc, commit_id)?)?.tests)
 }
@@ -859,6 +1261,44 @@ pub fn subtest_result_key(test: &str, subtest: &str, kernel: &str, env: &str) ->
     format!("{}.{}", result_basename(test, kernel, env), subtest.replace('/', "."))
 }
 
+/// A subtest_result_key() split back into its parts; kernel is the
+/// sanitized form and env the encoded one, each empty if absent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResultKey<'a> {
+    pub test: &'a str,
+    pub kernel: &'a str,
+    pub env: &'a str,
+    pub subtest: &'a str,
+}
+
+/// Inverse of subtest_result_key(), for keys with a kernel or env
+/// qualifier. An unqualified key (`fs.bcachefs.foo.bar`) doesn't say
+/// where the test ends and the subtest begins, so that's None. The
+/// last qualifier ends at its first `.`, so a kernel or env value
+/// containing one (and with no qualifier after it) splits wrongly.
+pub fn parse_result_key(key: &str) -> Option<ResultKey<'_>> {
+    let (test, rest) = key.split_once('@')?;
+    let (quals, subtest) = match rest.rsplit_once('@') {
+        Some((first, last)) => {
+            let (q, subtest) = last.split_once('.')?;
+            (vec![first, q], subtest)
+        }
+        None => {
+            let (q, subtest) = rest.split_once('.')?;
+            (vec![q], subtest)
+        }
+    };
+    let mut r = ResultKey { test, kernel: "", env: "", subtest };
+    for q in quals {
+        match q.contains('=') {
+            true if r.env.is_empty() => r.env = q,
+            false if r.kernel.is_empty() => r.kernel = q,
+            _ => return None,
+        }
+    }
+    Some(r)
+}
+
 /// Wire format for the env column in `jobs.<user>` and the TEST_JOB
 /// line: `K1=V1,K2=V2` (empty BTreeMap → empty string; the writer
 /// renders empty as the `-` sentinel). Keys/values must not contain
@@ -974,19 +1414,130 @@ mod kernel_key_tests {
             "boot.boot"
         );
     }
+
+    #[test]
+    fn parse_inverts_qualified_keys() {
+        let roundtrip = |kernel: &str, env: &str| {
+            let key = subtest_result_key("fs/bcachefs/fstests.ktest", "generic/001", kernel, env);
+            let k = parse_result_key(&key).map(|k| (k.test, k.kernel.to_string(), k.env, k.subtest));
+            assert_eq!(
+                k,
+                Some(("fs.bcachefs.fstests", sanitize_kernel(kernel), env, "generic.001")),
+                "{}", key
+            );
+        };
+        roundtrip("debian/forky", "");
+        roundtrip("", "ktest_x=1,Y=2");
+        roundtrip("upstream/stable-default", "ktest_x=1");
+
+        assert_eq!(parse_result_key("fs.bcachefs.ec.ec"), None);
+        assert_eq!(parse_result_key("boot@upstream_stable"), None);
+    }
 }
 
-#[derive(Debug)]
+/// One result key's history, as gen-avg-duration writes it to
+/// test_durations.capnp.
+#[derive(Debug, Default, PartialEq)]
 pub struct TestStats {
     pub nr: u64,
     pub passed: u64,
     pub failed: u64,
+    /// Mean duration, seconds
+    pub duration: u64,
+    /// Median, 90th percentile and longest duration of the runs with a
+    /// verdict: unlike the mean, p50 and p90 aren't thrown off by one
+    /// hung run
+    pub p50: u64,
+    pub p90: u64,
+    pub max: u64,
+    /// Failed fraction of the newest RECENT_RUNS verdicts
+    pub recent_failure_rate: f64,
+    /// Start of the newest run, unix seconds
+    pub last_seen: i64,
+}
+
+/// Verdicts TestStats::recent_failure_rate covers.
+pub const RECENT_RUNS: usize = 20;
+
+/// One run of a result key, for TestStats::from_samples().
+#[derive(Clone, Copy, Debug)]
+pub struct DurationSample {
     pub duration: u64,
+    pub status: TestStatus,
+    /// Unix seconds
+    pub starttime: i64,
+}
+
+impl TestStats {
+    pub fn from_samples(samples: &[DurationSample]) -> TestStats {
+        let mut verdicts: Vec<&DurationSample> = samples
+            .iter()
+            .filter(|s| matches!(s.status, TestStatus::Passed | TestStatus::Failed))
+            .collect();
+
+        let mut durations: Vec<u64> = verdicts.iter().map(|s| s.duration).collect();
+        durations.sort_unstable();
+        // Nearest rank
+        let percentile = |p: usize| match durations.len() {
+            0 => 0,
+            n => durations[(n * p).div_ceil(100).max(1) - 1],
+        };
+
+        verdicts.sort_by_key(|s| std::cmp::Reverse(s.starttime));
+        let recent = &verdicts[..verdicts.len().min(RECENT_RUNS)];
+        let recent_failed = recent.iter().filter(|s| s.status == TestStatus::Failed).count();
+
+        let nr = samples.len() as u64;
+        TestStats {
+            nr,
+            passed: samples.iter().filter(|s| s.status == TestStatus::Passed).count() as u64,
+            failed: samples.iter().filter(|s| s.status == TestStatus::Failed).count() as u64,
+            duration: samples.iter().map(|s| s.duration).sum::<u64>() / nr.max(1),
+            p50: percentile(50),
+            p90: percentile(90),
+            max: durations.last().copied().unwrap_or(0),
+            recent_failure_rate: if recent.is_empty() {
+                0.0
+            } else {
+                recent_failed as f64 / recent.len() as f64
+            },
+            last_seen: samples.iter().map(|s| s.starttime).max().unwrap_or(0),
+        }
+    }
+
+    /// How long a run usually takes: p50, or the mean from a
+    /// test_durations.capnp written before there were percentiles.
+    pub fn typical_duration(&self) -> u64 {
+        if self.p50 != 0 { self.p50 } else { self.duration }
+    }
 }
 
 use durations_capnp::durations;
 pub fn test_stats(durations: Option<&[u8]>, test: &str, subtest: &str,
                   kernel: &str, env: &str) -> Option<TestStats> {
+    // Durations are stored under the on-disk result-dir key, which
+    // carries @kernel[@env] — match that, not subtest_full_name(),
+    // or every lookup misses once kernels/env are non-empty.
+    result_key_stats(durations, &subtest_result_key(test, subtest, kernel, env))
+}
+
+/// Where gen-avg-duration aggregates a subtest's runs across every
+/// kernel and env: `*` is never a kernel name, so unlike
+/// `<test>.<subtest>` this can't collide with a real result key.
+pub fn cross_kernel_key(test: &str, subtest: &str) -> String {
+    format!("{}@*.{}", test, subtest.replace('/', "."))
+}
+
+/// A subtest's stats over every kernel and env it ran on. A test only
+/// ever run unqualified has no aggregate — its own stats are the same
+/// thing.
+pub fn cross_kernel_stats(durations: Option<&[u8]>, test: &str, subtest: &str) -> Option<TestStats> {
+    result_key_stats(durations, &cross_kernel_key(test, subtest))
+        .or_else(|| test_stats(durations, test, subtest, "", ""))
+}
+
+/// test_stats() for a result key as is.
+pub fn result_key_stats(durations: Option<&[u8]>, key: &str) -> Option<TestStats> {
     if let Some(d) = durations {
         let mut d = d;
 
@@ -1011,12 +1562,6 @@ pub fn test_stats(durations: Option<&[u8]>, test: &str, subtest: &str,
         }
         let d = d.unwrap();
 
-        // Durations are stored under the on-disk result-dir key, which
-        // carries @kernel[@env] — match that, not subtest_full_name(),
-        // or every lookup misses once kernels/env are non-empty.
-        let full_test = subtest_result_key(test, subtest, kernel, env);
-        let full_test = full_test.as_str();
-
         let mut l = 0;
         let mut r = d.len();
 
@@ -1040,7 +1585,7 @@ pub fn test_stats(durations: Option<&[u8]>, test: &str, subtest: &str,
             let d_m_test = d_m_test.unwrap().to_str().unwrap();
 
             use std::cmp::Ordering::*;
-            match full_test.cmp(d_m_test) {
+            match key.cmp(d_m_test) {
                 Less => r = m,
                 Greater => l = m + 1,
                 Equal => {
@@ -1049,6 +1594,11 @@ pub fn test_stats(durations: Option<&[u8]>, test: &str, subtest: &str,
                         passed: d_m.get_passed(),
                         failed: d_m.get_failed(),
                         duration: d_m.get_duration(),
+                        p50: d_m.get_p50(),
+                        p90: d_m.get_p90(),
+                        max: d_m.get_max(),
+                        recent_failure_rate: d_m.get_recent_failure_rate(),
+                        last_seen: d_m.get_last_seen(),
                     })
                 }
             }
@@ -1058,6 +1608,96 @@ pub fn test_stats(durations: Option<&[u8]>, test: &str, subtest: &str,
     None
 }
 
+/// Default Ktestrc::slow_factor.
+pub const SLOW_FACTOR: f64 = 3.0;
+
+/// Verdicts a key needs in test_durations.capnp before its typical
+/// duration is trusted.
+const SLOW_MIN_RUNS: u64 = 3;
+
+/// Seconds a run must be over its typical duration to be flagged slow,
+/// however many times longer: a few seconds' jitter in a short test
+/// isn't a performance regression.
+const SLOW_MIN_DELTA: u64 = 30;
+
+/// Judged against the median, not the mean: the mean counts 0s runs
+/// that never got a verdict, and a single hung run drags it up for good.
+fn is_slow(duration: u64, stats: &TestStats, factor: f64) -> bool {
+    let typical = stats.typical_duration();
+    stats.passed + stats.failed >= SLOW_MIN_RUNS
+        && typical != 0
+        && duration >= typical + SLOW_MIN_DELTA
+        && duration as f64 > typical as f64 * factor
+}
+
+/// If the result in `testdir` passed but took more than `factor` times
+/// `key`'s typical duration in `durations` (test_durations.capnp),
+/// write that to `<testdir>/slow`, for read_test_result(). Failures are
+/// left alone — they already stand out; a performance regression often shows up
+/// first as a pass that got several times slower.
+pub fn flag_slow(testdir: &Path, key: &str, durations: Option<&[u8]>, factor: f64) -> std::io::Result<()> {
+    let Some(r) = read_test_attempt(testdir) else { return Ok(()) };
+    if r.status != TestStatus::Passed {
+        return Ok(());
+    }
+    match result_key_stats(durations, key) {
+        Some(stats) if is_slow(r.duration, &stats, factor) => {
+            std::fs::write(testdir.join("slow"), format!("{}\n", stats.typical_duration()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod slow_tests {
+    use super::*;
+
+    #[test]
+    fn slow_needs_history_and_margin() {
+        let typical = |passed, p50| TestStats { nr: passed, passed, p50, duration: p50, ..Default::default() };
+        assert!(is_slow(400, &typical(10, 100), 3.0));
+        assert!(!is_slow(250, &typical(10, 100), 3.0));
+        // Too few runs to trust.
+        assert!(!is_slow(400, &typical(2, 100), 3.0));
+        // 3x, but only seconds: noise.
+        assert!(!is_slow(20, &typical(10, 5), 3.0));
+        assert!(is_slow(200, &typical(10, 100), 1.5));
+
+        // Ten passes around 100s. Runs that never got a verdict would
+        // drag the mean down, a hung run up; the median stays put.
+        let mut samples: Vec<DurationSample> = (0..10)
+            .map(|i| DurationSample { duration: 95 + i, status: TestStatus::Passed, starttime: i as i64 })
+            .collect();
+        samples.extend((0..30).map(|i| DurationSample { duration: 0, status: TestStatus::Inprogress, starttime: i }));
+        assert!(!is_slow(150, &TestStats::from_samples(&samples), 3.0));
+        samples.push(DurationSample { duration: 7200, status: TestStatus::Failed, starttime: 10 });
+        assert!(is_slow(400, &TestStats::from_samples(&samples), 3.0));
+    }
+
+    #[test]
+    fn stats_from_samples() {
+        use TestStatus::*;
+        let mut samples: Vec<DurationSample> = (0..30)
+            .map(|i| DurationSample {
+                duration: 100 + i,
+                // The newest ten failed.
+                status: if i >= 20 { Failed } else { Passed },
+                starttime: i as i64,
+            })
+            .collect();
+        // One hung run, and one that never got a verdict.
+        samples.push(DurationSample { duration: 7200, status: Failed, starttime: 5 });
+        samples.push(DurationSample { duration: 0, status: Inprogress, starttime: 40 });
+
+        let s = TestStats::from_samples(&samples);
+        assert_eq!((s.nr, s.passed, s.failed), (32, 20, 11));
+        assert_eq!((s.p50, s.p90, s.max), (115, 127, 7200));
+        assert_eq!(s.recent_failure_rate, 0.5);
+        assert_eq!(s.last_seen, 40);
+        assert_eq!(TestStats::from_samples(&[]), TestStats::default());
+    }
+}
+
 // Shared query functions (used by both CGI and CLI)
 
 use regex::Regex;
@@ -1065,7 +1705,7 @@ use regex::Regex;
 pub fn filter_results(r: TestResultsMap, tests_matching: &Regex) -> TestResultsMap {
     r.iter()
         .filter(|i| tests_matching.is_match(&i.0))
-        .map(|(k, v)| (k.clone(), *v))
+        .map(|(k, v)| (k.clone(), v.clone()))
         .collect()
 }
 
@@ -1075,14 +1715,15 @@ pub struct CommitResults {
     pub tests: TestResultsMap,
 }
 
-pub fn branch_get_results(
+/// Commit ids and messages down `commit`, or else `user`/`branch`, as
+/// deep as gc-results keeps results.
+fn branch_commits(
     repo: &git2::Repository,
     ktest: &Ktestrc,
     user: Option<&str>,
     branch: Option<&str>,
     commit: Option<&str>,
-    tests_matching: &Regex,
-) -> Result<Vec<CommitResults>, String> {
+) -> Result<Vec<(String, String)>, String> {
     let branch_or_commit = if let Some(commit) = commit {
         commit.to_string()
     } else {
@@ -1105,12 +1746,23 @@ pub fn branch_get_results(
     // Match gc-results' liveness depth so the viewer can reach everything
     // that's still on disk.
     let depth: usize = ktest.keep_results_commits.unwrap_or(500) as usize;
-    let commits: Vec<(String, String)> = walk
+    Ok(walk
         .filter_map(|i| i.ok())
         .filter_map(|i| repo.find_commit(i).ok())
         .take(depth)
         .map(|c| (c.id().to_string(), c.message().unwrap_or("").to_string()))
-        .collect();
+        .collect())
+}
+
+pub fn branch_get_results(
+    repo: &git2::Repository,
+    ktest: &Ktestrc,
+    user: Option<&str>,
+    branch: Option<&str>,
+    commit: Option<&str>,
+    tests_matching: &Regex,
+) -> Result<Vec<CommitResults>, String> {
+    let commits = branch_commits(repo, ktest, user, branch, commit)?;
 
     // Phase 2: prefetch all capnp files in parallel (HTTP/2 multiplexed)
     if let Some(ref base_url) = ktest.ci_url {
@@ -1120,7 +1772,6 @@ pub fn branch_get_results(
 
     // Phase 3: build results from cache (now all filesystem reads)
     let mut nr_empty = 0;
-    let mut nr_commits = 0;
     let mut ret: Vec<CommitResults> = Vec::new();
 
     for (id, message) in commits {
@@ -1139,11 +1790,6 @@ pub fn branch_get_results(
         }
 
         ret.push(r);
-
-        nr_commits += 1;
-        if nr_commits > depth {
-            break;
-        }
     }
 
     while !ret.is_empty() && ret[ret.len() - 1].tests.is_empty() {
@@ -1153,6 +1799,11 @@ pub fn branch_get_results(
     Ok(ret)
 }
 
+/// The first 12 characters of a commit id, or all of a shorter one.
+pub fn short_commit(c: &str) -> &str {
+    &c[..c.len().min(12)]
+}
+
 pub fn format_duration(secs: u64) -> String {
     if secs < 60 {
         format!("{}s", secs)
@@ -1183,6 +1834,636 @@ pub fn last_good_line(results: &[CommitResults], test: &str) -> String {
     format!(">= {}", results.len())
 }
 
+/// The commit that broke `test`, once the daemon has bisected it
+/// (TestResult::first_bad): `results` is newest-first from the commit
+/// being viewed, which must have `test` Failed. Walks back as far as
+/// the first Passed, for the failure marked as the first bad one; None
+/// if there isn't one yet.
+pub fn first_bad_commit<'a>(results: &'a [CommitResults], test: &str) -> Option<&'a str> {
+    for r in results {
+        match r.tests.get(test) {
+            Some(t) if t.status == TestStatus::Failed && t.first_bad => return Some(&r.id),
+            Some(t) if t.status == TestStatus::Passed => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Tests failing at the tip of a branch that passed at some older commit.
+/// `results` is newest-first, as from branch_get_results(); the tip is
+/// the newest commit with any results. Each regression carries the
+/// newest commit where the test still passed.
+pub fn branch_regressions(results: &[CommitResults]) -> api::Regressions {
+    let Some(tip_idx) = results.iter().position(|r| !r.tests.is_empty()) else {
+        return api::Regressions { commit: String::new(), regressions: Vec::new() };
+    };
+    let history = &results[tip_idx..];
+    let tip = &history[0];
+
+    let regressions = tip
+        .tests
+        .iter()
+        .filter(|(_, t)| t.status == TestStatus::Failed)
+        .filter_map(|(name, _)| {
+            let (age, good) = history
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(_, r)| r.tests.get(name).is_some_and(|t| t.status == TestStatus::Passed))?;
+            Some(api::Regression {
+                name: name.clone(),
+                last_passed: good.id.clone(),
+                last_passed_subject: good.message.lines().next().unwrap_or("").to_string(),
+                age,
+                first_bad: first_bad_commit(history, name).map(String::from),
+            })
+        })
+        .collect();
+
+    api::Regressions { commit: tip.id.clone(), regressions }
+}
+
+/// `test`'s result at every commit in `results` (newest-first, as from
+/// branch_get_results()), including the ones where it has none.
+pub fn test_history(results: &[CommitResults], test: &str) -> api::TestHistory {
+    api::TestHistory {
+        test: test.to_string(),
+        commits: results
+            .iter()
+            .map(|r| api::HistoryEntry {
+                commit: r.id.clone(),
+                subject: r.message.lines().next().unwrap_or("").to_string(),
+                result: r.tests.get(test).map(|t| api::HistoryResult {
+                    status: t.status.to_str().to_string(),
+                    duration: t.duration,
+                    starttime: t.starttime.timestamp(),
+                }),
+            })
+            .collect(),
+    }
+}
+
+/// test_history() for `test` (an exact result key) down a branch, as
+/// branch_get_results() walks it. With a synced results index that's
+/// one query instead of a capnp per commit; otherwise, or if the index
+/// can't be read, the capnps.
+pub fn branch_test_history(
+    repo: &git2::Repository,
+    ktest: &Ktestrc,
+    user: Option<&str>,
+    branch: Option<&str>,
+    commit: Option<&str>,
+    test: &str,
+) -> Result<api::TestHistory, String> {
+    let runs = ktest.results_index.as_ref().and_then(|path| {
+        let index = index::ResultsIndex::open(path).ok()?;
+        if !index.is_synced().ok()? {
+            return None;
+        }
+        index.history(test).ok()
+    });
+    let Some(mut runs) = runs else {
+        let exact = Regex::new(&format!("^{}$", regex::escape(test))).unwrap();
+        let results = branch_get_results(repo, ktest, user, branch, commit, &exact)?;
+        return Ok(test_history(&results, test));
+    };
+
+    // Same cutoffs as branch_get_results(): stop after 100 commits in a
+    // row without a result, and drop the empty tail.
+    let mut commits = Vec::new();
+    let mut nr_empty = 0;
+    for (id, message) in branch_commits(repo, ktest, user, branch, commit)? {
+        let result = runs.remove(&id);
+        if result.is_some() {
+            nr_empty = 0;
+        } else {
+            nr_empty += 1;
+            if nr_empty > 100 {
+                break;
+            }
+        }
+        commits.push(api::HistoryEntry {
+            commit: id,
+            subject: message.lines().next().unwrap_or("").to_string(),
+            result,
+        });
+    }
+    while commits.last().is_some_and(|c| c.result.is_none()) {
+        commits.pop();
+    }
+
+    Ok(api::TestHistory { test: test.to_string(), commits })
+}
+
+/// Default window for failure_clusters(), in commits from the tip.
+pub const FAILURE_CLUSTER_COMMITS: usize = 50;
+
+/// Every Failed result in `results` (newest-first, as from
+/// branch_get_results()) grouped by its signature, so one bug hitting
+/// many tests, kernels and commits shows up as one cluster.
+pub fn failure_clusters(results: &[CommitResults]) -> api::FailureClusters {
+    let keys = KeySplitter::new(results.iter().flat_map(|r| r.tests.keys()).map(String::as_str));
+    let mut clusters: BTreeMap<&str, api::FailureCluster> = BTreeMap::new();
+    let mut unclassified = 0;
+
+    for r in results {
+        for (key, t) in r.tests.iter().filter(|(_, t)| t.status == TestStatus::Failed) {
+            let Some(sig) = t.signature.as_deref() else {
+                unclassified += 1;
+                continue;
+            };
+            let (test, kernel, _, subtest) = keys.split(key);
+            let test = if subtest.is_empty() { test.to_string() } else { format!("{}.{}", test, subtest) };
+
+            let c = clusters.entry(sig).or_insert_with(|| api::FailureCluster {
+                signature: sig.to_string(),
+                count: 0,
+                tests: Vec::new(),
+                kernels: Vec::new(),
+                first_seen: String::new(),
+                first_seen_subject: String::new(),
+                last_seen: r.id.clone(),
+                last_seen_key: key.clone(),
+            });
+            c.count += 1;
+            // Walking newest to oldest: the last commit seen is the first.
+            if c.first_seen != r.id {
+                c.first_seen = r.id.clone();
+                c.first_seen_subject = r.message.lines().next().unwrap_or("").to_string();
+            }
+            if !c.tests.contains(&test) {
+                c.tests.push(test);
+            }
+            if !c.kernels.iter().any(|k| k == kernel) {
+                c.kernels.push(kernel.to_string());
+            }
+        }
+    }
+
+    let mut clusters: Vec<_> = clusters.into_values().collect();
+    for c in &mut clusters {
+        c.tests.sort();
+        c.kernels.sort();
+    }
+    // Stable: ties stay in signature order.
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.count));
+
+    api::FailureClusters { commits: results.len(), clusters, unclassified }
+}
+
+/// Default duration threshold for compare_results(): a change of more
+/// than half.
+pub const COMPARE_DURATION_THRESHOLD: f64 = 0.5;
+
+/// Duration changes smaller than this are noise, whatever the ratio.
+const COMPARE_DURATION_MIN_DELTA: u64 = 30;
+
+/// Splits result keys into (test, kernel, env, subtest), for views that
+/// group them by test. parse_result_key() can't split an unqualified key:
+/// test names and subtests both have dots in them (`fs.bcachefs.ec`,
+/// fstests' `generic.001`). So the boundary is taken from the test names
+/// spelled out by the qualified keys among the same results — a test's
+/// default-kernel results then line up with its `@kernel` ones — and
+/// only failing that is it the last `.`.
+pub(crate) struct KeySplitter<'a> {
+    tests: BTreeSet<&'a str>,
+}
+
+impl<'a> KeySplitter<'a> {
+    pub(crate) fn new(keys: impl IntoIterator<Item = &'a str>) -> Self {
+        KeySplitter {
+            tests: keys.into_iter().filter_map(parse_result_key).map(|k| k.test).collect(),
+        }
+    }
+
+    pub(crate) fn split<'k>(&self, key: &'k str) -> (&'k str, &'k str, &'k str, &'k str) {
+        if let Some(k) = parse_result_key(key) {
+            return (k.test, k.kernel, k.env, k.subtest);
+        }
+        let dot = key
+            .rmatch_indices('.')
+            .map(|(i, _)| i)
+            .find(|&i| self.tests.contains(&key[..i]))
+            .or_else(|| key.rfind('.'));
+        match dot {
+            Some(i) => (&key[..i], "", "", &key[i + 1..]),
+            None => (key, "", "", ""),
+        }
+    }
+}
+
+/// What changed from results `a` to results `b` — two commits, from any
+/// branch or repo. A verdict kept but with the duration changed by more
+/// than `threshold` (a fraction of the larger) is Slower/Faster.
+pub fn compare_results(
+    a: &TestResultsMap,
+    b: &TestResultsMap,
+    threshold: f64,
+) -> Vec<api::CompareGroup> {
+    use api::ChangeKind::*;
+
+    let side = |r: &TestResult| api::CompareSide {
+        status: r.status.to_str().to_string(),
+        duration: r.duration,
+    };
+    let verdict = |s: TestStatus| s == TestStatus::Passed || s == TestStatus::Failed;
+
+    let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    let splitter = KeySplitter::new(keys.iter().map(|k| k.as_str()));
+    let mut groups: BTreeMap<(&str, &str, &str), Vec<api::Change>> = BTreeMap::new();
+
+    for key in keys {
+        let (ra, rb) = (a.get(key), b.get(key));
+        let kind = match (ra, rb) {
+            (Some(_), None) => Missing,
+            (None, Some(_)) => Added,
+            (Some(ra), Some(rb)) if ra.status != rb.status => match (ra.status, rb.status) {
+                (_, TestStatus::Failed) => NewlyFailing,
+                (TestStatus::Failed, TestStatus::Passed) => NewlyPassing,
+                _ => Changed,
+            },
+            (Some(ra), Some(rb)) if verdict(rb.status) => {
+                let delta = ra.duration.abs_diff(rb.duration);
+                let longer = ra.duration.max(rb.duration);
+                if delta < COMPARE_DURATION_MIN_DELTA || (delta as f64) <= longer as f64 * threshold {
+                    continue;
+                }
+                if rb.duration > ra.duration { Slower } else { Faster }
+            }
+            _ => continue,
+        };
+
+        let (test, kernel, env, subtest) = splitter.split(key);
+        groups.entry((test, kernel, env)).or_default().push(api::Change {
+            subtest: subtest.to_string(),
+            key: key.clone(),
+            kind,
+            a: ra.map(side),
+            b: rb.map(side),
+        });
+    }
+
+    groups
+        .into_iter()
+        .map(|((test, kernel, env), changes)| api::CompareGroup {
+            test: test.to_string(),
+            kernel: kernel.to_string(),
+            env: env.to_string(),
+            changes,
+        })
+        .collect()
+}
+
+/// Pivot one commit's results: a row per (test, subtest), a column per
+/// kernel/env qualifier, so a failure confined to one kernel or env
+/// stands out.
+pub fn results_matrix(commit: &str, tests: &TestResultsMap) -> api::Matrix {
+    let keys = KeySplitter::new(tests.keys().map(String::as_str));
+    let parts: Vec<_> = tests
+        .iter()
+        .map(|(key, r)| (keys.split(key), key, r))
+        .collect();
+
+    let columns: Vec<(&str, &str)> = parts
+        .iter()
+        .map(|((_, kernel, env, _), _, _)| (*kernel, *env))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut rows: BTreeMap<(&str, &str), Vec<Option<api::MatrixCell>>> = BTreeMap::new();
+    for ((test, kernel, env, subtest), key, r) in &parts {
+        let col = columns.binary_search(&(*kernel, *env)).unwrap();
+        rows.entry((*test, *subtest)).or_insert_with(|| {
+            std::iter::repeat_with(|| None).take(columns.len()).collect()
+        })[col] = Some(api::MatrixCell {
+            key: key.to_string(),
+            status: r.status.to_str().to_string(),
+        });
+    }
+
+    api::Matrix {
+        commit: commit.to_string(),
+        columns: columns
+            .iter()
+            .map(|(kernel, env)| api::MatrixColumn {
+                kernel: kernel.to_string(),
+                env: env.to_string(),
+            })
+            .collect(),
+        rows: rows
+            .into_iter()
+            .map(|((test, subtest), cells)| api::MatrixRow {
+                test: test.to_string(),
+                subtest: subtest.to_string(),
+                cells,
+            })
+            .collect(),
+    }
+}
+
+/// Expand a short commit prefix to a full hash by matching against the
+/// result dirs in output_dir — the authoritative set of tested commits,
+/// and the same place commitdir_get_results_full reads from. Avoids
+/// guessing which git repo a bare commit hash belongs to.
+pub fn resolve_commit_prefix(ktest: &Ktestrc, prefix: &str) -> anyhow::Result<String> {
+    if prefix.len() >= 40 {
+        return Ok(prefix.to_string());
+    }
+
+    let mut matches: Vec<String> = std::fs::read_dir(&ktest.output_dir)?
+        .filter_map(|d| d.ok())
+        .filter_map(|d| d.file_name().into_string().ok())
+        .filter(|name| name.len() == 40 && name.starts_with(prefix))
+        .collect();
+    matches.sort();
+    matches.dedup();
+
+    match matches.len() {
+        0 => anyhow::bail!("no test results for commit {}", prefix),
+        1 => Ok(matches.pop().unwrap()),
+        n => anyhow::bail!("ambiguous commit prefix {} ({} matches)", prefix, n),
+    }
+}
+
+/// `compare_results()` of two tested commits, given as (prefixes of)
+/// hashes.
+pub fn compare_commits(
+    ktest: &Ktestrc,
+    a: &str,
+    b: &str,
+    threshold: f64,
+) -> anyhow::Result<api::Comparison> {
+    let a = resolve_commit_prefix(ktest, a)?;
+    let b = resolve_commit_prefix(ktest, b)?;
+    let ra = commitdir_get_results_full(ktest, &a)?;
+    let rb = commitdir_get_results_full(ktest, &b)?;
+    Ok(api::Comparison {
+        groups: compare_results(&ra.tests, &rb.tests, threshold),
+        a,
+        b,
+    })
+}
+
+#[cfg(test)]
+mod commit_results_tests {
+    use super::*;
+    use api::ChangeKind::*;
+
+    fn results(tests: &[(&str, TestStatus, u64)]) -> TestResultsMap {
+        tests
+            .iter()
+            .map(|(name, status, duration)| {
+                (name.to_string(), TestResult::new(*status, *duration))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn classifies_and_groups_changes() {
+        use TestStatus::*;
+        let a = results(&[
+            ("fs.ec@upstream_stable.one", Passed, 100),
+            ("fs.ec@upstream_stable.two", Failed, 100),
+            ("fs.ec@upstream_stable@X=1.one", Passed, 100),
+            ("fs.ec@upstream_stable@X=1.two", Passed, 100),
+            ("fs.ec@upstream_stable@X=1.three", Passed, 10),
+            ("boot.boot", Passed, 20),
+            ("gone.gone", Passed, 20),
+        ]);
+        let b = results(&[
+            ("fs.ec@upstream_stable.one", Failed, 100),
+            ("fs.ec@upstream_stable.two", Passed, 100),
+            ("fs.ec@upstream_stable@X=1.one", Passed, 250),
+            ("fs.ec@upstream_stable@X=1.two", FailedToRun, 0),
+            // Doubled, but by less than the minimum delta
+            ("fs.ec@upstream_stable@X=1.three", Passed, 20),
+            ("boot.boot", Passed, 20),
+            ("new.new", Inprogress, 0),
+        ]);
+
+        let groups = compare_results(&a, &b, COMPARE_DURATION_THRESHOLD);
+        let flat: Vec<_> = groups
+            .iter()
+            .flat_map(|g| g.changes.iter().map(move |c| {
+                (g.test.as_str(), g.kernel.as_str(), g.env.as_str(), c.subtest.as_str(), c.kind)
+            }))
+            .collect();
+        assert_eq!(flat, [
+            ("fs.ec", "upstream_stable", "", "one", NewlyFailing),
+            ("fs.ec", "upstream_stable", "", "two", NewlyPassing),
+            ("fs.ec", "upstream_stable", "X=1", "one", Slower),
+            ("fs.ec", "upstream_stable", "X=1", "two", Changed),
+            ("gone", "", "", "gone", Missing),
+            ("new", "", "", "new", Added),
+        ]);
+    }
+
+    #[test]
+    fn matrix_pivots_on_kernel_and_env() {
+        use TestStatus::*;
+        let m = results_matrix("c", &results(&[
+            ("fs.ec@upstream_stable.one", Passed, 1),
+            ("fs.ec@upstream_stable-kasan.one", Failed, 1),
+            ("fs.ec@upstream_stable@X=1.one", Passed, 1),
+            ("fs.ec@upstream_stable.two", Passed, 1),
+            ("boot.boot", Passed, 1),
+        ]));
+
+        let labels: Vec<_> = m.columns.iter().map(|c| c.label()).collect();
+        assert_eq!(labels, ["default", "upstream_stable", "upstream_stable X=1", "upstream_stable-kasan"]);
+
+        let rows: Vec<_> = m.rows
+            .iter()
+            .map(|r| {
+                let cells: Vec<_> = r.cells
+                    .iter()
+                    .map(|c| c.as_ref().map(|c| c.status.as_str()))
+                    .collect();
+                (r.test.as_str(), r.subtest.as_str(), cells)
+            })
+            .collect();
+        assert_eq!(rows, [
+            ("boot", "boot", vec![Some("Passed"), None, None, None]),
+            ("fs.ec", "one", vec![None, Some("Passed"), Some("Passed"), Some("Failed")]),
+            ("fs.ec", "two", vec![None, Some("Passed"), None, None]),
+        ]);
+        assert_eq!(m.rows[1].cells[3].as_ref().unwrap().key, "fs.ec@upstream_stable-kasan.one");
+    }
+
+    #[test]
+    fn dotted_subtests_line_up() {
+        use TestStatus::*;
+        // fstests' generic/001: its default-kernel key is split where
+        // the @kernel key says the test ends, not at the last dot.
+        let m = results_matrix("c", &results(&[
+            ("fs.ec.generic.001", Passed, 1),
+            ("fs.ec@upstream_stable.generic.001", Failed, 1),
+            ("boot.boot", Passed, 1),
+        ]));
+        let rows: Vec<_> = m.rows
+            .iter()
+            .map(|r| (r.test.as_str(), r.subtest.as_str(), r.cells.iter().flatten().count()))
+            .collect();
+        assert_eq!(rows, [("boot", "boot", 1), ("fs.ec", "generic.001", 2)]);
+    }
+}
+
+#[cfg(test)]
+mod regression_tests {
+    use super::*;
+
+    fn commit(id: &str, tests: &[(&str, TestStatus)]) -> CommitResults {
+        CommitResults {
+            id: id.to_string(),
+            message: format!("subject {}\n\nbody", id),
+            tests: tests
+                .iter()
+                .map(|(name, status)| (name.to_string(), TestResult::new(*status, 1)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn finds_newest_passing_commit() {
+        use TestStatus::*;
+        let mut results = [
+            commit("untested", &[]),
+            commit("tip", &[("a", Failed), ("b", Failed), ("c", Passed)]),
+            commit("mid", &[("a", Failed), ("c", Passed)]),
+            commit("old", &[("a", Passed), ("b", Failed), ("c", Passed)]),
+            commit("older", &[("a", Passed), ("b", Failed)]),
+        ];
+        assert_eq!(branch_regressions(&results).regressions[0].first_bad, None);
+        results[2].tests.get_mut("a").unwrap().first_bad = true;
+
+        let r = branch_regressions(&results);
+        assert_eq!(r.commit, "tip");
+        // "b" never passed: a long-standing failure, not a regression
+        assert_eq!(r.regressions.len(), 1);
+        let a = &r.regressions[0];
+        assert_eq!(a.name, "a");
+        assert_eq!(a.last_passed, "old");
+        assert_eq!(a.last_passed_subject, "subject old");
+        assert_eq!(a.age, 2);
+        assert_eq!(a.first_bad.as_deref(), Some("mid"));
+    }
+
+    #[test]
+    fn first_bad_mark_is_kept() {
+        let dir = crate::test_util::TempDir::new("first-bad");
+        let commit = "a".repeat(40);
+        let testdir = dir.join(&commit).join("fs.ec.one");
+        create_dir_all(&testdir).unwrap();
+        std::fs::write(testdir.join("status"), "FAILED\n").unwrap();
+        commit_update_results(&dir, &commit);
+
+        let store = TestResultsStore::load(dir.to_path_buf(), false);
+        store.mark_first_bad(&commit, "fs.ec.one").unwrap();
+        assert!(store.commit_results(&commit).unwrap()["fs.ec.one"].first_bad);
+        // In the capnp the views read, and on disk for the next rewrite.
+        let capnp = std::fs::read(dir.join(format!("{}.capnp", commit))).unwrap();
+        assert!(parse_test_results(&capnp).unwrap().tests["fs.ec.one"].first_bad);
+        assert!(read_test_result(&testdir).unwrap().first_bad);
+    }
+
+    #[test]
+    fn first_bad_commit_is_the_bisected_one() {
+        use TestStatus::*;
+        let mut results = vec![
+            commit("tip", &[("a", Failed)]),
+            commit("gap", &[]),
+            commit("bisected", &[("a", Failed)]),
+            commit("skipped", &[("a", FailedToRun)]),
+            commit("good", &[("a", Passed)]),
+            commit("older", &[("a", Failed)]),
+        ];
+        // Not bisected yet.
+        assert_eq!(first_bad_commit(&results, "a"), None);
+
+        results[2].tests.get_mut("a").unwrap().first_bad = true;
+        assert_eq!(first_bad_commit(&results, "a"), Some("bisected"));
+        assert_eq!(first_bad_commit(&results[1..], "a"), Some("bisected"));
+
+        // An older regression's first bad commit, below the last pass.
+        results[2].tests.get_mut("a").unwrap().first_bad = false;
+        results[5].tests.get_mut("a").unwrap().first_bad = true;
+        assert_eq!(first_bad_commit(&results, "a"), None);
+    }
+
+    #[test]
+    fn empty_branch_has_no_regressions() {
+        let r = branch_regressions(&[commit("untested", &[])]);
+        assert!(r.commit.is_empty());
+        assert!(r.regressions.is_empty());
+    }
+
+    #[test]
+    fn history_covers_every_commit() {
+        use TestStatus::*;
+        let results = [
+            commit("tip", &[("a", Failed), ("b", Passed)]),
+            commit("mid", &[("a", Inprogress)]),
+            commit("untested", &[]),
+            commit("old", &[("a", Passed)]),
+        ];
+
+        let h = test_history(&results, "a");
+        let statuses: Vec<_> = h.commits
+            .iter()
+            .map(|c| (c.commit.as_str(), c.result.as_ref().map(|r| r.status.as_str())))
+            .collect();
+        assert_eq!(statuses, [
+            ("tip", Some("Failed")),
+            ("mid", Some("In progress")),
+            ("untested", None),
+            ("old", Some("Passed")),
+        ]);
+        assert_eq!(h.commits[0].subject, "subject tip");
+        assert_eq!(h.stats(), api::HistoryStats {
+            completed: 2,
+            passed: 1,
+            failed: 1,
+            mean_duration: 1,
+        });
+    }
+
+    #[test]
+    fn clusters_failures_by_signature() {
+        use TestStatus::*;
+        let lockdep = "WARNING: possible circular locking dependency detected";
+        let mut results = [
+            commit("tip", &[("a@upstream_stable.x", Failed), ("b.y", Failed), ("c.z", Failed)]),
+            commit("mid", &[("a@upstream_stable.x", Failed), ("a@debian_forky.x", Failed)]),
+            commit("old", &[("a@upstream_stable.x", Passed), ("b.y", Failed)]),
+        ];
+        for (r, key, sig) in [
+            (0, "a@upstream_stable.x", lockdep),
+            (0, "b.y", lockdep),
+            (1, "a@upstream_stable.x", lockdep),
+            (1, "a@debian_forky.x", lockdep),
+            (2, "b.y", "TEST FAILED: fsck not clean"),
+        ] {
+            results[r].tests.get_mut(key).unwrap().signature = Some(sig.to_string());
+        }
+
+        let f = failure_clusters(&results);
+        assert_eq!((f.commits, f.unclassified, f.clusters.len()), (3, 1, 2));
+
+        let c = &f.clusters[0];
+        assert_eq!(c.signature, lockdep);
+        assert_eq!(c.count, 4);
+        assert_eq!(c.tests, ["a.x", "b.y"]);
+        assert_eq!(c.kernels, ["", "debian_forky", "upstream_stable"]);
+        assert_eq!((c.first_seen.as_str(), c.first_seen_subject.as_str()), ("mid", "subject mid"));
+        assert_eq!(c.last_seen, "tip");
+
+        assert_eq!(f.clusters[1].count, 1);
+        assert_eq!(f.clusters[1].first_seen, "old");
+    }
+}
+
 // Branch log generation and parsing
 
 use branchlog_capnp::branch_log;
@@ -1203,24 +2484,53 @@ pub struct BranchEntry {
     pub inprogress: u32,
     pub unknown: u32,
     pub duration: u64,
+    /// Failures of quarantined subtests — not included in `failed`.
+    #[serde(default)]
+    pub quarantined: u32,
+    /// Failures matching a known issue — not included in `failed`
+    /// either. Zero unless branch_entries() was given known issues.
+    #[serde(default)]
+    pub known: u32,
 }
 
 /// Roll commit results up into branch-log entries; commits with no test
-/// results are dropped. Shared by the cgi's JSON view and ci-status.
-pub fn branch_entries(results: Vec<CommitResults>) -> Vec<BranchEntry> {
+/// results are dropped. Failures matching `quarantine` (see
+/// Userrc::branch_quarantine) are counted as `quarantined`, not
+/// `failed`; with `known`, the rest matching a known issue are counted
+/// as `known` — the same KnownIssues::lookup() the commit page annotates
+/// them with, so a log is only read for a failure whose key some entry
+/// covers and whose signature doesn't settle it.
+/// Shared by the cgi's JSON view and ci-status.
+pub fn branch_entries(
+    results: Vec<CommitResults>,
+    quarantine: &Quarantine,
+    known: Option<&known_issues::KnownIssues>,
+) -> Vec<BranchEntry> {
     results
         .into_iter()
         .filter(|r| !r.tests.is_empty())
-        .map(|r| BranchEntry {
-            duration: r.tests.values().map(|t| t.duration).sum(),
-            passed: count_status(&r.tests, TestStatus::Passed),
-            failed: count_status(&r.tests, TestStatus::Failed),
-            notrun: count_status(&r.tests, TestStatus::Notrun),
-            failed_to_run: count_status(&r.tests, TestStatus::FailedToRun),
-            inprogress: count_status(&r.tests, TestStatus::Inprogress),
-            unknown: count_status(&r.tests, TestStatus::Unknown),
-            commit_id: r.id,
-            message: r.message,
+        .map(|r| {
+            let quarantined = count_quarantined(&r.tests, quarantine);
+            let known = known.map_or(0, |k| {
+                r.tests
+                    .iter()
+                    .filter(|(name, _)| !is_quarantined(quarantine, name))
+                    .filter(|(name, t)| k.lookup(&r.id, name, t).is_some())
+                    .count() as u32
+            });
+            BranchEntry {
+                duration: r.tests.values().map(|t| t.duration).sum(),
+                passed: count_status(&r.tests, TestStatus::Passed),
+                failed: count_status(&r.tests, TestStatus::Failed) - quarantined - known,
+                notrun: count_status(&r.tests, TestStatus::Notrun),
+                failed_to_run: count_status(&r.tests, TestStatus::FailedToRun),
+                inprogress: count_status(&r.tests, TestStatus::Inprogress),
+                unknown: count_status(&r.tests, TestStatus::Unknown),
+                quarantined,
+                known,
+                commit_id: r.id,
+                message: r.message,
+            }
         })
         .collect()
 }
@@ -1229,34 +2539,31 @@ pub fn count_status(tests: &TestResultsMap, status: TestStatus) -> u32 {
     tests.iter().filter(|x| x.1.status == status).count() as u32
 }
 
+pub fn is_quarantined(quarantine: &Quarantine, test: &str) -> bool {
+    quarantine.matches(test)
+}
+
+/// Failed results of quarantined subtests.
+pub fn count_quarantined(tests: &TestResultsMap, quarantine: &Quarantine) -> u32 {
+    tests
+        .iter()
+        .filter(|(name, r)| r.status == TestStatus::Failed && is_quarantined(quarantine, name))
+        .count() as u32
+}
+
 pub fn generate_branch_log(
     repo: &git2::Repository,
     ktest: &Ktestrc,
     user: &str,
     branch: &str,
+    quarantine: &Quarantine,
+    known: Option<&known_issues::KnownIssues>,
 ) -> anyhow::Result<Vec<BranchEntry>> {
     let all = Regex::new("").unwrap();
     let results = branch_get_results(repo, ktest, Some(user), Some(branch), None, &all)
         .map_err(|e| anyhow::anyhow!(e))?;
 
-    Ok(results
-        .into_iter()
-        .filter(|r| !r.tests.is_empty())
-        .map(|r| {
-            let duration: u64 = r.tests.iter().map(|x| x.1.duration).sum();
-            BranchEntry {
-                commit_id: r.id,
-                message: r.message,
-                passed: count_status(&r.tests, TestStatus::Passed),
-                failed: count_status(&r.tests, TestStatus::Failed),
-                notrun: count_status(&r.tests, TestStatus::Notrun),
-                failed_to_run: count_status(&r.tests, TestStatus::FailedToRun),
-                inprogress: count_status(&r.tests, TestStatus::Inprogress),
-                unknown: count_status(&r.tests, TestStatus::Unknown),
-                duration,
-            }
-        })
-        .collect())
+    Ok(branch_entries(results, quarantine, known))
 }
 
 pub fn write_branch_log(
@@ -1280,6 +2587,8 @@ pub fn write_branch_log(
         dst.set_inprogress(entry.inprogress);
         dst.set_unknown(entry.unknown);
         dst.set_duration(entry.duration);
+        dst.set_quarantined(entry.quarantined);
+        dst.set_known(entry.known);
     }
 
     let fname = output_dir.join(format!("branch.{}.{}.capnp", user, branch));
@@ -1312,6 +2621,8 @@ pub fn branchlog_parse(f: &[u8]) -> anyhow::Result<Vec<BranchEntry>> {
             inprogress: e.get_inprogress(),
             unknown: e.get_unknown(),
             duration: e.get_duration(),
+            quarantined: e.get_quarantined(),
+            known: e.get_known(),
         })
         .collect();
 
@@ -0,0 +1,198 @@
+//! Prometheus metrics in the text exposition format.
+//!
+//! ci-daemon accumulates counters, gauges and summaries here and
+//! periodically writes them out to a file — either into node_exporter's
+//! textfile-collector directory, or under the (web-served) output_dir
+//! where Prometheus can scrape it directly. No HTTP server, no client
+//! library: the format is a handful of text lines.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    /// Sum and count only, no quantiles: enough for rates and averages
+    /// over any window.
+    Summary,
+}
+
+impl Kind {
+    fn to_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Summary => "summary",
+        }
+    }
+}
+
+/// A metric family, declared up front: the HELP/TYPE header is emitted
+/// once per family, and updating an undeclared name is a bug.
+pub struct Family {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub help: &'static str,
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Default)]
+struct Sample {
+    value: f64,
+    count: u64,
+}
+
+pub struct Metrics {
+    families: &'static [Family],
+    samples: Mutex<BTreeMap<(&'static str, Labels), Sample>>,
+}
+
+impl Metrics {
+    pub fn new(families: &'static [Family]) -> Metrics {
+        Metrics {
+            families,
+            samples: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn update(&self, name: &str, kind: Kind, labels: &[(&str, &str)], f: impl FnOnce(&mut Sample)) {
+        let family = self
+            .families
+            .iter()
+            .find(|f| f.name == name)
+            .unwrap_or_else(|| panic!("undeclared metric {}", name));
+        assert_eq!(family.kind, kind, "metric {} used as the wrong kind", name);
+
+        let labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut samples = self.samples.lock().unwrap();
+        f(samples.entry((family.name, labels)).or_default());
+    }
+
+    pub fn inc(&self, name: &str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1.0);
+    }
+
+    pub fn add(&self, name: &str, labels: &[(&str, &str)], v: f64) {
+        self.update(name, Kind::Counter, labels, |s| s.value += v);
+    }
+
+    pub fn set(&self, name: &str, labels: &[(&str, &str)], v: f64) {
+        self.update(name, Kind::Gauge, labels, |s| s.value = v);
+    }
+
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], v: f64) {
+        self.update(name, Kind::Summary, labels, |s| {
+            s.value += v;
+            s.count += 1;
+        });
+    }
+
+    /// Drop every sample of a family — for gauges recomputed from a
+    /// snapshot, so a label set that has gone away doesn't linger at
+    /// its last value.
+    pub fn clear(&self, name: &str) {
+        self.samples.lock().unwrap().retain(|(n, _), _| *n != name);
+    }
+
+    pub fn render(&self) -> String {
+        fn labels_str(labels: &Labels) -> String {
+            if labels.is_empty() {
+                return String::new();
+            }
+            let l: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| {
+                    let v = v
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                        .replace('\n', "\\n");
+                    format!("{}=\"{}\"", k, v)
+                })
+                .collect();
+            format!("{{{}}}", l.join(","))
+        }
+
+        let samples = self.samples.lock().unwrap();
+        let mut out = String::new();
+
+        for f in self.families {
+            writeln!(out, "# HELP {} {}", f.name, f.help).unwrap();
+            writeln!(out, "# TYPE {} {}", f.name, f.kind.to_str()).unwrap();
+
+            for ((_, labels), s) in samples.iter().filter(|((n, _), _)| *n == f.name) {
+                let l = labels_str(labels);
+                if f.kind == Kind::Summary {
+                    writeln!(out, "{}_sum{} {}", f.name, l, s.value).unwrap();
+                    writeln!(out, "{}_count{} {}", f.name, l, s.count).unwrap();
+                } else {
+                    writeln!(out, "{}{} {}", f.name, l, s.value).unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    /// Write the rendered metrics to `path` via a temp file + rename, so
+    /// a collector never reads a partial file.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let tmp = path.with_extension("prom.new");
+        std::fs::write(&tmp, self.render())?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILIES: &[Family] = &[
+        Family { name: "t_total", kind: Kind::Counter, help: "A counter" },
+        Family { name: "t_gauge", kind: Kind::Gauge, help: "A gauge" },
+        Family { name: "t_seconds", kind: Kind::Summary, help: "A summary" },
+    ];
+
+    #[test]
+    fn renders_exposition_format() {
+        let m = Metrics::new(FAMILIES);
+        m.inc("t_total", &[("host", "a")]);
+        m.inc("t_total", &[("host", "a")]);
+        m.add("t_total", &[("host", "b\"c")], 0.5);
+        m.set("t_gauge", &[], 7.0);
+        m.observe("t_seconds", &[("step", "run")], 1.5);
+        m.observe("t_seconds", &[("step", "run")], 2.0);
+
+        assert_eq!(
+            m.render(),
+            "# HELP t_total A counter\n\
+             # TYPE t_total counter\n\
+             t_total{host=\"a\"} 2\n\
+             t_total{host=\"b\\\"c\"} 0.5\n\
+             # HELP t_gauge A gauge\n\
+             # TYPE t_gauge gauge\n\
+             t_gauge 7\n\
+             # HELP t_seconds A summary\n\
+             # TYPE t_seconds summary\n\
+             t_seconds_sum{step=\"run\"} 3.5\n\
+             t_seconds_count{step=\"run\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn clear_drops_one_family() {
+        let m = Metrics::new(FAMILIES);
+        m.inc("t_total", &[]);
+        m.set("t_gauge", &[("user", "x")], 1.0);
+        m.clear("t_gauge");
+
+        let out = m.render();
+        assert!(out.contains("t_total 1\n"));
+        assert!(!out.contains("user=\"x\""));
+    }
+}
@@ -0,0 +1,508 @@
+//! Failure notifications: when a branch tip finishes testing with
+//! subtests that newly fail compared to its parent commit, ci-daemon
+//! tells the `notify` targets of the branch and of the test groups that
+//! ran them — by email through a plain SMTP relay, or as a JSON POST to
+//! a webhook.
+
+use crate::jobs::BranchTip;
+use crate::{Ktestrc, TestResultsMap, TestStatus, Userrc};
+use anyhow::{anyhow, Context};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A `notify` entry: an http(s) URL is a webhook, anything else an
+/// email address.
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Email(String),
+    Webhook(String),
+}
+
+impl Target {
+    pub fn parse(s: &str) -> anyhow::Result<Target> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Target::Webhook(s.to_string()))
+        } else if s.contains('@') && !s.contains(char::is_whitespace) {
+            Ok(Target::Email(s.to_string()))
+        } else {
+            Err(anyhow!("invalid notify target {:?}: not an email address or http(s) URL", s))
+        }
+    }
+}
+
+/// Result keys Failed at the tip that Passed at its parent.
+pub fn new_failures(tip: &TestResultsMap, parent: &TestResultsMap) -> Vec<String> {
+    tip.iter()
+        .filter(|(_, r)| r.status == TestStatus::Failed)
+        .filter(|(key, _)| parent.get(*key).is_some_and(|r| r.status == TestStatus::Passed))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Which targets hear about which of `failures`: the branch's targets
+/// get all of them, a test group's targets those its subtests produced.
+pub fn recipients(
+    userrc: &Userrc,
+    tip: &BranchTip,
+    failures: &[String],
+) -> BTreeMap<String, Vec<String>> {
+    let mut out: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+    if let Some(b) = userrc.branches.get(&tip.branch) {
+        for t in &b.notify {
+            out.entry(t.clone()).or_default().extend(failures.iter().cloned());
+        }
+    }
+    for key in failures {
+        for tg in tip.keys.get(key).into_iter().flatten() {
+            for t in userrc.test_groups.get(tg).into_iter().flat_map(|tg| &tg.notify) {
+                out.entry(t.clone()).or_default().insert(key.clone());
+            }
+        }
+    }
+
+    out.into_iter()
+        .filter(|(_, keys)| !keys.is_empty())
+        .map(|(t, keys)| (t, keys.into_iter().collect()))
+        .collect()
+}
+
+/// Where send() delivers from: the config's SMTP settings, owned, so
+/// sending can happen off the thread that has the config.
+#[derive(Clone, Debug, Default)]
+pub struct Delivery {
+    pub smtp_server: Option<String>,
+    pub notify_from: Option<String>,
+}
+
+impl Delivery {
+    pub fn new(ktest: &Ktestrc) -> Delivery {
+        Delivery {
+            smtp_server: ktest.smtp_server.clone(),
+            notify_from: ktest.notify_from.clone(),
+        }
+    }
+}
+
+/// Why a send failed, and whether trying again could help.
+#[derive(Debug)]
+pub struct SendError {
+    /// Retrying won't fix it: a bad target or missing config, or the
+    /// other end refused the message (an SMTP 5xx; an HTTP 4xx other
+    /// than a timeout or rate limit)
+    pub permanent: bool,
+    pub error: anyhow::Error,
+}
+
+impl SendError {
+    fn permanent(error: impl Into<anyhow::Error>) -> SendError {
+        SendError { permanent: true, error: error.into() }
+    }
+
+    fn context(self, context: String) -> SendError {
+        SendError { error: self.error.context(context), ..self }
+    }
+}
+
+/// Anything else — I/O, DNS, a dropped connection — may well go through
+/// next time.
+impl<E: Into<anyhow::Error>> From<E> for SendError {
+    fn from(error: E) -> SendError {
+        SendError { permanent: false, error: error.into() }
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+}
+
+/// The message, also the webhook's JSON body.
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub user: String,
+    pub branch: String,
+    pub commit: String,
+    pub subject: String,
+    /// The commit's dashboard page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub failures: Vec<Failure>,
+}
+
+impl Notification {
+    /// Links are built from `dashboard_url`; logs live under `ci_url`
+    /// if set, else the dashboard's `c/` sibling, as the cgi links them.
+    pub fn new(ktest: &Ktestrc, tip: &BranchTip, failures: &[String]) -> Notification {
+        let dashboard = ktest.dashboard_url.as_deref();
+        let logs = ktest.ci_url.clone().or_else(|| {
+            dashboard.and_then(|d| d.rsplit_once('/')).map(|(dir, _)| format!("{}/c", dir))
+        });
+
+        Notification {
+            user: tip.user.clone(),
+            branch: tip.branch.clone(),
+            commit: tip.commit.clone(),
+            subject: tip.subject.clone(),
+            url: dashboard.map(|d| {
+                format!("{}?user={}&branch={}&commit={}", d, tip.user, tip.branch, tip.commit)
+            }),
+            failures: failures
+                .iter()
+                .map(|name| Failure {
+                    name: name.clone(),
+                    log: logs.as_ref().map(|l| format!("{}/{}/{}/log.br", l, tip.commit, name)),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn email_subject(&self) -> String {
+        format!(
+            "[ci] {}/{}: {} new failures at {}",
+            self.user,
+            self.branch,
+            self.failures.len(),
+            crate::short_commit(&self.commit)
+        )
+    }
+
+    pub fn email_body(&self) -> String {
+        let mut out = format!("{} {}\n", self.commit, self.subject);
+        if let Some(url) = &self.url {
+            out.push_str(url);
+            out.push('\n');
+        }
+        out.push_str("\nNew failures (passed at the parent commit):\n\n");
+        for f in &self.failures {
+            out.push_str(&format!("  {}\n", f.name));
+            if let Some(log) = &f.log {
+                out.push_str(&format!("    {}\n", log));
+            }
+        }
+        out
+    }
+
+    /// Deliver to one `notify` target.
+    pub fn send(&self, via: &Delivery, target: &str) -> Result<(), SendError> {
+        match Target::parse(target).map_err(SendError::permanent)? {
+            Target::Email(to) => {
+                let server = via
+                    .smtp_server
+                    .as_deref()
+                    .ok_or_else(|| SendError::permanent(anyhow!("smtp_server not set in config")))?;
+                let from = via.notify_from.as_deref().unwrap_or("ci-daemon@localhost");
+                send_email(server, from, &to, &self.email_subject(), &self.email_body())
+                    .map_err(|e| e.context(format!("mailing {}", to)))
+            }
+            Target::Webhook(url) => send_webhook(&url, self),
+        }
+    }
+}
+
+/// Read one SMTP reply (possibly multi-line: `250-...` continues,
+/// `250 ...` ends it) and check its code. A 5xx is a permanent refusal.
+fn smtp_reply(r: &mut impl BufRead, expect: u32) -> Result<(), SendError> {
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Err(anyhow!("SMTP server closed the connection").into());
+        }
+        let code: u32 = line
+            .get(..3)
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(|| anyhow!("bad SMTP reply {:?}", line.trim_end()))?;
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if code != expect {
+            let e = anyhow!("SMTP: expected {}, got {:?}", expect, line.trim_end());
+            return Err(if code >= 500 { SendError::permanent(e) } else { e.into() });
+        }
+        return Ok(());
+    }
+}
+
+/// Minimal SMTP submission to a relay that accepts us unauthenticated
+/// (the local MTA): no TLS, no AUTH.
+fn send_email(server: &str, from: &str, to: &str, subject: &str, body: &str) -> Result<(), SendError> {
+    let server = if server.contains(':') { server.to_string() } else { format!("{}:25", server) };
+    let addr = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("{}: no address", server))?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+        .with_context(|| format!("connecting to {}", server))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = stream;
+
+    smtp_reply(&mut r, 220)?;
+    let mut cmd = |line: &str, expect: u32| -> Result<(), SendError> {
+        w.write_all(line.as_bytes())?;
+        w.write_all(b"\r\n")?;
+        smtp_reply(&mut r, expect)
+    };
+
+    let mut msg = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to,
+        subject,
+        chrono::Utc::now().to_rfc2822()
+    );
+    for line in body.lines() {
+        // dot-stuffing: a leading '.' is doubled, so only the
+        // terminator is a lone "."
+        if line.starts_with('.') {
+            msg.push('.');
+        }
+        msg.push_str(line);
+        msg.push_str("\r\n");
+    }
+    msg.push('.');
+
+    cmd("HELO localhost", 250)?;
+    cmd(&format!("MAIL FROM:<{}>", from), 250)?;
+    cmd(&format!("RCPT TO:<{}>", to), 250)?;
+    cmd("DATA", 354)?;
+    cmd(&msg, 250)?;
+    cmd("QUIT", 221)
+}
+
+fn send_webhook(url: &str, n: &Notification) -> Result<(), SendError> {
+    let e = match reqwest::blocking::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(n)?)
+        .send()
+        .and_then(|r| r.error_for_status())
+    {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+    let permanent = e.is_builder()
+        || e.status().is_some_and(|s| s.is_client_error() && !matches!(s.as_u16(), 408 | 429));
+    Err(SendError { permanent, error: anyhow::Error::new(e).context(format!("posting to {}", url)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::users::userrc_read_str;
+    use crate::TestResult;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn results(tests: &[(&str, TestStatus)]) -> TestResultsMap {
+        tests
+            .iter()
+            .map(|(name, status)| (name.to_string(), TestResult::new(*status, 1)))
+            .collect()
+    }
+
+    fn tip() -> BranchTip {
+        BranchTip {
+            user: "u".to_string(),
+            branch: "br".to_string(),
+            commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            subject: "bcachefs: fix".to_string(),
+            parent: None,
+            keys: [
+                ("a.x".to_string(), vec!["ga".to_string()]),
+                ("b.y".to_string(), vec!["gb".to_string()]),
+            ]
+            .into_iter()
+            .collect(),
+            done: true,
+        }
+    }
+
+    fn ktest(dashboard: Option<&str>, smtp: Option<String>) -> Ktestrc {
+        let mut config = r#"{ linux_repo: "/l", output_dir: "/o", ktest_dir: "/k""#.to_string();
+        if let Some(d) = dashboard {
+            config += &format!(r#", dashboard_url: "{}""#, d);
+        }
+        if let Some(s) = smtp {
+            config += &format!(r#", smtp_server: "{}""#, s);
+        }
+        config += "}";
+        json_five::from_str(&config).unwrap()
+    }
+
+    #[test]
+    fn only_pass_to_fail_is_new() {
+        use TestStatus::*;
+        let parent = results(&[("a", Passed), ("b", Failed), ("c", Passed)]);
+        let tip = results(&[("a", Failed), ("b", Failed), ("c", Passed), ("d", Failed)]);
+        // "b" was already failing, "d" has nothing to compare against
+        assert_eq!(new_failures(&tip, &parent), vec!["a"]);
+    }
+
+    #[test]
+    fn routes_by_branch_and_group() {
+        let rc = userrc_read_str(r#"{
+            test_groups: {
+                ga: { tests: ["a.ktest"], notify: ["a@example.org"] },
+                gb: { tests: ["b.ktest"] },
+            },
+            branches: {
+                br: { fetch: "x", test_groups: ["ga", "gb"], notify: ["https://hook.example/x"] },
+            },
+        }"#).unwrap();
+
+        let r = recipients(&rc, &tip(), &["a.x".to_string(), "b.y".to_string()]);
+        assert_eq!(r["https://hook.example/x"], vec!["a.x", "b.y"]);
+        assert_eq!(r["a@example.org"], vec!["a.x"]);
+        assert_eq!(r.len(), 2);
+        assert!(recipients(&rc, &tip(), &[]).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_targets() {
+        assert!(Target::parse("not an address").is_err());
+        assert_eq!(Target::parse("a@b.org").unwrap(), Target::Email("a@b.org".to_string()));
+        assert!(userrc_read_str(r#"{
+            test_groups: {},
+            branches: { br: { fetch: "x", test_groups: [], notify: ["nope"] } },
+        }"#).is_err());
+    }
+
+    #[test]
+    fn email_through_smtp_stand_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut r = BufReader::new(stream.try_clone().unwrap());
+            let mut w = stream;
+            let mut transcript = String::new();
+            let mut in_data = false;
+
+            w.write_all(b"220 stand-in\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if r.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    w.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else if line.starts_with("HELO") {
+                    b"250-stand-in\r\n250 ok\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                w.write_all(reply).unwrap();
+            }
+            transcript
+        });
+
+        let ktest = ktest(Some("https://ci.example/~td/ci"), Some(addr.to_string()));
+        let n = Notification::new(&ktest, &tip(), &["a.x".to_string()]);
+        n.send(&Delivery::new(&ktest), "dev@example.org").unwrap();
+
+        let transcript = server.join().unwrap();
+        assert!(transcript.contains("RCPT TO:<dev@example.org>\r\n"));
+        assert!(transcript.contains("Subject: [ci] u/br: 1 new failures at 0123456789ab\r\n"));
+        assert!(transcript.contains(
+            "https://ci.example/~td/ci?user=u&branch=br&commit=0123456789abcdef0123456789abcdef01234567\r\n"
+        ));
+        assert!(transcript.contains(
+            "https://ci.example/~td/c/0123456789abcdef0123456789abcdef01234567/a.x/log.br\r\n"
+        ));
+        assert!(transcript.ends_with(".\r\nQUIT\r\n"));
+    }
+
+    /// A one-request HTTP server answering `status`; joins to the
+    /// request body.
+    fn http_stand_in(status: &'static str) -> (String, std::thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut r = BufReader::new(stream.try_clone().unwrap());
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((k, v)) = line.split_once(':') {
+                    if k.eq_ignore_ascii_case("content-length") {
+                        len = v.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; len];
+            r.read_exact(&mut body).unwrap();
+            let mut w = stream;
+            write!(w, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            body
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn webhook_through_http_stand_in() {
+        let (url, server) = http_stand_in("200 OK");
+        let ktest = ktest(None, None);
+        let n = Notification::new(&ktest, &tip(), &["b.y".to_string()]);
+        n.send(&Delivery::new(&ktest), &url).unwrap();
+
+        let body: serde_json::Value = serde_json::from_slice(&server.join().unwrap()).unwrap();
+        assert_eq!(body["branch"], "br");
+        assert_eq!(body["failures"][0]["name"], "b.y");
+        // no dashboard_url or ci_url configured: no links
+        assert!(body.get("url").is_none());
+        assert!(body["failures"][0].get("log").is_none());
+    }
+
+    #[test]
+    fn refusals_are_not_retried() {
+        let ktest = ktest(None, None);
+        let n = Notification::new(&ktest, &tip(), &["b.y".to_string()]);
+        let send = |target: &str| n.send(&Delivery::new(&ktest), target).unwrap_err();
+
+        assert!(send("nope").permanent);
+        // No smtp_server configured
+        assert!(send("dev@example.org").permanent);
+
+        let (url, server) = http_stand_in("404 Not Found");
+        assert!(send(&url).permanent);
+        server.join().unwrap();
+        let (url, server) = http_stand_in("503 Service Unavailable");
+        assert!(!send(&url).permanent);
+        server.join().unwrap();
+    }
+}
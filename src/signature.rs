@@ -0,0 +1,158 @@
+//! Failure signatures: one short line saying why a subtest failed, so a
+//! page of failures can be triaged without opening each log.br.
+//!
+//! After a batch is pulled, the daemon scans each failed subtest's log
+//! and writes what it finds to a `signature` file in the result dir;
+//! read_test_result() picks it up as TestResult::signature. In order of
+//! preference the signature is the first kernel splat header (BUG,
+//! WARNING, Oops, KASAN/UBSAN, lockdep, panic), else the supervisor's
+//! timeout marker, else the test's own `TEST FAILED` line. Addresses,
+//! offsets, pids, CPU numbers and timestamps are stripped, so the same
+//! failure on different runs gives the same signature.
+
+use crate::TestStatus;
+use regex::Regex;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// Longest signature kept, in chars.
+pub const MAX_LEN: usize = 120;
+
+/// The supervisor's elapsed-seconds prefix (`00012 `) and the kernel's
+/// console timestamp (`[  1.234] `), each optional.
+static LINE_PREFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:\d+ )?(?:\[[^\]]*\] ?)?").unwrap());
+
+static KERNEL_SPLAT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?:BUG:|kernel BUG at |WARNING:|Oops|KASAN:|UBSAN:|Kernel panic",
+        r"|general protection fault|Unable to handle kernel",
+        r"|possible circular locking|possible recursive locking|inconsistent lock state)",
+    ))
+    .unwrap()
+});
+
+static TIMEOUT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"FAILED TIMEOUT (\S+)").unwrap());
+
+static TEST_FAILED: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^TEST FAILED(?::.*)?$").unwrap());
+
+/// Run-specific noise, and what it's replaced with.
+static NOISE: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    [
+        // symbol+0x1c/0x2a0 [module]
+        (r"\+0x[0-9a-fA-F]+/0x[0-9a-fA-F]+", ""),
+        (r"\b(?:CPU|PID|pid|cpu)[:=]? ?\d+\b", ""),
+        (r"\[#\d+\]", ""),
+        (r"\b0x[0-9a-fA-F]+\b", "X"),
+        (r"\b[0-9a-fA-F]{8,}\b", "X"),
+        (r"\[ *\d+\.\d+\]", ""),
+        (r"\s+", " "),
+    ]
+    .into_iter()
+    .map(|(re, with)| (Regex::new(re).unwrap(), with))
+    .collect()
+});
+
+fn normalize(line: &str) -> String {
+    let mut s = line.to_string();
+    for (re, with) in NOISE.iter() {
+        s = re.replace_all(&s, *with).into_owned();
+    }
+    let s = s.trim();
+    match s.char_indices().nth(MAX_LEN) {
+        Some((i, _)) => s[..i].to_string(),
+        None => s.to_string(),
+    }
+}
+
+/// The signature of a failed run's `log`, if anything in it explains
+/// the failure.
+pub fn extract(log: &str) -> Option<String> {
+    let lines = || log.lines().map(|l| LINE_PREFIX.replace(l, ""));
+
+    if let Some(splat) = lines().find_map(|l| KERNEL_SPLAT.find(&l).map(|m| normalize(&l[m.start()..]))) {
+        return Some(splat);
+    }
+    if let Some(test) = lines().find_map(|l| TIMEOUT.captures(&l).map(|c| c[1].to_string())) {
+        return Some(format!("TIMEOUT {}", test));
+    }
+    // A bare "TEST FAILED" is the runner's generic verdict; prefer a
+    // line that says what went wrong.
+    let failed: Vec<String> = lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| TEST_FAILED.is_match(l))
+        .collect();
+    failed
+        .iter()
+        .find(|l| l.len() > "TEST FAILED".len())
+        .or(failed.first())
+        .map(|l| normalize(l))
+}
+
+/// If `<testdir>/status` says the subtest failed, scan its `log` and
+/// write `<testdir>/signature`. No log, or nothing found, is not an
+/// error.
+pub fn ingest(testdir: &Path) -> std::io::Result<()> {
+    let status = std::fs::read_to_string(testdir.join("status")).unwrap_or_default();
+    if TestStatus::from_str(&status) != TestStatus::Failed {
+        return Ok(());
+    }
+    let log = match std::fs::read(testdir.join("log")) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    match extract(&String::from_utf8_lossy(&log)) {
+        Some(sig) => std::fs::write(testdir.join("signature"), sig + "\n"),
+        None => Ok(()),
+    }
+}
+
+/// The `signature` file ingest() wrote, if any.
+pub fn read_signature(testdir: &Path) -> Option<String> {
+    let sig = std::fs::read_to_string(testdir.join("signature")).ok()?;
+    let sig = sig.trim();
+    (!sig.is_empty()).then(|| sig.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_splat_wins() {
+        let log = "\
+00001 TEST FAILED: fsck not clean
+00003 [   12.345678] ------------[ cut here ]------------
+00003 [   12.345679] WARNING: CPU: 3 PID: 1234 at fs/bcachefs/btree_iter.c:2841 bch2_trans_put+0x1c/0x2a0
+00003 [   12.400000] BUG: KASAN: use-after-free in bch2_btree_node_read+0x3f/0x120
+00004 ========= FAILED TIMEOUT foo in 7200s
+";
+        assert_eq!(
+            extract(log).as_deref(),
+            Some("WARNING: at fs/bcachefs/btree_iter.c:2841 bch2_trans_put"),
+        );
+    }
+
+    #[test]
+    fn same_failure_same_signature() {
+        let a = "[    1.0] BUG: unable to handle page fault for address: ffff88810a3c4000\n";
+        let b = "[ 9999.9] BUG: unable to handle page fault for address: ffff8881deadb000\n";
+        assert_eq!(extract(a), extract(b));
+        assert_eq!(extract(a).as_deref(), Some("BUG: unable to handle page fault for address: X"));
+    }
+
+    #[test]
+    fn timeout_then_test_failed() {
+        assert_eq!(
+            extract("00010 ========= FAILED TIMEOUT subvol_create in 600s\n00010 TEST FAILED\n").as_deref(),
+            Some("TIMEOUT subvol_create"),
+        );
+        assert_eq!(
+            extract("00001 TEST FAILED\n00001 TEST FAILED: errors remain after fsck -y\n").as_deref(),
+            Some("TEST FAILED: errors remain after fsck -y"),
+        );
+        assert_eq!(extract("00001 TEST FAILED\n").as_deref(), Some("TEST FAILED"));
+        assert_eq!(extract("all good\n"), None);
+    }
+}
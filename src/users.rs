@@ -20,6 +20,20 @@ struct RawTestGroup {
     #[serde(default)]
     test_always_passes_nice: Option<u64>,
     #[serde(default)]
+    bisect_commits: Option<u64>,
+    #[serde(default)]
+    rerun_failed: Option<u64>,
+    #[serde(default)]
+    quarantine: Option<Vec<String>>,
+    #[serde(default)]
+    notify: Option<Vec<String>>,
+    #[serde(default)]
+    batch_timeout_factor: Option<f64>,
+    #[serde(default)]
+    batch_timeout_min: Option<u64>,
+    #[serde(default)]
+    batch_timeout_max: Option<u64>,
+    #[serde(default)]
     tests: Option<Vec<PathBuf>>,
     #[serde(default)]
     kernels: Option<Vec<String>>,
@@ -35,6 +49,8 @@ struct RawBranch {
     #[serde(default = "default_repo")]
     repo: String,
     test_groups: Vec<String>,
+    #[serde(default)]
+    notify: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -51,6 +67,29 @@ pub struct RcTestGroup {
     pub nice: u64,
     pub test_duration_nice: u64,
     pub test_always_passes_nice: u64,
+    /// How far back from the branch tip (in commits) to look for a
+    /// passing result when bisecting a new failure; 0 disables
+    /// bisection. Independent of `max_commits` - bisection jobs are
+    /// emitted for the untested gap, not the whole history.
+    pub bisect_commits: u64,
+    /// How many more times to run a subtest at the same commit after it
+    /// fails, to tell a flake from a real regression. 0 = the first
+    /// verdict stands.
+    pub rerun_failed: u64,
+    /// Globs over result keys (`/` accepted for `.`, as in rm-results)
+    /// for known-broken subtests: they keep running and recording
+    /// results, but their failures are counted apart from `failed`.
+    pub quarantine: Vec<String>,
+    /// Where to report new failures of this group's subtests at the
+    /// branch tip: email addresses or webhook URLs (see notify::Target).
+    pub notify: Vec<String>,
+    /// The supervisor's timeout for a batch of this group's subtests is
+    /// the longest subtest's historical p90 duration times
+    /// `batch_timeout_factor`, clamped to [min, max] seconds (see
+    /// jobs::BatchTimeout).
+    pub batch_timeout_factor: f64,
+    pub batch_timeout_min: u64,
+    pub batch_timeout_max: u64,
     pub tests: Vec<PathBuf>,
     pub kernels: Vec<String>,
     pub env: BTreeMap<String, String>,
@@ -60,6 +99,9 @@ pub struct RcBranch {
     pub fetch: String,
     pub repo: String,
     pub test_groups: Vec<String>,
+    /// Where to report any new failure at the branch tip; see
+    /// RcTestGroup::notify.
+    pub notify: Vec<String>,
 }
 
 pub struct Userrc {
@@ -67,6 +109,80 @@ pub struct Userrc {
     pub branches: BTreeMap<String, RcBranch>,
 }
 
+/// Compile a quarantine entry. Result keys use `.` as the separator;
+/// `/` is accepted for it, so a test path can be pasted in.
+pub fn quarantine_pattern(s: &str) -> anyhow::Result<glob::Pattern> {
+    glob::Pattern::new(&s.replace('/', "."))
+        .with_context(|| format!("invalid quarantine pattern {:?}", s))
+}
+
+/// Split a branch's `fetch` into the `git fetch` arguments: usually a
+/// remote (name or URL) and the ref(s) or refspecs to fetch from it,
+/// though anything git takes will do. Options aren't allowed - the
+/// daemon fetches with its own, and points the branch at FETCH_HEAD.
+pub fn fetch_args(fetch: &str) -> anyhow::Result<Vec<&str>> {
+    let args: Vec<&str> = fetch.split_whitespace().collect();
+    if let Some(a) = args.iter().find(|a| a.starts_with('-')) {
+        return Err(anyhow!("fetch {:?}: options like {} aren't allowed", fetch, a));
+    }
+    Ok(args)
+}
+
+/// A branch's quarantine: each of its test groups' patterns, applied
+/// only to result keys that group produces — by their kernel and env —
+/// so quarantining a subtest in one group leaves it counted in the
+/// others.
+#[derive(Default)]
+pub struct Quarantine {
+    groups: Vec<GroupQuarantine>,
+}
+
+struct GroupQuarantine {
+    /// (sanitized kernel, encoded env) of each of the group's runs
+    runs: Vec<(String, String)>,
+    patterns: Vec<glob::Pattern>,
+}
+
+impl Quarantine {
+    pub fn matches(&self, key: &str) -> bool {
+        let (kernel, env) = crate::parse_result_key(key).map_or(("", ""), |k| (k.kernel, k.env));
+        self.groups.iter().any(|g| {
+            g.runs.iter().any(|(k, e)| k == kernel && e == env)
+                && g.patterns.iter().any(|p| p.matches(key))
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+impl Userrc {
+    /// The quarantine of every test group `branch` runs; empty for an
+    /// unknown branch.
+    pub fn branch_quarantine(&self, branch: &str) -> Quarantine {
+        let Some(b) = self.branches.get(branch) else {
+            return Quarantine::default();
+        };
+        let groups = b.test_groups
+            .iter()
+            .filter_map(|tg| self.test_groups.get(tg))
+            .filter(|tg| !tg.quarantine.is_empty())
+            .filter_map(|tg| {
+                let env = crate::encode_env(&tg.env).ok()?;
+                let runs = if tg.kernels.is_empty() {
+                    vec![(String::new(), env)]
+                } else {
+                    tg.kernels.iter().map(|k| (crate::sanitize_kernel(k), env.clone())).collect()
+                };
+                let patterns = tg.quarantine.iter().filter_map(|p| quarantine_pattern(p).ok()).collect();
+                Some(GroupQuarantine { runs, patterns })
+            })
+            .collect();
+        Quarantine { groups }
+    }
+}
+
 fn resolve_group(
     name: &str,
     raw: &BTreeMap<String, RawTestGroup>,
@@ -106,6 +222,36 @@ fn resolve_group(
             .test_always_passes_nice
             .or(parent.map(|p| p.test_always_passes_nice))
             .unwrap_or(10),
+        bisect_commits: g
+            .bisect_commits
+            .or(parent.map(|p| p.bisect_commits))
+            .unwrap_or(100),
+        rerun_failed: g
+            .rerun_failed
+            .or(parent.map(|p| p.rerun_failed))
+            .unwrap_or(0),
+        quarantine: g
+            .quarantine
+            .clone()
+            .or_else(|| parent.map(|p| p.quarantine.clone()))
+            .unwrap_or_default(),
+        notify: g
+            .notify
+            .clone()
+            .or_else(|| parent.map(|p| p.notify.clone()))
+            .unwrap_or_default(),
+        batch_timeout_factor: g
+            .batch_timeout_factor
+            .or(parent.map(|p| p.batch_timeout_factor))
+            .unwrap_or(3.0),
+        batch_timeout_min: g
+            .batch_timeout_min
+            .or(parent.map(|p| p.batch_timeout_min))
+            .unwrap_or(300),
+        batch_timeout_max: g
+            .batch_timeout_max
+            .or(parent.map(|p| p.batch_timeout_max))
+            .unwrap_or(7200),
         tests: g
             .tests
             .clone()
@@ -141,7 +287,25 @@ pub fn userrc_read_str(s: &str) -> anyhow::Result<Userrc> {
         resolve_group(name, &raw.test_groups, &mut resolved, &mut stack)?;
     }
 
+    for (name, tg) in &resolved {
+        for p in &tg.quarantine {
+            quarantine_pattern(p).with_context(|| format!("test_group {:?}", name))?;
+        }
+        for t in &tg.notify {
+            crate::notify::Target::parse(t).with_context(|| format!("test_group {:?}", name))?;
+        }
+        if tg.batch_timeout_min > tg.batch_timeout_max {
+            return Err(anyhow!(
+                "test_group {:?}: batch_timeout_min {} > batch_timeout_max {}",
+                name, tg.batch_timeout_min, tg.batch_timeout_max
+            ));
+        }
+    }
+
     for (bname, b) in &raw.branches {
+        for t in &b.notify {
+            crate::notify::Target::parse(t).with_context(|| format!("branch {:?}", bname))?;
+        }
         for tg in &b.test_groups {
             if !resolved.contains_key(tg) {
                 return Err(anyhow!(
@@ -163,6 +327,7 @@ pub fn userrc_read_str(s: &str) -> anyhow::Result<Userrc> {
                     fetch: b.fetch,
                     repo: b.repo,
                     test_groups: b.test_groups,
+                    notify: b.notify,
                 },
             )
         })
@@ -214,6 +379,25 @@ mod tests {
         assert_eq!(ext.nice, 5);
     }
 
+    #[test]
+    fn batch_timeout_overrides() {
+        let rc = userrc_read_str(r#"{
+            test_groups: {
+                base: { tests: ["a.ktest"], batch_timeout_max: 36000 },
+                soak: { extends: "base", batch_timeout_min: 3600 },
+            },
+            branches: {},
+        }"#).unwrap();
+        let soak = &rc.test_groups["soak"];
+        assert_eq!((soak.batch_timeout_factor, soak.batch_timeout_min, soak.batch_timeout_max),
+                   (3.0, 3600, 36000));
+
+        assert!(userrc_read_str(r#"{
+            test_groups: { g: { batch_timeout_min: 600, batch_timeout_max: 60 } },
+            branches: {},
+        }"#).is_err());
+    }
+
     #[test]
     fn env_merges_with_parent() {
         let rc = userrc_read_str(r#"{
@@ -236,6 +420,28 @@ mod tests {
         assert_eq!(ext.env.get("BAZ").map(String::as_str), Some("2"));
     }
 
+    #[test]
+    fn quarantine_collects_branch_groups() {
+        let rc = userrc_read_str(r#"{
+            test_groups: {
+                base: { tests: ["a.ktest"], quarantine: ["fs/bcachefs/ec*"] },
+                ext: { extends: "base", kernels: ["debian/k"] },
+                other: { tests: ["b.ktest"], quarantine: ["*.generic.475"] },
+                kasan: { tests: ["b.ktest"], kernels: ["debian/k"], env: { KASAN: "1" } },
+            },
+            branches: {
+                br: { fetch: "x", test_groups: ["ext", "other", "kasan"] },
+            },
+        }"#).unwrap();
+        let q = rc.branch_quarantine("br");
+        assert!(q.matches("fs.bcachefs.ec@debian_k.ec_seq"));
+        assert!(q.matches("fs.bcachefs.fstests.generic.475"));
+        // Each group's patterns only cover its own runs.
+        assert!(!q.matches("fs.bcachefs.ec.ec_seq"));
+        assert!(!q.matches("fs.bcachefs.fstests@debian_k@KASAN=1.generic.475"));
+        assert!(rc.branch_quarantine("nope").is_empty());
+    }
+
     #[test]
     fn cycle_detected() {
         let err = userrc_read_str(r#"{
@@ -256,4 +462,18 @@ mod tests {
         }"#).err().expect("expected undefined test_group error");
         assert!(err.to_string().contains("nope"), "got: {}", err);
     }
+
+    #[test]
+    fn fetch_args_refuse_only_options() {
+        assert_eq!(fetch_args("linux  master").unwrap(), vec!["linux", "master"]);
+        assert_eq!(
+            fetch_args("https://evilpiepirate.org/git/bcachefs.git for-next").unwrap(),
+            vec!["https://evilpiepirate.org/git/bcachefs.git", "for-next"]
+        );
+        // As the daemon always took them
+        assert_eq!(fetch_args("linux").unwrap(), vec!["linux"]);
+        assert!(fetch_args("linux master:refs/heads/x").is_ok());
+        assert!(fetch_args("linux --force master").is_err());
+        assert!(fetch_args("-q linux master").is_err());
+    }
 }